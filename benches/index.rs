@@ -0,0 +1,106 @@
+// SPDX-License-Identifier: MIT
+//
+// MIT License
+//
+// © 2024 Nathan Dautenhahn & Serenitix LLC
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+// Compares linear lookups on CPMPrivMap with the IndexedPrivMap on a
+// generated map shaped like the Linux policies: one object per domain.
+//
+//   cargo bench --bench index [-- <objects>]
+
+use std::env;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use cpm_if::cpm_priv_map::{
+    AllocType, CPMPrivMap, CallRetPrivField, ContextField, ObjectDomain, ObjectID, Principal, Privilege,
+    RWPrivField, SubjectDomain,
+};
+use cpm_if::index::IndexedPrivMap;
+
+const QUERIES: usize = 1000;
+
+fn generate(objects: usize) -> CPMPrivMap {
+    let mut priv_map = CPMPrivMap::new();
+    for i in 0..objects {
+        priv_map.add_object_domain(ObjectDomain::new(format!("GlobalObjectDomain{}.g{}", i, i), vec![
+            ObjectID::new(AllocType::Global, format!("dir{}/file{}.c", i % 100, i % 1000), i.to_string(), format!("g{}", i)),
+        ]));
+    }
+    let subjects = objects / 10;
+    for i in 0..subjects {
+        priv_map.add_subject_domain(SubjectDomain::new(format!("s{}", i), vec![format!("fn{}", i)]));
+        priv_map.add_privilege(Privilege::new(
+            Principal { subject: format!("s{}", i), execution_context: ContextField::All },
+            CallRetPrivField::List(vec![]),
+            CallRetPrivField::List(vec![]),
+            RWPrivField::All,
+            RWPrivField::List(vec![]),
+        ));
+    }
+    priv_map
+}
+
+fn time<F: FnMut(usize)>(label: &str, mut query: F) -> Duration {
+    let start = Instant::now();
+    for q in 0..QUERIES {
+        query(q);
+    }
+    let elapsed = start.elapsed();
+    println!("{:<32} {:>12.3?} / query", label, elapsed / QUERIES as u32);
+    elapsed
+}
+
+fn main() {
+    let objects: usize = env::args()
+        .skip(1)
+        .find_map(|a| a.parse().ok())
+        .unwrap_or(200_000);
+    let priv_map = generate(objects);
+    let subjects = priv_map.subject_map().len();
+    println!("map: {} object domains, {} subject domains, {} queries each", objects, subjects, QUERIES);
+
+    let start = Instant::now();
+    let indexed = IndexedPrivMap::new(generate(objects));
+    println!("{:<32} {:>12.3?}", "build index", start.elapsed());
+
+    // spread the queried names over the whole map
+    let nth = |q: usize, n: usize| (q * 7919) % n;
+
+    time("linear global lookup", |q| {
+        black_box(priv_map.get_object_domain_for_global(&format!("g{}", nth(q, objects))));
+    });
+    time("indexed global lookup", |q| {
+        black_box(indexed.object_domain_for_global(&format!("g{}", nth(q, objects))));
+    });
+
+    time("linear (path, line) lookup", |q| {
+        let i = nth(q, objects);
+        let path = format!("dir{}/file{}.c", i % 100, i % 1000);
+        black_box(priv_map.find_object_domains(None, Some(&path), Some(&i.to_string()), None));
+    });
+    time("indexed (path, line) lookup", |q| {
+        let i = nth(q, objects);
+        let path = format!("dir{}/file{}.c", i % 100, i % 1000);
+        black_box(indexed.objects_at_site(&path, &i.to_string()));
+    });
+
+    time("linear subject privileges", |q| {
+        let subject = format!("s{}", nth(q, subjects));
+        black_box(priv_map.privileges().iter().filter(|p| p.principal().subject() == &subject).count());
+    });
+    time("indexed subject privileges", |q| {
+        black_box(indexed.privileges_for(&format!("s{}", nth(q, subjects))).len());
+    });
+}
@@ -0,0 +1,480 @@
+// SPDX-License-Identifier: MIT
+//
+// MIT License
+//
+// © 2024 Nathan Dautenhahn & Serenitix LLC
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::cpm_priv_map::{
+    CPMPrivMap, CallContextSubField, CallRetPrivField, Context, ContextField, ContextSimpleString, Object,
+    Principal, PrivField, Privilege, RWPrivField,
+};
+use crate::permissions::DomainSet;
+
+/*
+ * Set algebra over the grants of policies.
+ *
+ * A policy is read as a set of grants: subject S may use field F on target
+ * domain T when called from a caller in C, running as uid U and gid G. The
+ * caller set comes from the execution context's call_context, narrowed by
+ * the object context for reads and writes. `all` targets and callers range
+ * over a universe, the domains defined by the policies being combined, so
+ * that differences such as "all but x" can be expressed by listing domains.
+ * uid and gid are compared by containment only: `all` covers `root`, but
+ * `all` minus `root` is kept whole, which may overstate a difference.
+ *
+ * Results are turned back into privileges with one privilege per subject
+ * and context. A target or caller list covering the universe is written as
+ * `all`, and object contexts are folded into the execution context.
+ */
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Guard {
+    All,
+    Only(String),
+}
+
+impl Guard {
+    fn from_simple(value: &Option<ContextSimpleString>) -> Self {
+        match value {
+            Some(ContextSimpleString::String(s)) => Guard::Only(s.to_string()),
+            _ => Guard::All,
+        }
+    }
+
+    fn to_simple(&self) -> Option<ContextSimpleString> {
+        match self {
+            Guard::All => Some(ContextSimpleString::All),
+            Guard::Only(s) => Some(ContextSimpleString::String(s.to_string())),
+        }
+    }
+
+    pub fn contains(&self, other: &Guard) -> bool {
+        *self == Guard::All || self == other
+    }
+
+    fn meet(&self, other: &Guard) -> Option<Guard> {
+        match (self.contains(other), other.contains(self)) {
+            (true, _) => Some(other.clone()),
+            (_, true) => Some(self.clone()),
+            _ => None,
+        }
+    }
+}
+
+// uid and gid guards of a grant
+type Guards = (Guard, Guard);
+
+fn guards_contain(a: &Guards, b: &Guards) -> bool {
+    a.0.contains(&b.0) && a.1.contains(&b.1)
+}
+
+fn meet(a: &DomainSet, b: &DomainSet) -> DomainSet {
+    match (a, b) {
+        (DomainSet::All, other) | (other, DomainSet::All) => other.clone(),
+        (DomainSet::Only(a), DomainSet::Only(b)) => DomainSet::Only(a.intersection(b).cloned().collect()),
+    }
+}
+
+fn minus(a: &DomainSet, b: &DomainSet, universe: &BTreeSet<String>) -> DomainSet {
+    match (a, b) {
+        (_, DomainSet::All) => DomainSet::default(),
+        (DomainSet::All, DomainSet::Only(b)) => DomainSet::Only(universe.difference(b).cloned().collect()),
+        (DomainSet::Only(a), DomainSet::Only(b)) => DomainSet::Only(a.difference(b).cloned().collect()),
+    }
+}
+
+fn is_empty(set: &DomainSet) -> bool {
+    matches!(set, DomainSet::Only(s) if s.is_empty())
+}
+
+/// The domains `all` stands for: every domain defined by the policies combined.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Universe {
+    pub subjects: BTreeSet<String>,
+    pub objects: BTreeSet<String>,
+}
+
+impl Universe {
+    pub fn of(priv_maps: &[&CPMPrivMap]) -> Self {
+        let mut universe = Universe::default();
+        for priv_map in priv_maps {
+            universe.subjects.extend(priv_map.subject_map().iter().map(|sd| sd.name().to_string()));
+            universe.objects.extend(priv_map.object_map().iter().map(|od| od.name().to_string()));
+        }
+        universe
+    }
+
+    fn targets(&self, field: PrivField) -> &BTreeSet<String> {
+        match field {
+            PrivField::CanCall | PrivField::CanReturn => &self.subjects,
+            PrivField::CanRead | PrivField::CanWrite => &self.objects,
+        }
+    }
+
+    // `all` if the set covers a non-empty universe
+    fn canonical(set: DomainSet, universe: &BTreeSet<String>) -> DomainSet {
+        match set {
+            DomainSet::Only(s) if !universe.is_empty() && universe.is_subset(&s) => DomainSet::All,
+            other => other,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct GrantKey {
+    pub subject: String,
+    pub field: PrivField,
+    pub target: String,
+}
+
+// callers and uid/gid guards of a context
+fn context_condition(context: &ContextField) -> (DomainSet, Guards) {
+    match context {
+        ContextField::All => (DomainSet::All, (Guard::All, Guard::All)),
+        ContextField::Context(context) => {
+            let callers = match context.call_context() {
+                Some(CallContextSubField::List(names)) => DomainSet::Only(names.iter().cloned().collect()),
+                _ => DomainSet::All,
+            };
+            (callers, (Guard::from_simple(context.uid()), Guard::from_simple(context.gid())))
+        }
+    }
+}
+
+/// Grants keyed by subject, field and target, each holding the callers allowed per uid/gid guard.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PermissionSet {
+    universe: Universe,
+    grants: BTreeMap<GrantKey, BTreeMap<Guards, DomainSet>>,
+}
+
+impl PermissionSet {
+    pub fn new(universe: Universe) -> Self {
+        PermissionSet { universe, grants: BTreeMap::new() }
+    }
+
+    /// The grants of a policy; `universe` must cover its domains.
+    pub fn of(priv_map: &CPMPrivMap, universe: &Universe) -> Self {
+        let mut set = PermissionSet::new(universe.clone());
+        for privilege in priv_map.privileges() {
+            set.add_privilege(privilege);
+        }
+        set
+    }
+
+    pub fn add_privilege(&mut self, privilege: &Privilege) {
+        let subject = privilege.principal().subject();
+        let (callers, guards) = context_condition(privilege.principal().execution_context());
+        for field in [PrivField::CanCall, PrivField::CanReturn] {
+            let targets = DomainSet::of_field(privilege, field);
+            self.add(subject, field, &targets, &guards, &callers);
+        }
+        for (field, value) in [(PrivField::CanRead, privilege.can_read()), (PrivField::CanWrite, privilege.can_write())] {
+            let objects = match value {
+                RWPrivField::All => vec![(DomainSet::All, ContextField::All)],
+                RWPrivField::List(objects) => objects.iter()
+                    .map(|o| (DomainSet::Only(o.objects().iter().cloned().collect()), o.object_context().clone()))
+                    .collect(),
+            };
+            for (targets, context) in objects {
+                let (object_callers, object_guards) = context_condition(&context);
+                let (Some(uid), Some(gid)) = (guards.0.meet(&object_guards.0), guards.1.meet(&object_guards.1)) else {
+                    continue;
+                };
+                self.add(subject, field, &targets, &(uid, gid), &meet(&callers, &object_callers));
+            }
+        }
+    }
+
+    fn add(&mut self, subject: &str, field: PrivField, targets: &DomainSet, guards: &Guards, callers: &DomainSet) {
+        if is_empty(callers) {
+            return;
+        }
+        for target in targets.expand(self.universe.targets(field).iter().map(|s| s.as_str())) {
+            let key = GrantKey { subject: subject.to_string(), field, target: target.to_string() };
+            let entry = self.grants.entry(key).or_default();
+            entry.entry(guards.clone()).or_default().union_with(callers);
+            normalize(entry, &self.universe.subjects);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.grants.is_empty()
+    }
+
+    pub fn grants(&self) -> &BTreeMap<GrantKey, BTreeMap<Guards, DomainSet>> {
+        &self.grants
+    }
+
+    pub fn union(&self, other: &PermissionSet) -> PermissionSet {
+        let mut result = self.clone();
+        for (key, entry) in &other.grants {
+            let merged = result.grants.entry(key.clone()).or_default();
+            for (guards, callers) in entry {
+                merged.entry(guards.clone()).or_default().union_with(callers);
+            }
+            normalize(merged, &self.universe.subjects);
+        }
+        result
+    }
+
+    pub fn intersection(&self, other: &PermissionSet) -> PermissionSet {
+        let mut result = PermissionSet::new(self.universe.clone());
+        for (key, entry) in &self.grants {
+            let Some(other_entry) = other.grants.get(key) else { continue };
+            let mut merged: BTreeMap<Guards, DomainSet> = BTreeMap::new();
+            for ((uid, gid), callers) in entry {
+                for ((other_uid, other_gid), other_callers) in other_entry {
+                    let (Some(uid), Some(gid)) = (uid.meet(other_uid), gid.meet(other_gid)) else { continue };
+                    merged.entry((uid, gid)).or_default().union_with(&meet(callers, other_callers));
+                }
+            }
+            normalize(&mut merged, &self.universe.subjects);
+            if !merged.is_empty() {
+                result.grants.insert(key.clone(), merged);
+            }
+        }
+        result
+    }
+
+    /// Grants of `self` that `other` does not cover.
+    pub fn difference(&self, other: &PermissionSet) -> PermissionSet {
+        let mut result = PermissionSet::new(self.universe.clone());
+        for (key, entry) in &self.grants {
+            let mut rest: BTreeMap<Guards, DomainSet> = BTreeMap::new();
+            for (guards, callers) in entry {
+                let mut covered = DomainSet::default();
+                for (other_guards, other_callers) in other.grants.get(key).into_iter().flatten() {
+                    if guards_contain(other_guards, guards) {
+                        covered.union_with(other_callers);
+                    }
+                }
+                rest.insert(guards.clone(), minus(callers, &covered, &self.universe.subjects));
+            }
+            normalize(&mut rest, &self.universe.subjects);
+            if !rest.is_empty() {
+                result.grants.insert(key.clone(), rest);
+            }
+        }
+        result
+    }
+
+    pub fn is_subset(&self, other: &PermissionSet) -> bool {
+        self.difference(other).is_empty()
+    }
+
+    /// One privilege per subject and context, ordered by subject.
+    pub fn to_privileges(&self) -> Vec<Privilege> {
+        let mut groups: Vec<PrivilegeGroup> = vec![];
+        for (key, entry) in &self.grants {
+            for (guards, callers) in entry {
+                let callers = Universe::canonical(callers.clone(), &self.universe.subjects);
+                let position = groups.iter().position(|(s, g, c, _)| *s == key.subject && *g == guards && *c == callers);
+                let i = position.unwrap_or_else(|| {
+                    groups.push((&key.subject, guards, callers, BTreeMap::new()));
+                    groups.len() - 1
+                });
+                groups[i].3.entry(key.field).or_default().insert(key.target.to_string());
+            }
+        }
+        groups.into_iter().map(|(subject, (uid, gid), callers, mut targets)| {
+            let execution_context = match (&callers, uid, gid) {
+                (DomainSet::All, Guard::All, Guard::All) => ContextField::All,
+                _ => {
+                    let call_context = match callers {
+                        DomainSet::All => CallContextSubField::All,
+                        DomainSet::Only(names) => CallContextSubField::List(names.into_iter().collect()),
+                    };
+                    ContextField::Context(Context::new(Some(call_context), uid.to_simple(), gid.to_simple()))
+                }
+            };
+            let mut field = |field: PrivField| {
+                let targets = targets.remove(&field).unwrap_or_default();
+                Universe::canonical(DomainSet::Only(targets), self.universe.targets(field))
+            };
+            let callret = |set: DomainSet| match set {
+                DomainSet::All => CallRetPrivField::All,
+                DomainSet::Only(names) => CallRetPrivField::List(names.into_iter().collect()),
+            };
+            let rw = |set: DomainSet| match set {
+                DomainSet::All => RWPrivField::All,
+                DomainSet::Only(names) if names.is_empty() => RWPrivField::List(vec![]),
+                DomainSet::Only(names) => RWPrivField::List(vec![Object::new(names.into_iter().collect())]),
+            };
+            Privilege::new(
+                Principal { subject: subject.to_string(), execution_context },
+                callret(field(PrivField::CanCall)),
+                callret(field(PrivField::CanReturn)),
+                rw(field(PrivField::CanRead)),
+                rw(field(PrivField::CanWrite)),
+            )
+        }).collect()
+    }
+}
+
+// subject, guards and callers of a privilege to emit, with its targets per field
+type PrivilegeGroup<'a> = (&'a str, &'a Guards, DomainSet, BTreeMap<PrivField, BTreeSet<String>>);
+
+// drops callers already allowed under strictly more general guards, then empty guards
+fn normalize(entry: &mut BTreeMap<Guards, DomainSet>, subjects: &BTreeSet<String>) {
+    let snapshot = entry.clone();
+    for (guards, callers) in entry.iter_mut() {
+        for (other_guards, other_callers) in &snapshot {
+            if other_guards != guards && guards_contain(other_guards, guards) {
+                *callers = minus(callers, other_callers, subjects);
+            }
+        }
+    }
+    entry.retain(|_, callers| !is_empty(callers));
+}
+
+// a policy with the domains of both operands, `a`'s definitions first
+fn combine(a: &CPMPrivMap, b: &CPMPrivMap, permissions: PermissionSet) -> CPMPrivMap {
+    let mut priv_map = CPMPrivMap::new();
+    for od in a.object_map().iter().chain(b.object_map()) {
+        if !priv_map.object_map().iter().any(|o| o.name() == od.name()) {
+            priv_map.add_object_domain(od.clone());
+        }
+    }
+    for sd in a.subject_map().iter().chain(b.subject_map()) {
+        if !priv_map.subject_map().iter().any(|s| s.name() == sd.name()) {
+            priv_map.add_subject_domain(sd.clone());
+        }
+    }
+    for privilege in permissions.to_privileges() {
+        priv_map.add_privilege(privilege);
+    }
+    priv_map
+}
+
+fn operands(a: &CPMPrivMap, b: &CPMPrivMap) -> (PermissionSet, PermissionSet) {
+    let universe = Universe::of(&[a, b]);
+    (PermissionSet::of(a, &universe), PermissionSet::of(b, &universe))
+}
+
+/// Everything either policy grants.
+pub fn union(a: &CPMPrivMap, b: &CPMPrivMap) -> CPMPrivMap {
+    let (pa, pb) = operands(a, b);
+    combine(a, b, pa.union(&pb))
+}
+
+/// Everything both policies grant.
+pub fn intersection(a: &CPMPrivMap, b: &CPMPrivMap) -> CPMPrivMap {
+    let (pa, pb) = operands(a, b);
+    combine(a, b, pa.intersection(&pb))
+}
+
+/// What `a` grants beyond `b`.
+pub fn difference(a: &CPMPrivMap, b: &CPMPrivMap) -> CPMPrivMap {
+    let (pa, pb) = operands(a, b);
+    combine(a, b, pa.difference(&pb))
+}
+
+/// True if `a` is no more permissive than `b`.
+pub fn is_subset(a: &CPMPrivMap, b: &CPMPrivMap) -> bool {
+    let (pa, pb) = operands(a, b);
+    pa.is_subset(&pb)
+}
+
+/// True if privilege `p` grants nothing beyond `q`, with `all` ranging over `universe`.
+pub fn privilege_is_subset(p: &Privilege, q: &Privilege, universe: &Universe) -> bool {
+    let mut pa = PermissionSet::new(universe.clone());
+    pa.add_privilege(p);
+    let mut pb = PermissionSet::new(universe.clone());
+    pb.add_privilege(q);
+    pa.is_subset(&pb)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOMAINS: &str = "
+object_map:
+  - {name: od1, objects: []}
+  - {name: od2, objects: []}
+subject_map:
+  - {name: s1, subjects: [f1]}
+  - {name: s2, subjects: [f2]}
+  - {name: s3, subjects: [f3]}
+";
+
+    fn policy(privileges: &str) -> CPMPrivMap {
+        serde_yaml::from_str(&format!("{}privileges:\n{}", DOMAINS, privileges)).unwrap()
+    }
+
+    #[test]
+    fn test_all_and_lists() {
+        let generated = policy("
+  - principal: {subject: s1}
+    can_call: all
+    can_return: []
+    can_read: [{objects: [od1]}]
+    can_write: []
+");
+        let baseline = policy("
+  - principal: {subject: s1}
+    can_call: [s2]
+    can_return: []
+    can_read: all
+    can_write: []
+");
+        assert!(!is_subset(&generated, &baseline));
+        assert!(is_subset(&intersection(&generated, &baseline), &baseline));
+        assert!(is_subset(&generated, &union(&generated, &baseline)));
+
+        let excess = difference(&generated, &baseline);
+        assert_eq!(excess.privileges().len(), 1);
+        assert_eq!(excess.privileges()[0].can_call(), &CallRetPrivField::List(vec!["s1".to_string(), "s3".to_string()]));
+        assert_eq!(excess.privileges()[0].can_read(), &RWPrivField::List(vec![]));
+
+        let both = union(&generated, &baseline);
+        assert!(both.privileges()[0].grants_all(PrivField::CanCall) && both.privileges()[0].grants_all(PrivField::CanRead));
+        let common = intersection(&generated, &baseline);
+        assert_eq!(common.privileges()[0].can_call(), &CallRetPrivField::List(vec!["s2".to_string()]));
+        assert_eq!(common.privileges()[0].can_read(), &RWPrivField::List(vec![Object::new(vec!["od1".to_string()])]));
+        assert_eq!(common.object_map().len(), 2);
+    }
+
+    #[test]
+    fn test_contexts() {
+        let narrow = policy("
+  - principal: {subject: s1, execution_context: {call_context: [s2], uid: root}}
+    can_call: []
+    can_return: []
+    can_read: []
+    can_write: [{objects: [od2], object_context: {call_context: [s2, s3]}}]
+");
+        let wide = policy("
+  - principal: {subject: s1, execution_context: {call_context: [s2, s3]}}
+    can_call: []
+    can_return: []
+    can_read: []
+    can_write: [{objects: [od2]}]
+");
+        assert!(is_subset(&narrow, &wide));
+        assert!(!is_subset(&wide, &narrow));
+        assert!(privilege_is_subset(&narrow.privileges()[0], &wide.privileges()[0], &Universe::of(&[&narrow])));
+
+        // uids are compared by containment, so the root-only grant covers none of the any-uid one
+        let excess = difference(&wide, &narrow);
+        assert_eq!(excess.privileges().len(), 1);
+        assert_eq!(excess.privileges()[0].principal().execution_context(),
+                   &ContextField::Context(Context::with_call_context(vec!["s2".to_string(), "s3".to_string()])));
+
+        let common = intersection(&wide, &narrow);
+        assert!(is_subset(&common, &narrow) && is_subset(&narrow, &common));
+    }
+}
@@ -0,0 +1,253 @@
+// SPDX-License-Identifier: MIT
+//
+// MIT License
+//
+// © 2024 Nathan Dautenhahn & Serenitix LLC
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Component, Path};
+
+use crate::cpm_priv_map::CPMPrivMap;
+use crate::locations::normalize;
+use crate::parallel::Parallelism;
+use crate::permissions::all_effective_permissions;
+
+/*
+ * Source annotations: for every object site (ObjectID path and line) the
+ * object domain holding it and the subject domains that can read or write
+ * that domain according to their effective permissions.
+ *
+ * `annotations_json` renders them as a side file for editors and review
+ * tools. `annotate_tree` writes a copy of the annotated files of a source
+ * tree to another directory, with a C comment above each site:
+ *
+ *   /* cpm_if: od12 (GLOBAL tcp_hashinfo) read: net, fs write: net */
+ *
+ * Sites without a numeric line, and paths that are absolute or leave the
+ * tree, are not annotated in the copy.
+ */
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SiteAnnotation {
+    pub line: String,
+    pub alloc_type: String,
+    pub name: String,
+    pub domain: String,
+    pub readers: Vec<String>,
+    pub writers: Vec<String>,
+}
+
+impl SiteAnnotation {
+    pub fn comment(&self) -> String {
+        let mut comment = format!("/* cpm_if: {} ({}", self.domain, self.alloc_type);
+        if !self.name.is_empty() {
+            comment.push(' ');
+            comment.push_str(&self.name);
+        }
+        comment.push(')');
+        for (label, subjects) in [("read", &self.readers), ("write", &self.writers)] {
+            if !subjects.is_empty() {
+                comment.push_str(&format!(" {}: {}", label, subjects.join(", ")));
+            }
+        }
+        comment.push_str(" */");
+        comment
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileAnnotations {
+    pub path: String,
+    // ordered by line, numeric lines first
+    pub sites: Vec<SiteAnnotation>,
+}
+
+/// Annotations of every object site with a path, grouped by file and sorted by path.
+pub fn annotations(priv_map: &CPMPrivMap, par: Parallelism) -> Vec<FileAnnotations> {
+    // readers and writers per object domain, in subject map order
+    let mut access: HashMap<&str, (Vec<String>, Vec<String>)> = HashMap::new();
+    let permissions = all_effective_permissions(priv_map, par);
+    for od in priv_map.object_map() {
+        let readers = permissions.iter().filter(|(_, p)| p.can_read.contains(od.name())).map(|(s, _)| s.to_string());
+        let writers = permissions.iter().filter(|(_, p)| p.can_write.contains(od.name())).map(|(s, _)| s.to_string());
+        access.entry(od.name()).or_insert_with(|| (readers.collect(), writers.collect()));
+    }
+
+    let mut files: BTreeMap<&str, Vec<SiteAnnotation>> = BTreeMap::new();
+    for od in priv_map.object_map() {
+        let (readers, writers) = &access[od.name()];
+        for object in od.objects().iter().filter(|o| !o.path().is_empty()) {
+            files.entry(normalize(object.path())).or_default().push(SiteAnnotation {
+                line: object.lineno().to_string(),
+                alloc_type: object.alloc_type().to_string(),
+                name: object.name().to_string(),
+                domain: od.name().to_string(),
+                readers: readers.clone(),
+                writers: writers.clone(),
+            });
+        }
+    }
+    files.into_iter().map(|(path, mut sites)| {
+        sites.sort_by_key(|s| s.line.parse::<u32>().map_or((1, 0), |n| (0, n)));
+        FileAnnotations { path: path.to_string(), sites }
+    }).collect()
+}
+
+pub fn annotations_json(files: &[FileAnnotations]) -> Result<String, String> {
+    serde_json::to_string_pretty(files).map_err(|e| format!("Failed to serialize annotations: {}", e))
+}
+
+/// What `annotate_tree` wrote and what it had to skip.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct AnnotateReport {
+    pub files_written: usize,
+    pub sites_annotated: usize,
+    // annotated paths that are missing from the source tree or unsafe to join
+    pub skipped_files: Vec<String>,
+    // sites whose line is not a line of the file, as "path:line"
+    pub skipped_sites: Vec<String>,
+}
+
+// a relative path that stays inside the tree it is joined to
+fn is_contained(path: &str) -> bool {
+    Path::new(path).components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+fn annotate_source(source: &str, sites: &[SiteAnnotation], path: &str, report: &mut AnnotateReport) -> String {
+    let mut by_line: BTreeMap<usize, Vec<&SiteAnnotation>> = BTreeMap::new();
+    let line_count = source.lines().count();
+    for site in sites {
+        match site.line.parse::<usize>() {
+            Ok(line) if (1..=line_count).contains(&line) => by_line.entry(line).or_default().push(site),
+            _ => report.skipped_sites.push(format!("{}:{}", path, site.line)),
+        }
+    }
+    let mut out = String::with_capacity(source.len());
+    for (i, line) in source.lines().enumerate() {
+        let indent: String = line.chars().take_while(|c| c.is_whitespace()).collect();
+        for site in by_line.get(&(i + 1)).into_iter().flatten() {
+            out.push_str(&indent);
+            out.push_str(&site.comment());
+            out.push('\n');
+            report.sites_annotated += 1;
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+/*
+ * Writes an annotated copy of every annotated file found under `source_root`
+ * to the same relative path under `out_root`. The output directory must not
+ * be the source tree, so sources are never modified in place.
+ */
+pub fn annotate_tree(files: &[FileAnnotations], source_root: &str, out_root: &str) -> Result<AnnotateReport, String> {
+    let source = fs::canonicalize(source_root)
+        .map_err(|e| format!("Failed to read source tree {}: {}", source_root, e))?;
+    fs::create_dir_all(out_root).map_err(|e| format!("Failed to create {}: {}", out_root, e))?;
+    let out = fs::canonicalize(out_root).map_err(|e| format!("Failed to create {}: {}", out_root, e))?;
+    if out == source {
+        return Err("the output directory must differ from the source tree".to_string());
+    }
+
+    let mut report = AnnotateReport::default();
+    for file in files {
+        let text = match is_contained(&file.path) {
+            true => fs::read_to_string(source.join(&file.path)).ok(),
+            false => None,
+        };
+        let Some(text) = text else {
+            report.skipped_files.push(file.path.to_string());
+            continue;
+        };
+        let annotated = annotate_source(&text, &file.sites, &file.path, &mut report);
+        let target = out.join(&file.path);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        fs::write(&target, annotated).map_err(|e| format!("Failed to write {}: {}", target.display(), e))?;
+        report.files_written += 1;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: &str = "
+object_map:
+  - name: od1
+    objects: ['GLOBAL|net/tcp.c|2|tcp_hashinfo', 'HEAP|net/tcp.c|9|']
+  - name: od2
+    objects: ['HEAP|net/tcp.c|4|', 'HEAP|../etc/passwd|1|']
+subject_map:
+  - {name: net, subjects: [f1]}
+  - {name: fs, subjects: [f2]}
+privileges:
+  - principal: {subject: net}
+    can_call: []
+    can_return: []
+    can_read: all
+    can_write: [{objects: [od1]}]
+  - principal: {subject: fs}
+    can_call: []
+    can_return: []
+    can_read: [{objects: [od2]}]
+    can_write: []
+";
+
+    #[test]
+    fn test_annotations() {
+        let priv_map: CPMPrivMap = serde_yaml::from_str(POLICY).unwrap();
+        let files = annotations(&priv_map, Parallelism::Sequential);
+        assert_eq!(files.iter().map(|f| f.path.as_str()).collect::<Vec<_>>(), vec!["../etc/passwd", "net/tcp.c"]);
+        let comments: Vec<String> = files[1].sites.iter().map(|s| s.comment()).collect();
+        assert_eq!(comments, vec![
+            "/* cpm_if: od1 (GLOBAL tcp_hashinfo) read: net write: net */",
+            "/* cpm_if: od2 (HEAP) read: net, fs */",
+            "/* cpm_if: od1 (HEAP) read: net write: net */",
+        ]);
+        assert!(annotations_json(&files).unwrap().contains("\"readers\": ["));
+    }
+
+    #[test]
+    fn test_annotate_tree() {
+        let priv_map: CPMPrivMap = serde_yaml::from_str(POLICY).unwrap();
+        let files = annotations(&priv_map, Parallelism::Sequential);
+        let root = std::env::temp_dir().join(format!("cpm_if_annotate_{}", std::process::id()));
+        let (src, out) = (root.join("src"), root.join("out"));
+        fs::create_dir_all(src.join("net")).unwrap();
+        fs::write(src.join("net/tcp.c"), "int x;\nstruct inet_hashinfo tcp_hashinfo;\nvoid f(void) {\n\tp = kmalloc(8);\n}\n").unwrap();
+
+        let report = annotate_tree(&files, src.to_str().unwrap(), out.to_str().unwrap()).unwrap();
+        assert_eq!(report.files_written, 1);
+        assert_eq!(report.sites_annotated, 2);
+        assert_eq!(report.skipped_files, vec!["../etc/passwd"]);
+        assert_eq!(report.skipped_sites, vec!["net/tcp.c:9"]);
+        assert_eq!(fs::read_to_string(out.join("net/tcp.c")).unwrap(), "\
+int x;
+/* cpm_if: od1 (GLOBAL tcp_hashinfo) read: net write: net */
+struct inet_hashinfo tcp_hashinfo;
+void f(void) {
+\t/* cpm_if: od2 (HEAP) read: net, fs */
+\tp = kmalloc(8);
+}
+");
+        assert!(annotate_tree(&files, src.to_str().unwrap(), src.to_str().unwrap()).is_err());
+        fs::remove_dir_all(root).unwrap();
+    }
+}
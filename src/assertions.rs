@@ -0,0 +1,436 @@
+// SPDX-License-Identifier: MIT
+//
+// MIT License
+//
+// © 2024 Nathan Dautenhahn & Serenitix LLC
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fs;
+
+use crate::cpm_priv_map::{CPMPrivMap, PrivField};
+use crate::permissions::DomainSet;
+use crate::query::glob_match;
+
+/*
+ * Security invariants kept alongside a policy.
+ *
+ *   assertions:
+ *     - name: keyring-only-crypto
+ *       subjects: ["*"]                  # subject domains the rule covers (default all)
+ *       except: [crypto_dom]             # subject domains exempted
+ *       cannot: read                     # call | return | read | write
+ *       domains: ["GlobalObjectDomain*.keyring"]
+ *     - name: net-no-fs
+ *       subjects: [net_dom]
+ *       cannot: call
+ *       domains: [fs_dom]
+ *       transitive: true                 # also through chains of calls
+ *     - name: net-can-log
+ *       subjects: [net_dom]
+ *       can: write
+ *       domains: [log_buffer]
+ *
+ * Names are globs (`*` and `?`). A `cannot` assertion is violated by every
+ * covered subject that has the access to a matching domain according to its
+ * effective permissions (contexts ignored, `all` covering every domain); with
+ * `transitive: true` the access may also be held by any subject reachable
+ * through can_call edges. A `can` assertion is violated by every covered
+ * subject lacking the access to a matching domain. Violations carry a
+ * witness: the call chain and the privilege granting the access.
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    Call,
+    Return,
+    Read,
+    Write,
+}
+
+impl Access {
+    pub fn field(&self) -> PrivField {
+        match self {
+            Access::Call => PrivField::CanCall,
+            Access::Return => PrivField::CanReturn,
+            Access::Read => PrivField::CanRead,
+            Access::Write => PrivField::CanWrite,
+        }
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Access::Call => "call",
+            Access::Return => "return from",
+            Access::Read => "read",
+            Access::Write => "write",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+fn default_subjects() -> Vec<String> {
+    vec!["*".to_string()]
+}
+
+/// What an assertion requires: that the covered subjects can, or cannot, have the access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssertionKind {
+    Can(Access),
+    Cannot(Access),
+}
+
+impl AssertionKind {
+    pub fn access(&self) -> Access {
+        match *self {
+            AssertionKind::Can(access) | AssertionKind::Cannot(access) => access,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "RawAssertion", into = "RawAssertion")]
+pub struct Assertion {
+    pub name: String,
+    pub description: Option<String>,
+    pub subjects: Vec<String>,
+    pub except: Vec<String>,
+    pub kind: AssertionKind,
+    pub domains: Vec<String>,
+    pub transitive: bool,
+}
+
+// the file format, with `can` and `cannot` as separate keys
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct RawAssertion {
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default = "default_subjects")]
+    subjects: Vec<String>,
+    #[serde(default)]
+    except: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    can: Option<Access>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cannot: Option<Access>,
+    domains: Vec<String>,
+    #[serde(default)]
+    transitive: bool,
+}
+
+impl TryFrom<RawAssertion> for Assertion {
+    type Error = String;
+
+    fn try_from(raw: RawAssertion) -> Result<Self, String> {
+        let kind = match (raw.can, raw.cannot) {
+            (Some(access), None) => AssertionKind::Can(access),
+            (None, Some(access)) => AssertionKind::Cannot(access),
+            _ => return Err(format!("assertion '{}' needs exactly one of `can` and `cannot`", raw.name)),
+        };
+        Ok(Assertion {
+            name: raw.name,
+            description: raw.description,
+            subjects: raw.subjects,
+            except: raw.except,
+            kind,
+            domains: raw.domains,
+            transitive: raw.transitive,
+        })
+    }
+}
+
+impl From<Assertion> for RawAssertion {
+    fn from(assertion: Assertion) -> Self {
+        let (can, cannot) = match assertion.kind {
+            AssertionKind::Can(access) => (Some(access), None),
+            AssertionKind::Cannot(access) => (None, Some(access)),
+        };
+        RawAssertion {
+            name: assertion.name,
+            description: assertion.description,
+            subjects: assertion.subjects,
+            except: assertion.except,
+            can,
+            cannot,
+            domains: assertion.domains,
+            transitive: assertion.transitive,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AssertionFile {
+    pub assertions: Vec<Assertion>,
+}
+
+impl AssertionFile {
+    pub fn from_yaml_str(yaml: &str) -> Result<Self, String> {
+        serde_yaml::from_str(yaml).map_err(|e| format!("Failed to parse assertions: {}", e))
+    }
+
+    pub fn load(file_path: &str) -> Result<Self, String> {
+        let yaml = fs::read_to_string(file_path)
+            .map_err(|e| format!("Failed to read assertions file: {}", e))?;
+        Self::from_yaml_str(&yaml)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Violation {
+    pub assertion: String,
+    pub subject: String,
+    pub access: Access,
+    pub domain: String,
+    // `cannot`: the chain of calls from `subject` to the holder of the access, and
+    // the index of the privilege granting it. `can`: both empty.
+    pub call_chain: Vec<String>,
+    pub privilege: Option<usize>,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.privilege {
+            None => write!(f, "{}: {} cannot {} {}", self.assertion, self.subject, self.access, self.domain),
+            Some(index) => {
+                write!(f, "{}: {} can {} {} (privileges[{}]", self.assertion, self.subject, self.access,
+                       self.domain, index)?;
+                if self.call_chain.len() > 1 {
+                    write!(f, " via {}", self.call_chain.join(" -> "))?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AssertionResult {
+    pub name: String,
+    pub violations: Vec<Violation>,
+    // the assertion's `domains` matched no domain of the policy
+    pub vacuous: bool,
+}
+
+impl AssertionResult {
+    pub fn holds(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+struct Checker<'a> {
+    priv_map: &'a CPMPrivMap,
+    // subject domain -> (privilege index, granted domains) per field
+    grants: HashMap<&'a str, Vec<(usize, [DomainSet; 4])>>,
+}
+
+impl<'a> Checker<'a> {
+    fn new(priv_map: &'a CPMPrivMap) -> Self {
+        let mut grants: HashMap<&str, Vec<(usize, [DomainSet; 4])>> = HashMap::new();
+        for (i, privilege) in priv_map.privileges().iter().enumerate() {
+            let fields = PrivField::ALL.map(|f| DomainSet::of_field(privilege, f));
+            grants.entry(privilege.principal().subject().as_str()).or_default().push((i, fields));
+        }
+        Self { priv_map, grants }
+    }
+
+    // the first privilege of `subject` granting `field` on `domain`
+    fn granting(&self, subject: &str, field: PrivField, domain: &str) -> Option<usize> {
+        let slot = PrivField::ALL.iter().position(|f| *f == field).unwrap();
+        self.grants.get(subject)?.iter()
+            .find(|(_, fields)| fields[slot].contains(domain))
+            .map(|(i, _)| *i)
+    }
+
+    // subject domains callable from `subject` in one step
+    fn callees(&self, subject: &str) -> Vec<String> {
+        let slot = PrivField::ALL.iter().position(|f| *f == PrivField::CanCall).unwrap();
+        let mut callees: Vec<String> = vec![];
+        for (_, fields) in self.grants.get(subject).into_iter().flatten() {
+            for callee in fields[slot].expand(self.priv_map.subject_map().iter().map(|sd| sd.name())) {
+                if !callees.iter().any(|c| c == callee) {
+                    callees.push(callee.to_string());
+                }
+            }
+        }
+        callees
+    }
+
+    // shortest call chains from `subject` to every reachable subject domain, `subject` first
+    fn call_chains(&self, subject: &str) -> Vec<Vec<String>> {
+        let mut chains = vec![vec![subject.to_string()]];
+        let mut visited = HashSet::from([subject.to_string()]);
+        let mut queue = VecDeque::from([0]);
+        while let Some(c) = queue.pop_front() {
+            for callee in self.callees(chains[c].last().unwrap()) {
+                if visited.insert(callee.clone()) {
+                    let mut chain = chains[c].clone();
+                    chain.push(callee);
+                    chains.push(chain);
+                    queue.push_back(chains.len() - 1);
+                }
+            }
+        }
+        chains
+    }
+
+    fn check(&self, assertion: &Assertion) -> AssertionResult {
+        let matches = |globs: &[String], name: &str| globs.iter().any(|g| glob_match(g, name));
+        let (access, forbidden) = match assertion.kind {
+            AssertionKind::Cannot(access) => (access, true),
+            AssertionKind::Can(access) => (access, false),
+        };
+        let field = access.field();
+        let targets: Vec<&str> = match field {
+            PrivField::CanCall | PrivField::CanReturn => self.priv_map.subject_map().iter().map(|sd| sd.name()).collect(),
+            PrivField::CanRead | PrivField::CanWrite => self.priv_map.object_map().iter().map(|od| od.name()).collect(),
+        };
+        let targets: Vec<&str> = targets.into_iter().filter(|t| matches(&assertion.domains, t)).collect();
+        let subjects = self.priv_map.subject_map().iter()
+            .map(|sd| sd.name())
+            .filter(|s| matches(&assertion.subjects, s) && !matches(&assertion.except, s));
+
+        let mut violations = vec![];
+        let violation = |subject: &str, domain: &str, call_chain: Vec<String>, privilege| Violation {
+            assertion: assertion.name.to_string(),
+            subject: subject.to_string(),
+            access,
+            domain: domain.to_string(),
+            call_chain,
+            privilege,
+        };
+        for subject in subjects {
+            let chains = if forbidden && assertion.transitive {
+                self.call_chains(subject)
+            } else {
+                vec![vec![subject.to_string()]]
+            };
+            for target in &targets {
+                let witness = chains.iter().find_map(|chain| {
+                    self.granting(chain.last().unwrap(), field, target).map(|i| (chain, i))
+                });
+                match witness {
+                    Some((chain, i)) if forbidden => violations.push(violation(
+                        subject, target, chain.clone(), Some(i))),
+                    None if !forbidden => violations.push(violation(subject, target, vec![], None)),
+                    _ => (),
+                }
+            }
+        }
+        AssertionResult { name: assertion.name.to_string(), violations, vacuous: targets.is_empty() }
+    }
+}
+
+/// Evaluates every assertion of the file, in file order.
+pub fn check_assertions(priv_map: &CPMPrivMap, assertions: &AssertionFile) -> Vec<AssertionResult> {
+    let checker = Checker::new(priv_map);
+    assertions.assertions.iter().map(|a| checker.check(a)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: &str = "
+object_map:
+  - {name: GlobalObjectDomain1.keyring, objects: [k]}
+  - {name: log_buffer, objects: [l]}
+subject_map:
+  - {name: crypto_dom, subjects: [encrypt]}
+  - {name: net_dom, subjects: [xmit]}
+  - {name: util_dom, subjects: [memcpy]}
+  - {name: fs_dom, subjects: [iput]}
+privileges:
+  - principal: {subject: crypto_dom}
+    can_call: []
+    can_return: []
+    can_read:
+      - objects: [GlobalObjectDomain1.keyring]
+    can_write: []
+  - principal: {subject: net_dom}
+    can_call: [util_dom]
+    can_return: []
+    can_read: []
+    can_write: []
+  - principal: {subject: util_dom}
+    can_call: [fs_dom]
+    can_return: []
+    can_read: all
+    can_write: []
+";
+
+    const ASSERTIONS: &str = "
+assertions:
+  - name: keyring-only-crypto
+    except: [crypto_dom]
+    cannot: read
+    domains: ['GlobalObjectDomain*.keyring']
+  - name: net-no-fs
+    subjects: [net_dom]
+    cannot: call
+    domains: [fs_dom]
+  - name: net-no-fs-transitively
+    subjects: [net_dom]
+    cannot: call
+    domains: [fs_dom]
+    transitive: true
+  - name: net-can-log
+    subjects: [net_dom]
+    can: write
+    domains: [log_buffer]
+  - name: nothing-matches
+    cannot: read
+    domains: [missing*]
+";
+
+    #[test]
+    fn test_check_assertions() {
+        let priv_map: CPMPrivMap = serde_yaml::from_str(POLICY).unwrap();
+        let assertions = AssertionFile::from_yaml_str(ASSERTIONS).unwrap();
+        let results = check_assertions(&priv_map, &assertions);
+        let violations: Vec<Vec<String>> = results.iter()
+            .map(|r| r.violations.iter().map(|v| v.to_string()).collect())
+            .collect();
+        assert_eq!(violations, vec![
+            vec!["keyring-only-crypto: util_dom can read GlobalObjectDomain1.keyring (privileges[2])".to_string()],
+            vec![],
+            vec!["net-no-fs-transitively: net_dom can call fs_dom (privileges[2] via net_dom -> util_dom)".to_string()],
+            vec!["net-can-log: net_dom cannot write log_buffer".to_string()],
+            vec![],
+        ]);
+        assert!(results[4].holds() && results[4].vacuous);
+        assert!(!results[0].vacuous);
+    }
+
+    #[test]
+    fn test_invalid_assertions() {
+        let both = "assertions:\n  - {name: a, can: read, cannot: read, domains: [x]}\n";
+        assert!(AssertionFile::from_yaml_str(both).unwrap_err()
+            .contains("assertion 'a' needs exactly one of `can` and `cannot`"));
+        let neither = "assertions:\n  - {name: a, domains: [x]}\n";
+        assert!(AssertionFile::from_yaml_str(neither).is_err());
+
+        let file = AssertionFile::from_yaml_str("assertions:\n  - {name: a, can: write, domains: [x]}\n").unwrap();
+        assert_eq!(file.assertions[0].kind, AssertionKind::Can(Access::Write));
+        let yaml = serde_yaml::to_string(&file).unwrap();
+        assert_eq!(AssertionFile::from_yaml_str(&yaml).unwrap(), file);
+        assert!(AssertionFile::from_yaml_str("assertions:\n  - {name: a, cannot: fly, domains: [x]}\n").is_err());
+        assert!(AssertionFile::from_yaml_str("assertions:\n  - {name: a, cannot: read, domain: [x]}\n").is_err());
+    }
+}
@@ -0,0 +1,288 @@
+// SPDX-License-Identifier: MIT
+//
+// MIT License
+//
+// © 2024 Nathan Dautenhahn & Serenitix LLC
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+use cpm_if::lsp::{PolicyDocument, Position};
+use jsonschema::JSONSchema;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process;
+
+const USAGE: &str = "Usage: cpm_if_lsp [--schema <schema.json>]";
+
+/*
+ * Language server for policy files, speaking JSON-RPC over stdin and stdout
+ * with Content-Length framing. Documents are synced in full on every change
+ * and re-checked right away; the editor features themselves live in
+ * `cpm_if::lsp`.
+ */
+
+struct Server {
+    schema: Option<JSONSchema>,
+    documents: HashMap<String, PolicyDocument>,
+    shutdown: bool,
+}
+
+fn load_schema(path: &str) -> Result<JSONSchema, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read schema file: {}", e))?;
+    let schema_json: Value = serde_json::from_str(&content)
+        .map_err(|e| format!("Failed to parse schema file as JSON: {}", e))?;
+    JSONSchema::compile(&schema_json).map_err(|e| format!("Failed to compile schema: {}", e))
+}
+
+// one framed message, or None at end of input
+fn read_message(input: &mut impl BufRead) -> Result<Option<Value>, String> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header).map_err(|e| e.to_string())? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = Some(value.trim().parse::<usize>().map_err(|e| format!("Invalid Content-Length: {}", e))?);
+        }
+    }
+    let length = length.ok_or("Missing Content-Length header")?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body).map_err(|e| e.to_string())?;
+    serde_json::from_slice(&body).map(Some).map_err(|e| format!("Invalid message: {}", e))
+}
+
+fn write_message(message: &Value) -> Result<(), String> {
+    let body = message.to_string();
+    let mut stdout = io::stdout().lock();
+    write!(stdout, "Content-Length: {}\r\n\r\n{}", body.len(), body)
+        .and_then(|_| stdout.flush())
+        .map_err(|e| e.to_string())
+}
+
+// the local path of a file URI, for resolving includes
+fn uri_path(uri: &str) -> Option<String> {
+    let path = uri.strip_prefix("file://")?;
+    let raw = path.as_bytes();
+    let mut bytes = vec![];
+    let mut i = 0;
+    while i < raw.len() {
+        let escaped = match raw[i] {
+            b'%' => path.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+        match escaped {
+            Some(byte) => {
+                bytes.push(byte);
+                i += 3;
+            }
+            None => {
+                bytes.push(raw[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn position(params: &Value) -> Position {
+    let field = |name: &str| params["position"][name].as_u64().unwrap_or(0) as u32;
+    Position { line: field("line"), character: field("character") }
+}
+
+impl Server {
+    fn publish_diagnostics(&self, uri: &str) -> Result<(), String> {
+        let diagnostics = match self.documents.get(uri) {
+            Some(document) => json!(document.diagnostics(self.schema.as_ref())),
+            None => json!([]),
+        };
+        write_message(&json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        }))
+    }
+
+    fn handle_notification(&mut self, method: &str, params: &Value) -> Result<(), String> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default().to_string();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.clone(), PolicyDocument::open(text, uri_path(&uri).as_deref()));
+                self.publish_diagnostics(&uri)
+            }
+            "textDocument/didChange" => {
+                // full sync: the last change holds the whole text
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes.and_then(|c| c.last()).and_then(|c| c["text"].as_str()) {
+                    self.documents.insert(uri.clone(), PolicyDocument::open(text, uri_path(&uri).as_deref()));
+                }
+                self.publish_diagnostics(&uri)
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                self.publish_diagnostics(&uri)
+            }
+            "exit" => process::exit(if self.shutdown { 0 } else { 1 }),
+            _ => Ok(()),
+        }
+    }
+
+    fn handle_request(&mut self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let document = self.documents.get(uri);
+        let location = |range| json!({ "uri": uri, "range": range });
+        match method {
+            "initialize" => {
+                if let (None, Some(path)) = (&self.schema, params["initializationOptions"]["schema"].as_str()) {
+                    self.schema = Some(load_schema(path).map_err(|e| (-32602, e))?);
+                }
+                Ok(json!({
+                    "capabilities": {
+                        "textDocumentSync": 1,
+                        "definitionProvider": true,
+                        "referencesProvider": true,
+                        "hoverProvider": true,
+                        "completionProvider": {},
+                    },
+                    "serverInfo": { "name": "cpm_if_lsp", "version": env!("CARGO_PKG_VERSION") },
+                }))
+            }
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/definition" => Ok(document
+                .and_then(|d| d.definition(position(params)))
+                .map_or(Value::Null, location)),
+            "textDocument/references" => {
+                let include_declaration = params["context"]["includeDeclaration"].as_bool().unwrap_or(true);
+                let ranges = document.map(|d| d.references(position(params), include_declaration)).unwrap_or_default();
+                Ok(Value::Array(ranges.into_iter().map(location).collect()))
+            }
+            "textDocument/hover" => Ok(document
+                .and_then(|d| d.hover(position(params)))
+                .map_or(Value::Null, |text| json!({ "contents": { "kind": "markdown", "value": text } }))),
+            "textDocument/completion" => Ok(json!(document
+                .map(|d| d.completions(position(params)))
+                .unwrap_or_default())),
+            _ => Err((-32601, format!("Method not found: {}", method))),
+        }
+    }
+
+    fn run(&mut self) -> Result<(), String> {
+        let mut input = BufReader::new(io::stdin().lock());
+        while let Some(message) = read_message(&mut input)? {
+            let method = message["method"].as_str().unwrap_or_default();
+            let params = &message["params"];
+            match message.get("id") {
+                Some(id) => {
+                    let response = match self.handle_request(method, params) {
+                        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                        Err((code, e)) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": e } }),
+                    };
+                    write_message(&response)?;
+                }
+                None => self.handle_notification(method, params)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let schema = match args.as_slice() {
+        [] => None,
+        [flag, path] if flag == "--schema" => match load_schema(path) {
+            Ok(schema) => Some(schema),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        },
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+    };
+
+    let mut server = Server { schema, documents: HashMap::new(), shutdown: false };
+    if let Err(e) = server.run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(input: &str) -> Result<Option<Value>, String> {
+        read_message(&mut input.as_bytes())
+    }
+
+    #[test]
+    fn test_read_message() {
+        let body = r#"{"jsonrpc":"2.0","method":"initialized"}"#;
+        let framed = format!("Content-Length: {}\r\nContent-Type: application/vscode-jsonrpc\r\n\r\n{}", body.len(), body);
+        assert_eq!(read(&framed).unwrap().unwrap()["method"], "initialized");
+        assert_eq!(read("").unwrap(), None);
+
+        assert_eq!(read("Content-Type: x\r\n\r\n{}").unwrap_err(), "Missing Content-Length header");
+        assert!(read("Content-Length: ten\r\n\r\n{}").unwrap_err().starts_with("Invalid Content-Length"));
+        // the body ends before Content-Length bytes
+        assert!(read("Content-Length: 20\r\n\r\n{}").is_err());
+        assert!(read("Content-Length: 2\r\n\r\n{]").unwrap_err().starts_with("Invalid message"));
+    }
+
+    #[test]
+    fn test_uri_path() {
+        assert_eq!(uri_path("file:///home/me/my%20policy.yaml").as_deref(), Some("/home/me/my policy.yaml"));
+        assert_eq!(uri_path("file:///a/%C3%A9t%C3%A9.yaml").as_deref(), Some("/a/été.yaml"));
+        // a stray percent sign is kept as it is
+        assert_eq!(uri_path("file:///a/100%.yaml").as_deref(), Some("/a/100%.yaml"));
+        assert_eq!(uri_path("untitled:Untitled-1"), None);
+    }
+
+    #[test]
+    fn test_handle_request() {
+        let uri = "file:///policy.yaml";
+        let text = "object_map: []\nsubject_map:\n  - {name: net, subjects: [xmit]}\nprivileges:\n  - principal:\n      subject: net\n    can_call: [net]\n";
+        let mut server = Server { schema: None, documents: HashMap::new(), shutdown: false };
+        server.documents.insert(uri.to_string(), PolicyDocument::new(text));
+
+        let init = server.handle_request("initialize", &json!({})).unwrap();
+        assert_eq!(init["capabilities"]["definitionProvider"], true);
+
+        let at = |line, character| json!({ "textDocument": { "uri": uri }, "position": { "line": line, "character": character } });
+        let definition = server.handle_request("textDocument/definition", &at(6, 16)).unwrap();
+        assert_eq!(definition["uri"], uri);
+        assert_eq!(definition["range"]["start"], json!({ "line": 2, "character": 11 }));
+        let references = server.handle_request("textDocument/references", &at(6, 16)).unwrap();
+        assert_eq!(references.as_array().unwrap().len(), 3);
+        // requests on unknown documents are answered, with nothing
+        let unknown = json!({ "textDocument": { "uri": "file:///other.yaml" }, "position": { "line": 0, "character": 0 } });
+        assert_eq!(server.handle_request("textDocument/hover", &unknown).unwrap(), Value::Null);
+
+        assert_eq!(server.handle_request("workspace/symbol", &json!({})).unwrap_err().0, -32601);
+        assert_eq!(server.handle_request("shutdown", &Value::Null).unwrap(), Value::Null);
+        assert!(server.shutdown);
+    }
+}
@@ -0,0 +1,316 @@
+// SPDX-License-Identifier: MIT
+//
+// MIT License
+//
+// © 2024 Nathan Dautenhahn & Serenitix LLC
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+use crate::cpm_priv_map::{
+    CallContextSubField, CallRetPrivField, Context, ContextField, ContextSimpleString, Object, Principal,
+    Privilege, RWPrivField,
+};
+
+/*
+ * Builder for privileges constructed in code:
+ *
+ *   PrivilegeBuilder::for_subject("net")
+ *       .can_call(["fs"])
+ *       .can_read_domain("skbuff")
+ *       .uid_user()
+ *       .build()?
+ *
+ * Unlike a loaded policy, a field the builder is never told about grants
+ * nothing rather than `all`; use the `*_all` methods to grant everything.
+ * Domain names given for can_read/can_write accumulate in one object
+ * without a context, and `can_read_object` adds objects with their own
+ * context. Mistakes (empty names, `all` used as a name, a field granted
+ * both `all` and a list, a uid the schema does not allow) are collected
+ * and reported by `build`.
+ */
+
+#[derive(Debug, Clone)]
+pub struct PrivilegeBuilder {
+    subject: String,
+    context: Option<Context>,
+    can_call: CallRetPrivField,
+    can_return: CallRetPrivField,
+    can_read: RWPrivField,
+    can_write: RWPrivField,
+    errors: Vec<String>,
+}
+
+impl PrivilegeBuilder {
+    pub fn for_subject(subject: impl Into<String>) -> Self {
+        Self {
+            subject: subject.into(),
+            context: None,
+            can_call: CallRetPrivField::List(vec![]),
+            can_return: CallRetPrivField::List(vec![]),
+            can_read: RWPrivField::List(vec![]),
+            can_write: RWPrivField::List(vec![]),
+            errors: vec![],
+        }
+    }
+
+    fn check_names(&mut self, field: &str, names: &[String], all_method: &str) {
+        for name in names {
+            if name.is_empty() {
+                self.errors.push(format!("{}: empty domain name", field));
+            } else if name == "all" {
+                self.errors.push(format!("{}: 'all' is not a domain name, use {}", field, all_method));
+            }
+        }
+    }
+
+    fn context(&mut self) -> &mut Context {
+        self.context.get_or_insert_with(Context::all)
+    }
+
+    /// Restricts the privilege to calls made from the given subject domains.
+    pub fn call_context<I: IntoIterator<Item = S>, S: Into<String>>(mut self, subjects: I) -> Self {
+        let subjects: Vec<String> = subjects.into_iter().map(Into::into).collect();
+        self.check_names("call_context", &subjects, "no call context");
+        self.context().set_call_context(Some(CallContextSubField::List(subjects)));
+        self
+    }
+
+    /// Sets the principal's uid: `root`, `user` or `all`, as the schema allows.
+    pub fn uid(mut self, uid: impl Into<String>) -> Self {
+        let uid = match uid.into() {
+            uid if uid == "all" => ContextSimpleString::All,
+            uid if uid == "root" || uid == "user" => ContextSimpleString::String(uid),
+            uid => {
+                self.errors.push(format!("uid: '{}' is not root, user or all", uid));
+                return self;
+            }
+        };
+        self.context().set_uid(Some(uid));
+        self
+    }
+
+    pub fn uid_root(self) -> Self {
+        self.uid("root")
+    }
+
+    pub fn uid_user(self) -> Self {
+        self.uid("user")
+    }
+
+    pub fn gid(mut self, gid: impl Into<String>) -> Self {
+        self.context().set_gid(Some(ContextSimpleString::String(gid.into())));
+        self
+    }
+
+    fn add_subjects(&mut self, field: &str, names: Vec<String>) {
+        self.check_names(field, &names, &format!("{}_all", field));
+        let target = if field == "can_call" { &mut self.can_call } else { &mut self.can_return };
+        match target {
+            CallRetPrivField::All => self.errors.push(format!("{}: lists domains but already grants all", field)),
+            CallRetPrivField::List(list) => {
+                for name in names {
+                    if !list.contains(&name) {
+                        list.push(name);
+                    }
+                }
+            }
+        }
+    }
+
+    fn grant_all_subjects(&mut self, field: &str) {
+        let target = if field == "can_call" { &mut self.can_call } else { &mut self.can_return };
+        if matches!(target, CallRetPrivField::List(list) if !list.is_empty()) {
+            self.errors.push(format!("{}: grants all but already lists domains", field));
+        }
+        *target = CallRetPrivField::All;
+    }
+
+    pub fn can_call<I: IntoIterator<Item = S>, S: Into<String>>(mut self, subjects: I) -> Self {
+        self.add_subjects("can_call", subjects.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn can_call_all(mut self) -> Self {
+        self.grant_all_subjects("can_call");
+        self
+    }
+
+    pub fn can_return<I: IntoIterator<Item = S>, S: Into<String>>(mut self, subjects: I) -> Self {
+        self.add_subjects("can_return", subjects.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn can_return_all(mut self) -> Self {
+        self.grant_all_subjects("can_return");
+        self
+    }
+
+    fn rw_field(&mut self, write: bool) -> (&'static str, &mut RWPrivField) {
+        if write { ("can_write", &mut self.can_write) } else { ("can_read", &mut self.can_read) }
+    }
+
+    // adds domains to the field's context-free object
+    fn add_domains(&mut self, write: bool, names: Vec<String>) {
+        let (field, _) = self.rw_field(write);
+        self.check_names(field, &names, &format!("{}_all", field));
+        if names.is_empty() {
+            return;
+        }
+        let (field, target) = self.rw_field(write);
+        let RWPrivField::List(objects) = target else {
+            self.errors.push(format!("{}: lists domains but already grants all", field));
+            return;
+        };
+        let plain = match objects.iter().position(|o| o.object_context() == &ContextField::All) {
+            Some(i) => &mut objects[i],
+            None => {
+                objects.push(Object::new(vec![]));
+                objects.last_mut().unwrap()
+            }
+        };
+        let mut domains = plain.objects().clone();
+        for name in names {
+            if !domains.contains(&name) {
+                domains.push(name);
+            }
+        }
+        plain.set_objects(domains);
+    }
+
+    fn add_object(&mut self, write: bool, object: Object) {
+        let (field, _) = self.rw_field(write);
+        self.check_names(field, object.objects(), &format!("{}_all", field));
+        let (field, target) = self.rw_field(write);
+        match target {
+            RWPrivField::All => self.errors.push(format!("{}: lists domains but already grants all", field)),
+            RWPrivField::List(objects) => objects.push(object),
+        }
+    }
+
+    fn grant_all_objects(&mut self, write: bool) {
+        let (field, target) = self.rw_field(write);
+        let listed = matches!(target, RWPrivField::List(list) if !list.is_empty());
+        *target = RWPrivField::All;
+        if listed {
+            self.errors.push(format!("{}: grants all but already lists domains", field));
+        }
+    }
+
+    pub fn can_read<I: IntoIterator<Item = S>, S: Into<String>>(mut self, domains: I) -> Self {
+        self.add_domains(false, domains.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn can_read_domain(self, domain: impl Into<String>) -> Self {
+        self.can_read([domain.into()])
+    }
+
+    pub fn can_read_object(mut self, object: Object) -> Self {
+        self.add_object(false, object);
+        self
+    }
+
+    pub fn can_read_all(mut self) -> Self {
+        self.grant_all_objects(false);
+        self
+    }
+
+    pub fn can_write<I: IntoIterator<Item = S>, S: Into<String>>(mut self, domains: I) -> Self {
+        self.add_domains(true, domains.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn can_write_domain(self, domain: impl Into<String>) -> Self {
+        self.can_write([domain.into()])
+    }
+
+    pub fn can_write_object(mut self, object: Object) -> Self {
+        self.add_object(true, object);
+        self
+    }
+
+    pub fn can_write_all(mut self) -> Self {
+        self.grant_all_objects(true);
+        self
+    }
+
+    /// The privilege, or every mistake made while building it.
+    pub fn build(self) -> Result<Privilege, String> {
+        let mut errors = self.errors;
+        if self.subject.is_empty() {
+            errors.insert(0, "principal: empty subject domain name".to_string());
+        }
+        if !errors.is_empty() {
+            return Err(format!("invalid privilege for '{}': {}", self.subject, errors.join("; ")));
+        }
+        Ok(Privilege::new(
+            Principal {
+                subject: self.subject,
+                execution_context: self.context.map_or(ContextField::All, ContextField::Context),
+            },
+            self.can_call,
+            self.can_return,
+            self.can_read,
+            self.can_write,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build() {
+        let privilege = PrivilegeBuilder::for_subject("net")
+            .can_call(["fs"])
+            .can_call(vec!["mm".to_string(), "fs".to_string()])
+            .can_return_all()
+            .can_read_domain("skb")
+            .can_read_object(Object::with_context(vec!["sock".to_string()],
+                                                  ContextField::Context(Context::with_call_context(vec!["fs".to_string()]))))
+            .can_read(["skb", "dev"])
+            .uid_user()
+            .build()
+            .unwrap();
+        let expected: Privilege = serde_yaml::from_str("
+principal:
+  subject: net
+  execution_context: {uid: user}
+can_call: [fs, mm]
+can_return: all
+can_read:
+  - objects: [skb, dev]
+  - objects: [sock]
+    object_context: {call_context: [fs]}
+can_write: []
+").unwrap();
+        assert_eq!(privilege, expected);
+        assert!(!privilege.implicit().is_implicit(crate::cpm_priv_map::PrivField::CanWrite));
+    }
+
+    #[test]
+    fn test_build_errors() {
+        let error = PrivilegeBuilder::for_subject("")
+            .can_call(["a"])
+            .can_call_all()
+            .can_write_all()
+            .can_write_domain("d")
+            .can_read(["", "all"])
+            .uid("bob")
+            .build()
+            .unwrap_err();
+        assert_eq!(error, "invalid privilege for '': principal: empty subject domain name; \
+can_call: grants all but already lists domains; can_write: lists domains but already grants all; \
+can_read: empty domain name; can_read: 'all' is not a domain name, use can_read_all; \
+uid: 'bob' is not root, user or all");
+    }
+}
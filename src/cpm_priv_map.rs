@@ -17,7 +17,6 @@
 use serde::{Deserialize, Deserializer, Serialize};
 use serde::de::{self, Visitor};
 use serde::ser::Serializer;
-use std::char::ToUppercase;
 use std::fmt;
 use std::collections::{HashSet, HashMap};
 
@@ -185,6 +184,109 @@ impl CPMPrivMapContainer {
 	    .unwrap_or_default() // return vec![] if global domain lookup fails
     }
 
+    /*
+     * Merge every set of object domains that share an alias class into a
+     * single domain. Two domains end up in the same class if any alias maps
+     * to both of them (transitively), computed with a union-find over the
+     * alias map. The surviving domain of a class is the one appearing first
+     * in the object map; it keeps its name and absorbs the objects of the
+     * others. All `Object.objects` references in privileges, the alias maps
+     * and the function locals map are rewritten to the surviving names.
+     */
+    pub fn coalesce_alias_domains(&mut self) -> CoalesceReport {
+	let object_map = &self.cpm_priv_map.object_map;
+	let domains_before = object_map.len();
+
+	let index_of: HashMap<&str, usize> = object_map.iter()
+	    .enumerate()
+	    .map(|(i, od)| (od.name(), i))
+	    .collect();
+
+	// union every domain that shares an alias
+	let mut classes = UnionFind::new(domains_before);
+	for domain_names in self.alias_domain_map.values() {
+	    let mut members = domain_names.iter().filter_map(|d| index_of.get(d.as_str()));
+	    if let Some(&first) = members.next() {
+		for &other in members {
+		    classes.union(first, other);
+		}
+	    }
+	}
+
+	// group domain indices by their class root, in object map order
+	let mut groups: Vec<Vec<usize>> = vec![];
+	let mut group_of_root: HashMap<usize, usize> = HashMap::new();
+	for i in 0..domains_before {
+	    let root = classes.find(i);
+	    let g = *group_of_root.entry(root).or_insert_with(|| {
+		groups.push(vec![]);
+		groups.len() - 1
+	    });
+	    groups[g].push(i);
+	}
+
+	// collect renames of absorbed domains to the surviving domain
+	let mut renames: HashMap<String, String> = HashMap::new();
+	let mut merged = vec![];
+	for group in groups.iter().filter(|g| g.len() > 1) {
+	    let survivor = object_map[group[0]].name().to_string();
+	    let absorbed: Vec<String> = group[1..].iter()
+		.map(|&i| object_map[i].name().to_string())
+		.collect();
+	    for name in &absorbed {
+		renames.insert(name.to_string(), survivor.to_string());
+	    }
+	    merged.push(MergedDomains { survivor, absorbed });
+	}
+
+	// move objects into the surviving domains and drop the absorbed ones
+	let mut domains: Vec<Option<ObjectDomain>> = std::mem::take(&mut self.cpm_priv_map.object_map)
+	    .into_iter()
+	    .map(Some)
+	    .collect();
+	for group in groups.iter().filter(|g| g.len() > 1) {
+	    for &i in &group[1..] {
+		if let Some(absorbed) = domains[i].take() {
+		    if let Some(survivor) = domains[group[0]].as_mut() {
+			survivor.objects.extend(absorbed.objects);
+		    }
+		}
+	    }
+	}
+	self.cpm_priv_map.object_map = domains.into_iter().flatten().collect();
+
+	// rewrite references to the absorbed domains
+	self.cpm_priv_map.rename_object_domain_refs(&renames);
+	self.rename_alias_map_domains(&renames);
+	for domain_name in self.function_local_domain_map.values_mut() {
+	    if let Some(new_name) = renames.get(domain_name) {
+		*domain_name = new_name.to_string();
+	    }
+	}
+
+	CoalesceReport {
+	    domains_before,
+	    domains_after: self.cpm_priv_map.object_map.len(),
+	    merged,
+	}
+    }
+
+    // rewrite domain names in both alias maps after domains were merged
+    fn rename_alias_map_domains(&mut self, renames: &HashMap<String, String>) {
+	for domain_names in self.alias_domain_map.values_mut() {
+	    *domain_names = domain_names.drain()
+		.map(|d| renames.get(&d).cloned().unwrap_or(d))
+		.collect();
+	}
+	for (old_name, new_name) in renames {
+	    if let Some(aliases) = self.domain_alias_map.remove(old_name) {
+		self.domain_alias_map.entry(new_name.to_string())
+		    .or_default()
+		    .extend(aliases);
+	    }
+	}
+    }
+
     pub fn save_to_yaml(&self, file_path: &str) ->
         Result<(), Box<dyn std::error::Error>>
     {
@@ -192,7 +294,71 @@ impl CPMPrivMapContainer {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, PartialEq)]
+/*
+ * Result of coalescing alias-sharing object domains: the number of object
+ * domains before and after, and for each merged class the surviving domain
+ * and the domains folded into it.
+ */
+#[derive(Debug, Serialize, PartialEq)]
+pub struct CoalesceReport {
+    pub domains_before: usize,
+    pub domains_after: usize,
+    pub merged: Vec<MergedDomains>,
+}
+
+impl CoalesceReport {
+    pub fn domains_removed(&self) -> usize {
+        self.domains_before - self.domains_after
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct MergedDomains {
+    pub survivor: String,
+    pub absorbed: Vec<String>,
+}
+
+// Disjoint sets over domain indices, with path compression.
+struct UnionFind {
+    parent: Vec<usize>,
+}
+
+impl UnionFind {
+    fn new(size: usize) -> Self {
+        Self { parent: (0..size).collect() }
+    }
+
+    fn find(&mut self, i: usize) -> usize {
+        let mut root = i;
+        while self.parent[root] != root {
+            root = self.parent[root];
+        }
+        let mut cur = i;
+        while self.parent[cur] != root {
+            let next = self.parent[cur];
+            self.parent[cur] = root;
+            cur = next;
+        }
+        root
+    }
+
+    // the smaller index always becomes the root so the first domain survives
+    fn union(&mut self, a: usize, b: usize) {
+        let (ra, rb) = (self.find(a), self.find(b));
+        if ra != rb {
+            let (root, child) = if ra < rb { (ra, rb) } else { (rb, ra) };
+            self.parent[child] = root;
+        }
+    }
+}
+
+impl Default for CPMPrivMapContainer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct CPMPrivMap {
     pub object_map: Vec<ObjectDomain>,
     pub subject_map: Vec<SubjectDomain>,
@@ -236,6 +402,57 @@ impl CPMPrivMap {
 	self.object_map.iter().find(|od| od.find_object(Some(global_name), None, None, Some(&AllocType::Global)).is_some())
     }
 
+    pub fn get_subject_domain_for_subject(&self, subject: &str) -> Option<&SubjectDomain> {
+	self.subject_map.iter().find(|sd| sd.subjects().iter().any(|s| s == subject))
+    }
+
+    pub fn find_object_domains(&self, name: Option<&str>, path: Option<&str>, lineno: Option<&str>, alloc_type: Option<&AllocType>) -> Vec<&ObjectDomain> {
+	self.object_map.iter().filter(|od| od.find_object(name, path, lineno, alloc_type).is_some()).collect()
+    }
+
+    // rewrite object domain names referenced by the privileges' read/write fields
+    pub fn rename_object_domain_refs(&mut self, renames: &HashMap<String, String>) {
+	if renames.is_empty() {
+	    return;
+	}
+	for privilege in self.privileges.iter_mut() {
+	    privilege.can_read.rename_domains(renames);
+	    privilege.can_write.rename_domains(renames);
+	}
+    }
+
+    pub fn from_yaml_str(yaml: &str, mode: LoadMode) -> Result<Self, String> {
+        let mut priv_map: CPMPrivMap = serde_yaml::from_str(yaml)
+            .map_err(|e| format!("Failed to parse privilege map: {}", e))?;
+        if mode == LoadMode::DenyByDefault {
+            for privilege in priv_map.privileges.iter_mut() {
+                privilege.deny_implicit_grants();
+            }
+        }
+        Ok(priv_map)
+    }
+
+    pub fn load_from_yaml(file_path: &str, mode: LoadMode) -> Result<Self, String> {
+        let yaml = std::fs::read_to_string(file_path)
+            .map_err(|e| format!("Failed to read YAML file: {}", e))?;
+        Self::from_yaml_str(&yaml, mode)
+    }
+
+    /*
+     * Every `all` grant or execution context that the policy text never
+     * spelled out, i.e. that came from an omitted or empty field.
+     */
+    pub fn implicit_grants(&self) -> Vec<ImplicitGrant> {
+        let mut grants = vec![];
+        for (index, privilege) in self.privileges.iter().enumerate() {
+            let subject = privilege.principal().subject();
+            for field in privilege.implicit_all_fields() {
+                grants.push(ImplicitGrant { index, subject: subject.to_string(), field });
+            }
+        }
+        grants
+    }
+
     pub fn save_to_yaml(&self, file_path: &str) ->
         Result<(), Box<dyn std::error::Error>>
     {
@@ -253,9 +470,13 @@ impl CPMPrivMap {
 
 }
 
-static OBJECT_DOMAIN_NUM: &usize = &0;
+impl Default for CPMPrivMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct ObjectDomain {
     name: String,
     //objects: Vec<String>,
@@ -337,12 +558,16 @@ impl ObjectDomain {
         &self.name
     }
 
+    pub fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
     pub fn objects(&self) -> &Vec<ObjectID> {
         &self.objects
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ObjectID {
     alloc_type: AllocType,
     path: String,
@@ -378,6 +603,13 @@ impl ObjectID {
     }
 }
 
+// Grammar: "<alloc_type>|<path>|<lineno>|<name>"
+impl fmt::Display for ObjectID {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}|{}|{}|{}", self.alloc_type, self.path, self.lineno, self.name)
+    }
+}
+
 // Grammar: "<alloc_type>|<path>|<lineno>|<name>"
 impl Serialize for ObjectID {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -422,7 +654,7 @@ impl<'de> Deserialize<'de> for ObjectID {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 #[serde(rename_all = "UPPERCASE")] // Automatically convert to uppercase
 pub enum AllocType {
     Global,
@@ -491,10 +723,13 @@ impl std::str::FromStr for AllocType {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct SubjectDomain {
     name: String,
     subjects: Vec<String>,
+    // optional parent whose privileges this domain inherits, see hierarchy.rs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    parent: Option<String>,
 }
 
 impl SubjectDomain {
@@ -503,9 +738,14 @@ impl SubjectDomain {
         Self {
 	    name: fn_name,
 	    subjects,
+	    parent: None,
 	}
     }
 
+    pub fn with_parent(name: String, subjects: Vec<String>, parent: String) -> Self {
+        Self { name, subjects, parent: Some(parent) }
+    }
+
     pub fn add_subject(&mut self, subject: String) {
         self.subjects.push(subject);
     }
@@ -515,16 +755,28 @@ impl SubjectDomain {
     }
 
     pub fn new_empty(name: String) -> Self {
-        Self { name, subjects: vec![] }
+        Self { name, subjects: vec![], parent: None }
     }
 
     pub fn name(&self) -> &str {
         &self.name
     }
 
+    pub fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
     pub fn subjects(&self) -> &Vec<String> {
         &self.subjects
     }
+
+    pub fn parent(&self) -> Option<&str> {
+        self.parent.as_deref()
+    }
+
+    pub fn set_parent(&mut self, parent: Option<String>) {
+        self.parent = parent;
+    }
 }
 
 /*
@@ -537,20 +789,176 @@ impl SubjectDomain {
  *          ? can_write: [ Object ] | all,
  *      }
  */
-#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(from = "RawPrivilege")]
 pub struct Privilege {
     pub principal: Principal,
-    #[serde(default = "default_callret_priv_field")]
     pub can_call: CallRetPrivField,
-    #[serde(default = "default_callret_priv_field")]
     pub can_return: CallRetPrivField,
-    #[serde(default = "default_rw_priv_field")]
     pub can_read: RWPrivField,
-    #[serde(default = "default_rw_priv_field")]
     pub can_write: RWPrivField,
+    // which `all` values came from omitted or empty fields rather than the policy text
+    #[serde(skip)]
+    implicit: ImplicitGrants,
+}
+
+/*
+ * Deserialization form of a Privilege: every optional field is an Option so
+ * that an omitted or empty (`can_write:`) field can be told apart from an
+ * explicit `all` before defaulting it.
+ */
+#[derive(Deserialize)]
+struct RawPrivilege {
+    principal: RawPrincipal,
+    #[serde(default)]
+    can_call: Option<CallRetPrivField>,
+    #[serde(default)]
+    can_return: Option<CallRetPrivField>,
+    #[serde(default)]
+    can_read: Option<RWPrivField>,
+    #[serde(default)]
+    can_write: Option<RWPrivField>,
+}
+
+#[derive(Deserialize)]
+struct RawPrincipal {
+    subject: String,
+    #[serde(default)]
+    execution_context: Option<ContextField>,
+}
+
+impl From<RawPrivilege> for Privilege {
+    fn from(raw: RawPrivilege) -> Self {
+        let implicit = ImplicitGrants {
+            execution_context: raw.principal.execution_context.is_none(),
+            can_call: raw.can_call.is_none(),
+            can_return: raw.can_return.is_none(),
+            can_read: raw.can_read.is_none(),
+            can_write: raw.can_write.is_none(),
+        };
+        Self {
+            principal: Principal {
+                subject: raw.principal.subject,
+                execution_context: raw.principal.execution_context.unwrap_or_else(default_context_field),
+            },
+            can_call: raw.can_call.unwrap_or_else(default_callret_priv_field),
+            can_return: raw.can_return.unwrap_or_else(default_callret_priv_field),
+            can_read: raw.can_read.unwrap_or_else(default_rw_priv_field),
+            can_write: raw.can_write.unwrap_or_else(default_rw_priv_field),
+            implicit,
+        }
+    }
+}
+
+// Provenance is not part of a privilege's meaning, so it is left out of equality.
+impl PartialEq for Privilege {
+    fn eq(&self, other: &Self) -> bool {
+        self.principal == other.principal
+            && self.can_call == other.can_call
+            && self.can_return == other.can_return
+            && self.can_read == other.can_read
+            && self.can_write == other.can_write
+    }
+}
+
+impl Eq for Privilege {}
+
+// hashes the same fields as PartialEq compares
+impl std::hash::Hash for Privilege {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.principal.hash(state);
+        self.can_call.hash(state);
+        self.can_return.hash(state);
+        self.can_read.hash(state);
+        self.can_write.hash(state);
+    }
+}
+
+/*
+ * Records, per field, whether the value was defaulted because the field was
+ * omitted or left empty in the policy. Privileges built in code have no
+ * implicit fields.
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ImplicitGrants {
+    pub execution_context: bool,
+    pub can_call: bool,
+    pub can_return: bool,
+    pub can_read: bool,
+    pub can_write: bool,
+}
+
+impl ImplicitGrants {
+    pub fn is_implicit(&self, field: PrivField) -> bool {
+        match field {
+            PrivField::CanCall => self.can_call,
+            PrivField::CanReturn => self.can_return,
+            PrivField::CanRead => self.can_read,
+            PrivField::CanWrite => self.can_write,
+        }
+    }
+}
+
+/*
+ * How omitted privilege fields are interpreted when loading a policy. The
+ * grammar defaults them to `all`; deny-by-default treats them as empty lists
+ * instead. Omitted execution contexts are left as `all` in both modes since
+ * they restrict when a grant applies rather than grant anything.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LoadMode {
+    #[default]
+    AllowByDefault,
+    DenyByDefault,
 }
 
 impl Privilege {
+    pub fn new(principal: Principal, can_call: CallRetPrivField, can_return: CallRetPrivField,
+               can_read: RWPrivField, can_write: RWPrivField) -> Self {
+        Self {
+            principal,
+            can_call,
+            can_return,
+            can_read,
+            can_write,
+            implicit: ImplicitGrants::default(),
+        }
+    }
+
+    pub fn implicit(&self) -> &ImplicitGrants {
+        &self.implicit
+    }
+
+    // the fields, execution context included, whose `all` came from an omitted or empty field
+    pub fn implicit_all_fields(&self) -> Vec<&'static str> {
+        let mut fields = vec![];
+        if self.implicit.execution_context && self.principal.execution_context() == &ContextField::All {
+            fields.push("execution_context");
+        }
+        for field in PrivField::ALL {
+            if self.implicit.is_implicit(field) && self.grants_all(field) {
+                fields.push(field.name());
+            }
+        }
+        fields
+    }
+
+    // replace every `all` that came from an omitted field with an empty list
+    pub fn deny_implicit_grants(&mut self) {
+        if self.implicit.can_call && self.can_call == CallRetPrivField::All {
+            self.can_call = CallRetPrivField::List(vec![]);
+        }
+        if self.implicit.can_return && self.can_return == CallRetPrivField::All {
+            self.can_return = CallRetPrivField::List(vec![]);
+        }
+        if self.implicit.can_read && self.can_read == RWPrivField::All {
+            self.can_read = RWPrivField::List(vec![]);
+        }
+        if self.implicit.can_write && self.can_write == RWPrivField::All {
+            self.can_write = RWPrivField::List(vec![]);
+        }
+    }
+
     pub fn principal(&self) -> &Principal {
         &self.principal
     }
@@ -571,15 +979,67 @@ impl Privilege {
         &self.can_write
     }
 
+    // true if the given field grants `all`
+    pub fn grants_all(&self, field: PrivField) -> bool {
+        match field {
+            PrivField::CanCall => self.can_call == CallRetPrivField::All,
+            PrivField::CanReturn => self.can_return == CallRetPrivField::All,
+            PrivField::CanRead => self.can_read == RWPrivField::All,
+            PrivField::CanWrite => self.can_write == RWPrivField::All,
+        }
+    }
+
+}
+
+/*
+ * Names the four grant fields of a Privilege so analyses can report on them
+ * uniformly. Serializes to the field names used in the YAML grammar.
+ */
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum PrivField {
+    CanCall,
+    CanReturn,
+    CanRead,
+    CanWrite,
+}
+
+impl PrivField {
+    pub const ALL: [PrivField; 4] = [PrivField::CanCall, PrivField::CanReturn, PrivField::CanRead, PrivField::CanWrite];
+}
+
+impl PrivField {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PrivField::CanCall => "can_call",
+            PrivField::CanReturn => "can_return",
+            PrivField::CanRead => "can_read",
+            PrivField::CanWrite => "can_write",
+        }
+    }
+}
+
+impl fmt::Display for PrivField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// A grant of `all` that was defaulted from an omitted or empty field.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ImplicitGrant {
+    pub index: usize,
+    pub subject: String,
+    pub field: &'static str,
 }
 
 fn default_callret_priv_field() -> CallRetPrivField {
     CallRetPrivField::All
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum CallRetPrivField {
-    // TODO: switch to ObjectIdentifier/SubjectIdentifiers
+    // names are resolved to SubjectDomainId handles by crate::linked
     // Grammar: ? can call: [ SubjectDomainName ] | all,
     List(Vec<String>),
     All,
@@ -649,17 +1109,21 @@ fn default_rw_priv_field() -> RWPrivField {
     RWPrivField::All
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum RWPrivField {
     List(Vec<Object>),
     All,
 }
 
 impl RWPrivField {
-    pub fn add_object(&mut self, object: Object) {
+    // false if the field grants `all`, which already includes the object
+    pub fn add_object(&mut self, object: Object) -> bool {
 	match self {
-	    RWPrivField::List(ref mut list) => list.push(object),
-	    RWPrivField::All => () // technically object is already included in All
+	    RWPrivField::List(ref mut list) => {
+		list.push(object);
+		true
+	    }
+	    RWPrivField::All => false,
 	}
     }
     pub fn contains_domain(&self, domain: &str) -> bool {
@@ -668,6 +1132,13 @@ impl RWPrivField {
 	    RWPrivField::All => true,
 	}
     }
+    pub fn rename_domains(&mut self, renames: &HashMap<String, String>) {
+	if let RWPrivField::List(ref mut list) = self {
+	    for object in list.iter_mut() {
+		object.rename_domains(renames);
+	    }
+	}
+    }
 }
 
 impl<'de> Deserialize<'de> for RWPrivField {
@@ -734,25 +1205,16 @@ impl Serialize for RWPrivField {
  * Principal ::= { subject: SubjectDomain, ? execution context: Context | all }
  *   - if field missing, default to all, if it is then parse to all or Context
  */
-#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq, Hash)]
 pub struct Principal {
-    // TODO make this work correctly: point to a subject domain
-    //   eg: subject: SubjectDomain, // but a reference to a subject domain
-    //   well the grammar specifies it like this right now. so i will be
-    //   faithful to the grammar and propose changes after one version
-    //subject: SubjectDomain,
+    // the grammar names the subject domain; crate::linked resolves it to a
+    // SubjectDomainId
     pub subject: String,
     #[serde(default = "default_context_field")]
     pub execution_context: ContextField,
 }
 
 impl Principal {
-    /*
-    TODO Make proper subject doamin in next iteration, string for now
-    pub fn subject(&self) -> &SubjectDomain {
-        &self.subject
-    }
-    */
     pub fn subject(&self) -> &String {
         &self.subject
     }
@@ -773,7 +1235,7 @@ fn default_context_field() -> ContextField {
  * as a context object. This enum allows for either a defined context or "all",
  * which then leads to simpler serialization and deserialization.
  */
-#[derive(Debug, Serialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, PartialEq, Eq, Hash)]
 pub enum ContextField {
     Context(Context),
     #[serde(rename = "all")] // Serialize/deserialize "All" as "all"
@@ -833,7 +1295,7 @@ impl<'de> Deserialize<'de> for ContextField {
 //               ? uid: root | user | Variable | all,
 //               ? guid: Variable | all }
 // TODO: handle the option and default values correctly
-#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq, Hash)]
 pub struct Context {
     #[serde(default = "default_call_context_sub_field")]
     call_context: Option<CallContextSubField>,
@@ -844,10 +1306,28 @@ pub struct Context {
 }
 
 impl Context {
+    pub fn new(call_context: Option<CallContextSubField>, uid: Option<ContextSimpleString>,
+               gid: Option<ContextSimpleString>) -> Self {
+        Self { call_context, uid, gid }
+    }
+
+    // every field `all`, as when deserialized from an empty context
+    pub fn all() -> Self {
+        Self::new(default_call_context_sub_field(), default_context_simple(), default_context_simple())
+    }
+
+    pub fn with_call_context(subjects: Vec<String>) -> Self {
+        Self { call_context: Some(CallContextSubField::List(subjects)), ..Self::all() }
+    }
+
     pub fn call_context(&self) -> &Option<CallContextSubField> {
         &self.call_context
     }
 
+    pub fn set_call_context(&mut self, call_context: Option<CallContextSubField>) {
+        self.call_context = call_context;
+    }
+
     pub fn uid(&self) -> &Option<ContextSimpleString> {
         &self.uid
     }
@@ -855,13 +1335,27 @@ impl Context {
     pub fn gid(&self) -> &Option<ContextSimpleString> {
         &self.gid
     }
+
+    pub fn set_uid(&mut self, uid: Option<ContextSimpleString>) {
+        self.uid = uid;
+    }
+
+    pub fn set_gid(&mut self, gid: Option<ContextSimpleString>) {
+        self.gid = gid;
+    }
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Self::all()
+    }
 }
 
 fn default_call_context_sub_field() -> Option<CallContextSubField> {
     Some(CallContextSubField::All) // Placeholder for yet to be implemented
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 /*
  * This serializes to a vector of strings or a vector of a single string "all"
  */
@@ -897,7 +1391,7 @@ impl<'de> Deserialize<'de> for CallContextSubField {
                 formatter.write_str("a list of strings or a list with a single string \"all\"")
             }
 
-            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            fn visit_seq<A>(self, seq: A) -> Result<Self::Value, A::Error>
             where
                 A: de::SeqAccess<'de>,
             {
@@ -918,7 +1412,7 @@ fn default_context_simple() -> Option<ContextSimpleString> {
     Some(ContextSimpleString::All)
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum ContextSimpleString {
     String(String),
     All,
@@ -970,10 +1464,10 @@ impl<'de> Deserialize<'de> for ContextSimpleString {
  * Grammar: Object ::= { objects: [ ObjectDomainName ] | all
  *                     ? object_context: Context | all }
  */
-#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq, Hash)]
 pub struct Object {
+    // object domain names, resolved to ObjectDomainId handles by crate::linked
     objects: Vec<String>,
-    ///objects: Vec<ObjectIdentifier>,
     #[serde(default = "default_context_field")]
     object_context: ContextField,
 }
@@ -985,6 +1479,12 @@ impl Object {
 	    object_context: default_context_field(),
 	}
     }
+    pub fn with_context(objs: Vec<String>, object_context: ContextField) -> Self {
+	Self {
+	    objects: objs,
+	    object_context,
+	}
+    }
     pub fn new_empty_from_domain_name(name: String) -> Self {
 	Self {
 	    objects: vec![name],
@@ -995,9 +1495,33 @@ impl Object {
         &self.objects
     }
 
+    pub fn set_objects(&mut self, objects: Vec<String>) {
+        self.objects = objects;
+    }
+
+    // rename referenced domains, dropping only the duplicates created by the rename
+    pub fn rename_domains(&mut self, renames: &HashMap<String, String>) {
+        if !self.objects.iter().any(|o| renames.contains_key(o)) {
+            return;
+        }
+        let kept: HashSet<String> = self.objects.iter().filter(|o| !renames.contains_key(*o)).cloned().collect();
+        let mut renamed = HashSet::new();
+        self.objects = std::mem::take(&mut self.objects)
+            .into_iter()
+            .filter_map(|o| match renames.get(&o) {
+                None => Some(o),
+                Some(new) => (!kept.contains(new) && renamed.insert(new.to_string())).then(|| new.to_string()),
+            })
+            .collect();
+    }
+
     pub fn object_context(&self) -> &ContextField {
         &self.object_context
     }
+
+    pub fn set_object_context(&mut self, object_context: ContextField) {
+        self.object_context = object_context;
+    }
 }
 
 // Unit tests
@@ -1279,6 +1803,7 @@ object_context:
                 subject_map: vec![SubjectDomain {
                     name: "subject1".to_string(),
                     subjects: vec!["subject1".to_string(), "subject2".to_string()],
+                    parent: None,
                 }],
                 privileges: vec![Privilege {
                     principal: Principal {
@@ -1289,6 +1814,7 @@ object_context:
                     can_return: CallRetPrivField::All,
                     can_read: RWPrivField::All,
                     can_write: RWPrivField::All,
+                    implicit: ImplicitGrants::default(),
                 }],
             }
         );
@@ -1331,6 +1857,115 @@ privileges:
         assert_eq!(normalized_input, normalized_serialized);
     }
 
+    const IMPLICIT_YAML: &str = "
+object_map: []
+subject_map: []
+privileges:
+  - principal:
+      subject: s1
+      execution_context:
+    can_call: all
+    can_write:
+  - principal:
+      subject: s2
+      execution_context: all
+    can_call: []
+    can_return: all
+    can_read: []
+    can_write: all
+";
+
+    #[test]
+    fn test_implicit_grants() {
+        let priv_map = CPMPrivMap::from_yaml_str(IMPLICIT_YAML, LoadMode::AllowByDefault).unwrap();
+        let s1 = &priv_map.privileges()[0];
+        assert_eq!(s1.implicit(), &ImplicitGrants {
+            execution_context: true,
+            can_call: false,
+            can_return: true,
+            can_read: true,
+            can_write: true,
+        });
+        assert_eq!(s1.can_write(), &RWPrivField::All);
+        assert_eq!(priv_map.privileges()[1].implicit(), &ImplicitGrants::default());
+
+        let grants = priv_map.implicit_grants();
+        let fields: Vec<(&str, &str)> = grants.iter()
+            .map(|g| (g.subject.as_str(), g.field))
+            .collect();
+        assert_eq!(fields, vec![
+            ("s1", "execution_context"),
+            ("s1", "can_return"),
+            ("s1", "can_read"),
+            ("s1", "can_write"),
+        ]);
+    }
+
+    #[test]
+    fn test_deny_by_default() {
+        let priv_map = CPMPrivMap::from_yaml_str(IMPLICIT_YAML, LoadMode::DenyByDefault).unwrap();
+        let s1 = &priv_map.privileges()[0];
+        assert_eq!(s1.can_call(), &CallRetPrivField::All);
+        assert_eq!(s1.can_return(), &CallRetPrivField::List(vec![]));
+        assert_eq!(s1.can_read(), &RWPrivField::List(vec![]));
+        assert_eq!(s1.can_write(), &RWPrivField::List(vec![]));
+        assert_eq!(s1.principal().execution_context(), &ContextField::All);
+        assert_eq!(priv_map.privileges()[1].can_write(), &RWPrivField::All);
+
+        // only the defaulted execution context is left to flag
+        assert_eq!(priv_map.implicit_grants().len(), 1);
+    }
+
+    #[test]
+    fn test_coalesce_alias_domains() {
+        let mut container = CPMPrivMapContainer::new();
+        container.add_global("g".to_string(), "a.c".to_string(), "1".to_string(), "alias1".to_string());
+        container.add_alloc("kmalloc".to_string(), "a.c".to_string(), "10".to_string(),
+                            &vec!["alias1".to_string(), "alias2".to_string()]);
+        container.add_alloc("kmalloc".to_string(), "b.c".to_string(), "20".to_string(),
+                            &vec!["alias2".to_string()]);
+        container.add_alloc("vmalloc".to_string(), "c.c".to_string(), "30".to_string(),
+                            &vec!["alias3".to_string()]);
+
+        let names: Vec<String> = container.cpm_priv_map.object_map().iter()
+            .map(|od| od.name().to_string())
+            .collect();
+        container.add_privilege(Privilege::new(
+            Principal {
+                subject: "s".to_string(),
+                execution_context: ContextField::All,
+            },
+            CallRetPrivField::All,
+            CallRetPrivField::All,
+            RWPrivField::List(vec![Object::new(vec![names[1].clone(), names[2].clone()])]),
+            RWPrivField::List(vec![Object::new(vec![names[3].clone(), names[3].clone()])]),
+        ));
+
+        let report = container.coalesce_alias_domains();
+        assert_eq!(report.domains_before, 4);
+        assert_eq!(report.domains_after, 2);
+        assert_eq!(report.domains_removed(), 2);
+        assert_eq!(report.merged, vec![MergedDomains {
+            survivor: names[0].clone(),
+            absorbed: vec![names[1].clone(), names[2].clone()],
+        }]);
+
+        let object_map = container.cpm_priv_map.object_map();
+        assert_eq!(object_map[0].name(), names[0]);
+        assert_eq!(object_map[0].objects().len(), 3);
+        assert_eq!(object_map[1].name(), names[3]);
+
+        let privilege = &container.cpm_priv_map.privileges()[0];
+        assert_eq!(privilege.can_read, RWPrivField::List(vec![Object::new(vec![names[0].clone()])]));
+        // a list without renamed domains is left exactly as it was
+        assert_eq!(privilege.can_write, RWPrivField::List(vec![Object::new(vec![names[3].clone(), names[3].clone()])]));
+
+        let mut domains = container.get_domains_for_aliases(vec!["alias2".to_string()]);
+        domains.dedup();
+        assert_eq!(domains, vec![names[0].clone()]);
+        assert_eq!(container.get_all_domains_for_global("g"), vec![names[0].clone(); 2]);
+    }
+
     #[test]
     fn test_save_to_yaml() {
         let mut cpm_pmap = CPMPrivMap::new();
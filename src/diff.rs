@@ -0,0 +1,161 @@
+// SPDX-License-Identifier: MIT
+//
+// MIT License
+//
+// © 2024 Nathan Dautenhahn & Serenitix LLC
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+use serde::Serialize;
+use std::fmt;
+
+use crate::cpm_priv_map::{CPMPrivMap, Privilege};
+
+/*
+ * Structural difference between two policies, keyed by name: object and
+ * subject domains are compared by their objects/subjects, and privileges are
+ * compared per principal as the ordered list of privileges naming it.
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangedEntity {
+    ObjectDomain,
+    SubjectDomain,
+    // the privileges of a principal
+    Privileges,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PolicyChange {
+    pub kind: ChangeKind,
+    pub entity: ChangedEntity,
+    pub name: String,
+}
+
+impl fmt::Display for PolicyChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = match self.kind {
+            ChangeKind::Added => '+',
+            ChangeKind::Removed => '-',
+            ChangeKind::Changed => '~',
+        };
+        let entity = match self.entity {
+            ChangedEntity::ObjectDomain => "object domain",
+            ChangedEntity::SubjectDomain => "subject domain",
+            ChangedEntity::Privileges => "privileges of",
+        };
+        write!(f, "{} {} {}", sign, entity, self.name)
+    }
+}
+
+// compares two keyed lists, reporting in the order of `old` then the additions of `new`
+fn diff_keyed<T: PartialEq>(entity: ChangedEntity, old: &[(String, T)], new: &[(String, T)],
+                            changes: &mut Vec<PolicyChange>) {
+    let change = |kind, name: &str| PolicyChange { kind, entity, name: name.to_string() };
+    for (name, value) in old {
+        match new.iter().find(|(n, _)| n == name) {
+            None => changes.push(change(ChangeKind::Removed, name)),
+            Some((_, other)) if other != value => changes.push(change(ChangeKind::Changed, name)),
+            Some(_) => (),
+        }
+    }
+    for (name, _) in new.iter().filter(|(n, _)| !old.iter().any(|(o, _)| o == n)) {
+        changes.push(change(ChangeKind::Added, name));
+    }
+}
+
+// principals in first-privilege order with their privileges
+fn privileges_by_principal(priv_map: &CPMPrivMap) -> Vec<(String, Vec<&Privilege>)> {
+    let mut grouped: Vec<(String, Vec<&Privilege>)> = vec![];
+    for privilege in priv_map.privileges() {
+        let subject = privilege.principal().subject();
+        match grouped.iter_mut().find(|(s, _)| s == subject) {
+            Some((_, list)) => list.push(privilege),
+            None => grouped.push((subject.to_string(), vec![privilege])),
+        }
+    }
+    grouped
+}
+
+/// Changes turning `old` into `new`.
+pub fn diff_policies(old: &CPMPrivMap, new: &CPMPrivMap) -> Vec<PolicyChange> {
+    let mut changes = vec![];
+    let objects = |m: &CPMPrivMap| m.object_map().iter()
+        .map(|od| (od.name().to_string(), od.objects().clone()))
+        .collect::<Vec<_>>();
+    diff_keyed(ChangedEntity::ObjectDomain, &objects(old), &objects(new), &mut changes);
+    let subjects = |m: &CPMPrivMap| m.subject_map().iter()
+        .map(|sd| (sd.name().to_string(), sd.subjects().clone()))
+        .collect::<Vec<_>>();
+    diff_keyed(ChangedEntity::SubjectDomain, &subjects(old), &subjects(new), &mut changes);
+    diff_keyed(ChangedEntity::Privileges, &privileges_by_principal(old), &privileges_by_principal(new), &mut changes);
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_policies() {
+        let old: CPMPrivMap = serde_yaml::from_str("
+object_map:
+  - {name: od1, objects: [a]}
+  - {name: od2, objects: [b]}
+subject_map:
+  - {name: s1, subjects: [f1]}
+privileges:
+  - principal: {subject: s1}
+    can_call: []
+    can_return: []
+    can_read: []
+    can_write: []
+").unwrap();
+        let new: CPMPrivMap = serde_yaml::from_str("
+object_map:
+  - {name: od2, objects: [b, c]}
+  - {name: od3, objects: []}
+subject_map:
+  - {name: s1, subjects: [f1]}
+  - {name: s2, subjects: [f2]}
+privileges:
+  - principal: {subject: s1}
+    can_call: [s2]
+    can_return: []
+    can_read: []
+    can_write: []
+  - principal: {subject: s2}
+    can_call: []
+    can_return: [s1]
+    can_read: []
+    can_write: []
+").unwrap();
+        let changes: Vec<String> = diff_policies(&old, &new).iter().map(|c| c.to_string()).collect();
+        assert_eq!(changes, vec![
+            "- object domain od1",
+            "~ object domain od2",
+            "+ object domain od3",
+            "+ subject domain s2",
+            "~ privileges of s1",
+            "+ privileges of s2",
+        ]);
+        assert!(diff_policies(&new, &new).is_empty());
+    }
+}
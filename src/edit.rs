@@ -0,0 +1,546 @@
+// SPDX-License-Identifier: MIT
+//
+// MIT License
+//
+// © 2024 Nathan Dautenhahn & Serenitix LLC
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+use crate::cpm_priv_map::{
+    CPMPrivMap, CallContextSubField, CallRetPrivField, ContextField, Object, ObjectDomain, ObjectID, PrivField,
+    Privilege, RWPrivField, SubjectDomain,
+};
+
+/*
+ * Mutation API for CPMPrivMap. Every edit keeps the map referentially
+ * consistent: removing a domain drops every reference to it (and the
+ * privileges of a removed subject domain), renaming rewrites references
+ * and `parent` fields, and granting checks that the domains exist.
+ *
+ * Edits are recorded as a ChangeLog of entry-level insertions, removals and
+ * replacements in the order they were applied. `undo` applies the inverse
+ * of a log to the map as it was right after the log, and returns the log
+ * of the undo itself, which redoes the edit. Logs of several edits can be
+ * appended to undo them as one.
+ *
+ * Domains are looked up by their first definition. Patterns are ordinary
+ * names here, so edit maps after pattern expansion.
+ */
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    InsertObjectDomain { index: usize, domain: ObjectDomain },
+    RemoveObjectDomain { index: usize, domain: ObjectDomain },
+    ReplaceObjectDomain { index: usize, old: ObjectDomain, new: ObjectDomain },
+    InsertSubjectDomain { index: usize, domain: SubjectDomain },
+    RemoveSubjectDomain { index: usize, domain: SubjectDomain },
+    ReplaceSubjectDomain { index: usize, old: SubjectDomain, new: SubjectDomain },
+    InsertPrivilege { index: usize, privilege: Privilege },
+    RemovePrivilege { index: usize, privilege: Privilege },
+    ReplacePrivilege { index: usize, old: Privilege, new: Privilege },
+}
+
+impl Change {
+    pub fn inverse(&self) -> Change {
+        match self.clone() {
+            Change::InsertObjectDomain { index, domain } => Change::RemoveObjectDomain { index, domain },
+            Change::RemoveObjectDomain { index, domain } => Change::InsertObjectDomain { index, domain },
+            Change::ReplaceObjectDomain { index, old, new } => Change::ReplaceObjectDomain { index, old: new, new: old },
+            Change::InsertSubjectDomain { index, domain } => Change::RemoveSubjectDomain { index, domain },
+            Change::RemoveSubjectDomain { index, domain } => Change::InsertSubjectDomain { index, domain },
+            Change::ReplaceSubjectDomain { index, old, new } => Change::ReplaceSubjectDomain { index, old: new, new: old },
+            Change::InsertPrivilege { index, privilege } => Change::RemovePrivilege { index, privilege },
+            Change::RemovePrivilege { index, privilege } => Change::InsertPrivilege { index, privilege },
+            Change::ReplacePrivilege { index, old, new } => Change::ReplacePrivilege { index, old: new, new: old },
+        }
+    }
+
+    fn apply(&self, priv_map: &mut CPMPrivMap) {
+        match self {
+            Change::InsertObjectDomain { index, domain } => priv_map.object_map.insert(*index, domain.clone()),
+            Change::RemoveObjectDomain { index, .. } => { priv_map.object_map.remove(*index); }
+            Change::ReplaceObjectDomain { index, new, .. } => priv_map.object_map[*index] = new.clone(),
+            Change::InsertSubjectDomain { index, domain } => priv_map.subject_map.insert(*index, domain.clone()),
+            Change::RemoveSubjectDomain { index, .. } => { priv_map.subject_map.remove(*index); }
+            Change::ReplaceSubjectDomain { index, new, .. } => priv_map.subject_map[*index] = new.clone(),
+            Change::InsertPrivilege { index, privilege } => priv_map.privileges.insert(*index, privilege.clone()),
+            Change::RemovePrivilege { index, .. } => { priv_map.privileges.remove(*index); }
+            Change::ReplacePrivilege { index, new, .. } => priv_map.privileges[*index] = new.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChangeLog {
+    changes: Vec<Change>,
+}
+
+impl ChangeLog {
+    pub fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Appends a later log so both are undone together.
+    pub fn append(&mut self, mut other: ChangeLog) {
+        self.changes.append(&mut other.changes);
+    }
+}
+
+// maps a reference to its replacements; renamed references already present are dropped
+pub(crate) type Rewrite<'a> = &'a dyn Fn(&str) -> Vec<String>;
+
+fn rewrite_names(names: &[String], f: Rewrite) -> Vec<String> {
+    let mut out: Vec<String> = vec![];
+    for name in names {
+        for new in f(name) {
+            let duplicate = new != *name && (names.contains(&new) || out.contains(&new));
+            if !duplicate {
+                out.push(new);
+            }
+        }
+    }
+    out
+}
+
+fn rewrite_context(context: &mut ContextField, f: Rewrite) {
+    if let ContextField::Context(context) = context {
+        if let Some(CallContextSubField::List(names)) = context.call_context() {
+            let names = rewrite_names(names, f);
+            context.set_call_context(Some(CallContextSubField::List(names)));
+        }
+    }
+}
+
+// rewrites call/return targets and call contexts; the principal is left alone
+pub(crate) fn rewrite_subject_refs(privilege: &Privilege, f: Rewrite) -> Privilege {
+    let mut privilege = privilege.clone();
+    rewrite_context(&mut privilege.principal.execution_context, f);
+    for field in [&mut privilege.can_call, &mut privilege.can_return] {
+        if let CallRetPrivField::List(names) = field {
+            *names = rewrite_names(names, f);
+        }
+    }
+    for field in [&mut privilege.can_read, &mut privilege.can_write] {
+        if let RWPrivField::List(objects) = field {
+            for object in objects.iter_mut() {
+                let mut context = object.object_context().clone();
+                rewrite_context(&mut context, f);
+                object.set_object_context(context);
+            }
+        }
+    }
+    privilege
+}
+
+// rewrites read/write references, dropping objects left without domains
+pub(crate) fn rewrite_object_refs(privilege: &Privilege, f: Rewrite) -> Privilege {
+    let mut privilege = privilege.clone();
+    for field in [&mut privilege.can_read, &mut privilege.can_write] {
+        if let RWPrivField::List(objects) = field {
+            objects.retain_mut(|object| {
+                let names = rewrite_names(object.objects(), f);
+                let keep = object.objects().is_empty() || !names.is_empty();
+                object.set_objects(names);
+                keep
+            });
+        }
+    }
+    privilege
+}
+
+fn call_ret_field(privilege: &mut Privilege, field: PrivField) -> Option<&mut CallRetPrivField> {
+    match field {
+        PrivField::CanCall => Some(&mut privilege.can_call),
+        PrivField::CanReturn => Some(&mut privilege.can_return),
+        _ => None,
+    }
+}
+
+fn rw_field(privilege: &mut Privilege, field: PrivField) -> Option<&mut RWPrivField> {
+    match field {
+        PrivField::CanRead => Some(&mut privilege.can_read),
+        PrivField::CanWrite => Some(&mut privilege.can_write),
+        _ => None,
+    }
+}
+
+impl CPMPrivMap {
+    fn record(&mut self, change: Change, log: &mut ChangeLog) {
+        change.apply(self);
+        log.changes.push(change);
+    }
+
+    fn object_index(&self, name: &str) -> Result<usize, String> {
+        self.object_map.iter().position(|od| od.name() == name)
+            .ok_or_else(|| format!("undefined object domain '{}'", name))
+    }
+
+    fn subject_index(&self, name: &str) -> Result<usize, String> {
+        self.subject_map.iter().position(|sd| sd.name() == name)
+            .ok_or_else(|| format!("undefined subject domain '{}'", name))
+    }
+
+    fn check_unused_name(&self, name: &str) -> Result<(), String> {
+        let used = self.object_map.iter().any(|od| od.name() == name)
+            || self.subject_map.iter().any(|sd| sd.name() == name);
+        match used {
+            true => Err(format!("domain '{}' is already defined", name)),
+            false => Ok(()),
+        }
+    }
+
+    fn check_privilege_index(&self, index: usize) -> Result<(), String> {
+        match index < self.privileges.len() {
+            true => Ok(()),
+            false => Err(format!("no privilege at index {}", index)),
+        }
+    }
+
+    // every reference of the privilege must name a defined domain
+    fn check_references(&self, privilege: &Privilege) -> Result<(), String> {
+        self.subject_index(privilege.principal().subject())?;
+        let undefined = std::cell::RefCell::new(None);
+        let check = |name: &str, defined: Result<usize, String>| {
+            if let Err(e) = defined {
+                undefined.borrow_mut().get_or_insert(e);
+            }
+            vec![name.to_string()]
+        };
+        rewrite_subject_refs(privilege, &|n| check(n, self.subject_index(n)));
+        rewrite_object_refs(privilege, &|n| check(n, self.object_index(n)));
+        match undefined.into_inner() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    fn rewrite_privileges(&mut self, rewrite: impl Fn(&Privilege) -> Privilege, log: &mut ChangeLog) {
+        for index in 0..self.privileges.len() {
+            let new = rewrite(&self.privileges[index]);
+            if new != self.privileges[index] {
+                let old = self.privileges[index].clone();
+                self.record(Change::ReplacePrivilege { index, old, new }, log);
+            }
+        }
+    }
+
+    fn replace_privilege(&mut self, index: usize, new: Privilege) -> ChangeLog {
+        let mut log = ChangeLog::default();
+        if new != self.privileges[index] {
+            let old = self.privileges[index].clone();
+            self.record(Change::ReplacePrivilege { index, old, new }, &mut log);
+        }
+        log
+    }
+
+    /// Removes an object domain and every read/write reference to it.
+    pub fn remove_object_domain(&mut self, name: &str) -> Result<ChangeLog, String> {
+        let index = self.object_index(name)?;
+        let mut log = ChangeLog::default();
+        self.rewrite_privileges(|p| rewrite_object_refs(p, &|n| if n == name { vec![] } else { vec![n.to_string()] }), &mut log);
+        let domain = self.object_map[index].clone();
+        self.record(Change::RemoveObjectDomain { index, domain }, &mut log);
+        Ok(log)
+    }
+
+    /*
+     * Removes a subject domain, the privileges it is the principal of and
+     * every reference to it. Its children are re-parented to its parent so
+     * they keep what they inherited from further up.
+     */
+    pub fn remove_subject_domain(&mut self, name: &str) -> Result<ChangeLog, String> {
+        let index = self.subject_index(name)?;
+        let mut log = ChangeLog::default();
+        let grandparent = self.subject_map[index].parent().map(String::from);
+        for child in 0..self.subject_map.len() {
+            if self.subject_map[child].parent() == Some(name) {
+                let old = self.subject_map[child].clone();
+                let mut new = old.clone();
+                new.set_parent(grandparent.clone());
+                self.record(Change::ReplaceSubjectDomain { index: child, old, new }, &mut log);
+            }
+        }
+        for p in (0..self.privileges.len()).rev() {
+            if self.privileges[p].principal().subject() == name {
+                let privilege = self.privileges[p].clone();
+                self.record(Change::RemovePrivilege { index: p, privilege }, &mut log);
+            }
+        }
+        self.rewrite_privileges(|p| rewrite_subject_refs(p, &|n| if n == name { vec![] } else { vec![n.to_string()] }), &mut log);
+        let domain = self.subject_map[index].clone();
+        self.record(Change::RemoveSubjectDomain { index, domain }, &mut log);
+        Ok(log)
+    }
+
+    pub fn rename_object_domain(&mut self, name: &str, new_name: &str) -> Result<ChangeLog, String> {
+        let index = self.object_index(name)?;
+        self.check_unused_name(new_name)?;
+        let mut log = ChangeLog::default();
+        let old = self.object_map[index].clone();
+        let mut new = old.clone();
+        new.set_name(new_name.to_string());
+        self.record(Change::ReplaceObjectDomain { index, old, new }, &mut log);
+        let rename = |n: &str| vec![if n == name { new_name } else { n }.to_string()];
+        self.rewrite_privileges(|p| rewrite_object_refs(p, &rename), &mut log);
+        Ok(log)
+    }
+
+    /// Renames a subject domain in principals, call/return targets, call contexts and parents.
+    pub fn rename_subject_domain(&mut self, name: &str, new_name: &str) -> Result<ChangeLog, String> {
+        let index = self.subject_index(name)?;
+        self.check_unused_name(new_name)?;
+        let mut log = ChangeLog::default();
+        for i in 0..self.subject_map.len() {
+            let old = self.subject_map[i].clone();
+            let mut new = old.clone();
+            if i == index {
+                new.set_name(new_name.to_string());
+            }
+            if old.parent() == Some(name) {
+                new.set_parent(Some(new_name.to_string()));
+            }
+            if new != old {
+                self.record(Change::ReplaceSubjectDomain { index: i, old, new }, &mut log);
+            }
+        }
+        let rename = |n: &str| vec![if n == name { new_name } else { n }.to_string()];
+        self.rewrite_privileges(|p| {
+            let mut p = rewrite_subject_refs(p, &rename);
+            if p.principal.subject == name {
+                p.principal.subject = new_name.to_string();
+            }
+            p
+        }, &mut log);
+        Ok(log)
+    }
+
+    /*
+     * Adds domains to one field of a privilege. Read/write domains join its
+     * object without a context, which is created if needed. A field that
+     * grants `all` already includes them and is left unchanged.
+     */
+    pub fn grant(&mut self, index: usize, field: PrivField, domains: &[&str]) -> Result<ChangeLog, String> {
+        self.check_privilege_index(index)?;
+        for domain in domains {
+            match field {
+                PrivField::CanCall | PrivField::CanReturn => self.subject_index(domain)?,
+                PrivField::CanRead | PrivField::CanWrite => self.object_index(domain)?,
+            };
+        }
+        let mut new = self.privileges[index].clone();
+        if let Some(CallRetPrivField::List(names)) = call_ret_field(&mut new, field) {
+            for domain in domains {
+                if !names.iter().any(|n| n == domain) {
+                    names.push(domain.to_string());
+                }
+            }
+        }
+        if let Some(RWPrivField::List(objects)) = rw_field(&mut new, field) {
+            let mut missing: Vec<String> = vec![];
+            for domain in domains {
+                if !objects.iter().any(|o| o.objects().iter().any(|n| n == domain)) && !missing.iter().any(|n| n == domain) {
+                    missing.push(domain.to_string());
+                }
+            }
+            match objects.iter_mut().find(|o| o.object_context() == &ContextField::All) {
+                Some(object) => {
+                    let mut names = object.objects().clone();
+                    names.extend(missing);
+                    object.set_objects(names);
+                }
+                None if !missing.is_empty() => objects.push(Object::new(missing)),
+                None => (),
+            }
+        }
+        Ok(self.replace_privilege(index, new))
+    }
+
+    /// Removes domains from one field of a privilege; a field granting `all` cannot be narrowed this way.
+    pub fn revoke(&mut self, index: usize, field: PrivField, domains: &[&str]) -> Result<ChangeLog, String> {
+        self.check_privilege_index(index)?;
+        if self.privileges[index].grants_all(field) {
+            return Err(format!("cannot revoke from {} of privileges[{}]: it grants all", field, index));
+        }
+        let revoke = |n: &str| if domains.contains(&n) { vec![] } else { vec![n.to_string()] };
+        let mut new = self.privileges[index].clone();
+        if let Some(CallRetPrivField::List(names)) = call_ret_field(&mut new, field) {
+            *names = rewrite_names(names, &revoke);
+        }
+        if let Some(RWPrivField::List(objects)) = rw_field(&mut new, field) {
+            objects.retain_mut(|object| {
+                let names = rewrite_names(object.objects(), &revoke);
+                let keep = object.objects().is_empty() || !names.is_empty();
+                object.set_objects(names);
+                keep
+            });
+        }
+        Ok(self.replace_privilege(index, new))
+    }
+
+    /// Replaces a privilege by its edited copy, provided every reference of the result is defined.
+    pub fn edit_privilege(&mut self, index: usize, edit: impl FnOnce(&mut Privilege)) -> Result<ChangeLog, String> {
+        self.check_privilege_index(index)?;
+        let mut new = self.privileges[index].clone();
+        edit(&mut new);
+        self.check_references(&new)?;
+        Ok(self.replace_privilege(index, new))
+    }
+
+    /*
+     * Moves the objects matching `split_off` into a new domain placed right
+     * after the original. Every privilege referencing the original also gets
+     * the new domain, so no access is lost.
+     */
+    pub fn split_object_domain(&mut self, name: &str, new_name: &str, split_off: impl Fn(&ObjectID) -> bool)
+                               -> Result<ChangeLog, String> {
+        let index = self.object_index(name)?;
+        self.check_unused_name(new_name)?;
+        let old = self.object_map[index].clone();
+        let (moved, kept): (Vec<ObjectID>, Vec<ObjectID>) = old.objects().iter().cloned().partition(|o| split_off(o));
+        if moved.is_empty() {
+            return Err(format!("no object of object domain '{}' is split off", name));
+        }
+        let mut log = ChangeLog::default();
+        let mut new = old.clone();
+        new.set_objects(kept);
+        self.record(Change::ReplaceObjectDomain { index, old, new }, &mut log);
+        let domain = ObjectDomain::new(new_name.to_string(), moved);
+        self.record(Change::InsertObjectDomain { index: index + 1, domain }, &mut log);
+        let split = |n: &str| if n == name { vec![n.to_string(), new_name.to_string()] } else { vec![n.to_string()] };
+        self.rewrite_privileges(|p| rewrite_object_refs(p, &split), &mut log);
+        Ok(log)
+    }
+
+    /// Moves the objects of `from` into `into`, removes `from` and points its references at `into`.
+    pub fn merge_object_domains(&mut self, into: &str, from: &str) -> Result<ChangeLog, String> {
+        let into_index = self.object_index(into)?;
+        let from_index = self.object_index(from)?;
+        if into_index == from_index {
+            return Err(format!("cannot merge object domain '{}' into itself", into));
+        }
+        let mut log = ChangeLog::default();
+        let old = self.object_map[into_index].clone();
+        let mut new = old.clone();
+        let mut objects = old.objects().clone();
+        objects.extend(self.object_map[from_index].objects().iter().cloned());
+        new.set_objects(objects);
+        self.record(Change::ReplaceObjectDomain { index: into_index, old, new }, &mut log);
+        let rename = |n: &str| vec![if n == from { into } else { n }.to_string()];
+        self.rewrite_privileges(|p| rewrite_object_refs(p, &rename), &mut log);
+        let domain = self.object_map[from_index].clone();
+        self.record(Change::RemoveObjectDomain { index: from_index, domain }, &mut log);
+        Ok(log)
+    }
+
+    /// Reverts the changes of a log, newest first, returning the log that redoes them.
+    pub fn undo(&mut self, log: &ChangeLog) -> ChangeLog {
+        let mut redo = ChangeLog::default();
+        for change in log.changes.iter().rev() {
+            self.record(change.inverse(), &mut redo);
+        }
+        redo
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpm_priv_map::AllocType;
+    use crate::parallel::Parallelism;
+    use crate::semantic::validate_semantics;
+
+    const POLICY: &str = "
+object_map:
+  - name: od1
+    objects: ['HEAP|net/skb.c|10|', 'HEAP|net/skb.c|20|']
+  - {name: od2, objects: []}
+subject_map:
+  - {name: net, subjects: [xmit]}
+  - {name: net_tx, subjects: [sendmsg], parent: net}
+  - {name: fs, subjects: [iput]}
+privileges:
+  - principal: {subject: net, execution_context: {call_context: [fs]}}
+    can_call: [fs]
+    can_return: [fs]
+    can_read: [{objects: [od1, od2]}]
+    can_write: [{objects: [od2]}]
+  - principal: {subject: fs}
+    can_call: [net]
+    can_return: all
+    can_read: [{objects: [od1]}]
+    can_write: []
+";
+
+    fn policy() -> CPMPrivMap {
+        serde_yaml::from_str(POLICY).unwrap()
+    }
+
+    #[test]
+    fn test_edits_and_undo() {
+        let original = policy();
+        let mut priv_map = original.clone();
+        let mut log = priv_map.rename_subject_domain("fs", "vfs").unwrap();
+        log.append(priv_map.split_object_domain("od1", "od1_20", |o| o.lineno() == "20").unwrap());
+        log.append(priv_map.merge_object_domains("od2", "od1").unwrap());
+        log.append(priv_map.remove_subject_domain("net").unwrap());
+        assert!(validate_semantics(&priv_map, Parallelism::Sequential).is_empty());
+
+        let expected: CPMPrivMap = serde_yaml::from_str("
+object_map:
+  - name: od1_20
+    objects: ['HEAP|net/skb.c|20|']
+  - name: od2
+    objects: ['HEAP|net/skb.c|10|']
+subject_map:
+  - {name: net_tx, subjects: [sendmsg]}
+  - {name: vfs, subjects: [iput]}
+privileges:
+  - principal: {subject: vfs}
+    can_call: []
+    can_return: all
+    can_read: [{objects: [od2, od1_20]}]
+    can_write: []
+").unwrap();
+        assert_eq!(priv_map, expected);
+        assert_eq!(priv_map.object_map()[0].objects()[0].alloc_type(), &AllocType::Heap);
+
+        let redo = priv_map.undo(&log);
+        assert_eq!(priv_map, original);
+        priv_map.undo(&redo);
+        assert_eq!(priv_map, expected);
+    }
+
+    #[test]
+    fn test_grant_revoke() {
+        let mut priv_map = policy();
+        let log = priv_map.grant(1, PrivField::CanWrite, &["od2", "od1", "od2"]).unwrap();
+        assert_eq!(priv_map.privileges()[1].can_write(), &RWPrivField::List(vec![Object::new(vec!["od2".to_string(), "od1".to_string()])]));
+        assert!(priv_map.grant(1, PrivField::CanReturn, &["net"]).unwrap().is_empty());
+        assert!(priv_map.grant(1, PrivField::CanCall, &["net"]).unwrap().is_empty());
+
+        priv_map.revoke(0, PrivField::CanRead, &["od1", "od2"]).unwrap();
+        assert_eq!(priv_map.privileges()[0].can_read(), &RWPrivField::List(vec![]));
+        priv_map.edit_privilege(0, |p| p.can_call = CallRetPrivField::All).unwrap();
+        assert!(priv_map.privileges()[0].grants_all(PrivField::CanCall));
+        priv_map.undo(&log);
+        assert_eq!(priv_map.privileges()[1].can_write(), &RWPrivField::List(vec![]));
+
+        assert_eq!(priv_map.grant(0, PrivField::CanCall, &["ghost"]).unwrap_err(), "undefined subject domain 'ghost'");
+        assert_eq!(priv_map.revoke(1, PrivField::CanReturn, &["net"]).unwrap_err(), "cannot revoke from can_return of privileges[1]: it grants all");
+        assert_eq!(priv_map.edit_privilege(0, |p| assert!(p.can_read.add_object(Object::new(vec!["od9".to_string()])))).unwrap_err(), "undefined object domain 'od9'");
+        assert_eq!(priv_map.rename_object_domain("od1", "fs").unwrap_err(), "domain 'fs' is already defined");
+        assert!(priv_map.grant(9, PrivField::CanRead, &[]).is_err());
+    }
+}
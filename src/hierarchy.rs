@@ -0,0 +1,257 @@
+// SPDX-License-Identifier: MIT
+//
+// MIT License
+//
+// © 2024 Nathan Dautenhahn & Serenitix LLC
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::cpm_priv_map::{CPMPrivMap, LoadMode, Privilege, SubjectDomain};
+use crate::patterns::{load_expanded, UnmatchedPattern};
+
+/*
+ * Subject domain hierarchy.
+ *
+ * A subject domain may name a `parent`:
+ *
+ *   subject_map:
+ *     - name: net
+ *       subjects: [dev_queue_xmit]
+ *     - name: net_tx
+ *       subjects: [tcp_sendmsg]
+ *       parent: net
+ *
+ * A child holds every privilege whose principal is one of its ancestors, as
+ * if the privilege named the child itself. Only principals are inherited: a
+ * grant to call or return to the parent does not extend to its children.
+ * Parents must be defined subject domains and the relation must be acyclic.
+ *
+ * `flatten_hierarchy` turns a hierarchical map into plain v1.3 by copying the
+ * inherited privileges to each child and dropping the `parent` fields, so the
+ * other analyses only ever see flat maps.
+ */
+
+/// A problem with the parent relation, formatted only when reported.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HierarchyError {
+    UndefinedParent { subject: String, parent: String },
+    // the members of the cycle in parent order, starting and ending with the same domain
+    Cycle(Vec<String>),
+}
+
+impl fmt::Display for HierarchyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HierarchyError::UndefinedParent { subject, parent } =>
+                write!(f, "subject domain '{}' has undefined parent '{}'", subject, parent),
+            HierarchyError::Cycle(members) => write!(f, "cycle in subject domain hierarchy: {}", members.join(" -> ")),
+        }
+    }
+}
+
+pub fn has_hierarchy(priv_map: &CPMPrivMap) -> bool {
+    priv_map.subject_map().iter().any(|sd| sd.parent().is_some())
+}
+
+fn subject_domains(priv_map: &CPMPrivMap) -> HashMap<&str, &SubjectDomain> {
+    let mut domains = HashMap::new();
+    for sd in priv_map.subject_map() {
+        domains.entry(sd.name()).or_insert(sd);
+    }
+    domains
+}
+
+// the parent chain of `subject`, nearest first, or the cycle it runs into
+fn parent_chain<'a>(domains: &HashMap<&str, &'a SubjectDomain>, subject: &'a str)
+    -> Result<Vec<&'a str>, HierarchyError>
+{
+    let mut chain: Vec<&str> = vec![subject];
+    let mut current = subject;
+    while let Some(parent) = domains.get(current).and_then(|sd| sd.parent()) {
+        if let Some(start) = chain.iter().position(|s| *s == parent) {
+            let mut cycle: Vec<String> = chain[start..].iter().map(|s| s.to_string()).collect();
+            cycle.push(parent.to_string());
+            return Err(HierarchyError::Cycle(cycle));
+        }
+        if !domains.contains_key(parent) {
+            return Err(HierarchyError::UndefinedParent { subject: current.to_string(), parent: parent.to_string() });
+        }
+        chain.push(parent);
+        current = parent;
+    }
+    chain.remove(0);
+    Ok(chain)
+}
+
+/// Ancestors of a subject domain, nearest first.
+pub fn ancestors<'a>(priv_map: &'a CPMPrivMap, subject: &'a str) -> Result<Vec<&'a str>, HierarchyError> {
+    parent_chain(&subject_domains(priv_map), subject)
+}
+
+/// Every undefined parent and cycle, once per cycle, in subject map order.
+pub fn hierarchy_errors(priv_map: &CPMPrivMap) -> Vec<HierarchyError> {
+    let domains = subject_domains(priv_map);
+    let mut errors = vec![];
+    let mut cycles: Vec<Vec<String>> = vec![];
+    for sd in priv_map.subject_map() {
+        let Some(parent) = sd.parent() else { continue };
+        if !domains.contains_key(parent) {
+            errors.push(HierarchyError::UndefinedParent { subject: sd.name().to_string(), parent: parent.to_string() });
+            continue;
+        }
+        // report a cycle from the first of its members only; a chain leading
+        // into a cycle or to an undefined parent is reported from there
+        if let Err(HierarchyError::Cycle(cycle)) = parent_chain(&domains, sd.name()) {
+            let mut members = cycle[..cycle.len() - 1].to_vec();
+            members.sort();
+            if members.iter().any(|m| m == sd.name()) && !cycles.contains(&members) {
+                cycles.push(members);
+                errors.push(HierarchyError::Cycle(cycle));
+            }
+        }
+    }
+    errors
+}
+
+/// Privileges a subject domain holds directly or through its ancestors, with their indices, own first.
+pub fn inherited_privileges<'a>(priv_map: &'a CPMPrivMap, subject: &'a str)
+    -> Result<Vec<(usize, &'a Privilege)>, HierarchyError>
+{
+    let mut principals = vec![subject];
+    principals.extend(ancestors(priv_map, subject)?);
+    let mut privileges = vec![];
+    for principal in principals {
+        privileges.extend(priv_map.privileges().iter().enumerate()
+            .filter(|(_, p)| p.principal().subject() == principal));
+    }
+    Ok(privileges)
+}
+
+/// The privileges of a subject domain with inheritance applied, each naming the domain as principal.
+pub fn effective_privileges(priv_map: &CPMPrivMap, subject: &str) -> Result<Vec<Privilege>, HierarchyError> {
+    let mut privileges: Vec<Privilege> = vec![];
+    for (_, privilege) in inherited_privileges(priv_map, subject)? {
+        let mut privilege = privilege.clone();
+        privilege.principal.subject = subject.to_string();
+        if !privileges.contains(&privilege) {
+            privileges.push(privilege);
+        }
+    }
+    Ok(privileges)
+}
+
+/*
+ * Plain v1.3 equivalent of a hierarchical map: each child gets copies of
+ * its ancestors' privileges (skipping ones it already has), appended after
+ * the original privileges in subject map order, and `parent` is dropped.
+ */
+pub fn flatten_hierarchy(priv_map: &CPMPrivMap) -> Result<CPMPrivMap, HierarchyError> {
+    if let Some(error) = hierarchy_errors(priv_map).into_iter().next() {
+        return Err(error);
+    }
+    let mut flat = priv_map.clone();
+    for sd in flat.subject_map.iter_mut() {
+        sd.set_parent(None);
+    }
+    let mut present: HashSet<Privilege> = flat.privileges().iter().cloned().collect();
+    for sd in priv_map.subject_map().iter().filter(|sd| sd.parent().is_some()) {
+        for privilege in effective_privileges(priv_map, sd.name())? {
+            if present.insert(privilege.clone()) {
+                flat.add_privilege(privilege);
+            }
+        }
+    }
+    Ok(flat)
+}
+
+/// Loads a policy file for analysis: patterns expanded and the hierarchy flattened,
+/// with the patterns that matched nothing.
+pub fn load_flattened(file_path: &str, mode: LoadMode) -> Result<(CPMPrivMap, Vec<UnmatchedPattern>), String> {
+    let (priv_map, unmatched) = load_expanded(file_path, mode)?;
+    let flat = flatten_hierarchy(&priv_map).map_err(|e| format!("{}: {}", file_path, e))?;
+    Ok((flat, unmatched))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpm_priv_map::CallRetPrivField;
+
+    const POLICY: &str = "
+object_map: []
+subject_map:
+  - {name: net, subjects: [xmit]}
+  - {name: net_tx, subjects: [sendmsg], parent: net}
+  - {name: tcp_tx, subjects: [tcp_sendmsg], parent: net_tx}
+  - {name: fs, subjects: [iput]}
+privileges:
+  - principal: {subject: net}
+    can_call: [fs]
+    can_return: []
+    can_read: []
+    can_write: []
+  - principal: {subject: tcp_tx}
+    can_call: []
+    can_return: [net]
+    can_read: []
+    can_write: []
+";
+
+    #[test]
+    fn test_inheritance() {
+        let priv_map: CPMPrivMap = serde_yaml::from_str(POLICY).unwrap();
+        assert!(has_hierarchy(&priv_map));
+        assert!(hierarchy_errors(&priv_map).is_empty());
+        assert_eq!(ancestors(&priv_map, "tcp_tx").unwrap(), vec!["net_tx", "net"]);
+
+        let inherited: Vec<usize> = inherited_privileges(&priv_map, "tcp_tx").unwrap().iter().map(|(i, _)| *i).collect();
+        assert_eq!(inherited, vec![1, 0]);
+        let effective = effective_privileges(&priv_map, "net_tx").unwrap();
+        assert_eq!(effective.len(), 1);
+        assert_eq!(effective[0].principal().subject(), "net_tx");
+        assert_eq!(effective[0].can_call(), &CallRetPrivField::List(vec!["fs".to_string()]));
+
+        let flat = flatten_hierarchy(&priv_map).unwrap();
+        assert!(!has_hierarchy(&flat));
+        let principals: Vec<&str> = flat.privileges().iter().map(|p| p.principal().subject().as_str()).collect();
+        assert_eq!(principals, vec!["net", "tcp_tx", "net_tx", "tcp_tx"]);
+        assert!(!serde_yaml::to_string(&flat).unwrap().contains("parent"));
+        // flattening is idempotent
+        assert_eq!(flatten_hierarchy(&flat).unwrap(), flat);
+    }
+
+    #[test]
+    fn test_hierarchy_errors() {
+        let priv_map: CPMPrivMap = serde_yaml::from_str("
+object_map: []
+subject_map:
+  - {name: a, subjects: [], parent: c}
+  - {name: b, subjects: [], parent: a}
+  - {name: c, subjects: [], parent: b}
+  - {name: d, subjects: [], parent: a}
+  - {name: e, subjects: [], parent: ghost}
+  - {name: f, subjects: [], parent: f}
+privileges: []
+").unwrap();
+        let errors: Vec<String> = hierarchy_errors(&priv_map).iter().map(|e| e.to_string()).collect();
+        assert_eq!(errors, vec![
+            "cycle in subject domain hierarchy: a -> c -> b -> a",
+            "subject domain 'e' has undefined parent 'ghost'",
+            "cycle in subject domain hierarchy: f -> f",
+        ]);
+        let cycle = ["a", "c", "b", "a"].map(String::from).to_vec();
+        assert_eq!(ancestors(&priv_map, "d").unwrap_err(), HierarchyError::Cycle(cycle));
+        assert!(flatten_hierarchy(&priv_map).is_err());
+    }
+}
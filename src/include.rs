@@ -0,0 +1,500 @@
+// SPDX-License-Identifier: MIT
+//
+// MIT License
+//
+// © 2024 Nathan Dautenhahn & Serenitix LLC
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use crate::cpm_priv_map::{CPMPrivMap, LoadMode, ObjectDomain, Privilege, SubjectDomain};
+use crate::edit::{rewrite_object_refs, rewrite_subject_refs};
+use crate::patterns::DomainRef;
+use crate::semantic::SemanticError;
+use crate::source_map::Section;
+
+/*
+ * Policies assembled from several files.
+ *
+ * A policy file may start with an `include:` list of other files, given by
+ * paths relative to the including file:
+ *
+ *   include:
+ *     - common/kernel.yaml
+ *     - {path: net/policy.yaml, namespace: net}
+ *
+ * Included files (fragments) have the same format but every section is
+ * optional, and they may include further fragments. Their entries come
+ * before the including file's own, in include order. With a namespace,
+ * every domain the fragment defines is renamed to `<namespace>::<name>`,
+ * in its definition and in the fragment's own references to it; names the
+ * fragment does not define are left alone, so it can still refer to
+ * domains of the including policy. Domain patterns are not qualified and
+ * match against the assembled policy.
+ *
+ * Each file is loaded once: when several files include the same fragment,
+ * its entries come with the first include, and later ones add nothing (so
+ * a fragment shared that way should not be namespaced). Including a file
+ * that is still being loaded is an include cycle, and a domain name
+ * defined in two different files is a collision; both are load errors
+ * naming the files involved. Each entry of the assembled policy keeps its
+ * origin, so diagnostics can name the file and the entry's index within it.
+ */
+
+pub const NAMESPACE_SEPARATOR: &str = "::";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum IncludeEntry {
+    Path(String),
+    Namespaced {
+        path: String,
+        #[serde(default)]
+        namespace: Option<String>,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+struct Fragment {
+    #[serde(default)]
+    include: Vec<IncludeEntry>,
+    #[serde(default)]
+    object_map: Vec<ObjectDomain>,
+    #[serde(default)]
+    subject_map: Vec<SubjectDomain>,
+    #[serde(default)]
+    privileges: Vec<Privilege>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceFile {
+    // as reached from the top-level file, e.g. `policy/net/tcp.yaml`
+    pub path: String,
+    pub text: String,
+}
+
+/// Where an entry of an assembled policy comes from: a file and the entry's index in its section there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Origin {
+    pub file: usize,
+    pub index: usize,
+}
+
+// the top-level `include` key alone; every other key is skipped without being built
+#[derive(Deserialize)]
+struct Directives {
+    #[serde(default)]
+    include: Option<serde::de::IgnoredAny>,
+}
+
+// the key of a top-level block mapping entry, unquoted
+fn top_level_key(line: &str) -> Option<&str> {
+    if line.starts_with(|c: char| c.is_whitespace() || "#-".contains(c)) {
+        return None;
+    }
+    let (key, _) = line.split_once(':')?;
+    Some(key.trim().trim_matches(|c| c == '"' || c == '\''))
+}
+
+// a flow-style document has no key lines to scan, so it is parsed for its directives
+fn flow_includes(yaml: &str) -> bool {
+    serde_yaml::from_str::<Directives>(yaml).is_ok_and(|d| d.include.is_some())
+}
+
+/// True if the policy text is a mapping with a top-level `include` key.
+///
+/// Block-style text is scanned line by line without being parsed.
+pub fn has_includes(yaml: &str) -> bool {
+    for line in yaml.lines() {
+        if line.starts_with('{') {
+            return flow_includes(yaml);
+        }
+        if top_level_key(line) == Some("include") {
+            return true;
+        }
+    }
+    false
+}
+
+/// True if the policy file has a top-level `include` key.
+///
+/// Block-style files are scanned line by line, so a large policy is never
+/// held in memory; a flow-style document is parsed whole.
+pub fn declares_includes(file_path: &str) -> Result<bool, String> {
+    let read_error = |e: std::io::Error| format!("Failed to read YAML file: {}", e);
+    let file = fs::File::open(file_path).map_err(read_error)?;
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(read_error)?;
+        if line.starts_with('{') {
+            return Ok(flow_includes(&fs::read_to_string(file_path).map_err(read_error)?));
+        }
+        if top_level_key(&line) == Some("include") {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Loads a policy file, assembling it from its includes if it has any.
+pub fn load_policy(file_path: &str, mode: LoadMode) -> Result<CPMPrivMap, String> {
+    Ok(IncludedPolicy::load(file_path, mode)?.priv_map)
+}
+
+#[derive(Default)]
+struct Part {
+    object_map: Vec<(ObjectDomain, Origin)>,
+    subject_map: Vec<(SubjectDomain, Origin)>,
+    privileges: Vec<(Privilege, Origin)>,
+}
+
+impl Part {
+    fn append(&mut self, other: Part) {
+        self.object_map.extend(other.object_map);
+        self.subject_map.extend(other.subject_map);
+        self.privileges.extend(other.privileges);
+    }
+
+    // prefixes the domains defined in this part, and the part's references to them, with the namespace
+    fn qualify(&mut self, namespace: &str) {
+        let subjects: HashSet<String> = self.subject_map.iter().map(|(sd, _)| sd.name().to_string()).collect();
+        let objects: HashSet<String> = self.object_map.iter().map(|(od, _)| od.name().to_string()).collect();
+        let qualify = |defined: &HashSet<String>, name: &str| match defined.contains(name) {
+            true => format!("{}{}{}", namespace, NAMESPACE_SEPARATOR, name),
+            false => name.to_string(),
+        };
+
+        for (domain, _) in self.subject_map.iter_mut() {
+            domain.set_name(qualify(&subjects, domain.name()));
+            let parent = domain.parent().map(|p| qualify(&subjects, p));
+            domain.set_parent(parent);
+        }
+        for (domain, _) in self.object_map.iter_mut() {
+            domain.set_name(qualify(&objects, domain.name()));
+        }
+        let subject_refs = |name: &str| vec![qualify(&subjects, name)];
+        let object_refs = |name: &str| vec![qualify(&objects, name)];
+        for (privilege, _) in self.privileges.iter_mut() {
+            let mut qualified = rewrite_object_refs(&rewrite_subject_refs(privilege, &subject_refs), &object_refs);
+            qualified.principal.subject = qualify(&subjects, &qualified.principal.subject);
+            *privilege = qualified;
+        }
+    }
+}
+
+struct Loader {
+    mode: LoadMode,
+    files: Vec<SourceFile>,
+    // files being loaded, outermost first, for cycle detection
+    stack: Vec<(PathBuf, String)>,
+    // every file loaded so far, which later includes skip
+    loaded: HashSet<PathBuf>,
+}
+
+impl Loader {
+    fn new(mode: LoadMode) -> Self {
+        Loader { mode, files: vec![], stack: vec![], loaded: HashSet::new() }
+    }
+
+    fn load(&mut self, path: &str, text: Option<&str>) -> Result<Part, String> {
+        let text = match text {
+            Some(text) => text.to_string(),
+            None => fs::read_to_string(path).map_err(|e| format!("{}: Failed to read YAML file: {}", path, e))?,
+        };
+        let canonical = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+        if let Some(start) = self.stack.iter().position(|(c, _)| *c == canonical) {
+            let mut cycle: Vec<&str> = self.stack[start..].iter().map(|(_, p)| p.as_str()).collect();
+            cycle.push(path);
+            return Err(format!("include cycle: {}", cycle.join(" -> ")));
+        }
+        if !self.loaded.insert(canonical.clone()) {
+            return Ok(Part::default());
+        }
+        let fragment: Fragment = serde_yaml::from_str(&text)
+            .map_err(|e| format!("{}: Failed to parse privilege map: {}", path, e))?;
+
+        let file = self.files.len();
+        self.files.push(SourceFile { path: path.to_string(), text });
+        self.stack.push((canonical, path.to_string()));
+        let mut part = Part::default();
+        for entry in fragment.include {
+            let (relative, namespace) = match entry {
+                IncludeEntry::Path(path) => (path, None),
+                IncludeEntry::Namespaced { path, namespace } => (path, namespace),
+            };
+            if Path::new(&relative).is_absolute() {
+                return Err(format!("{}: include '{}' must be a relative path", path, relative));
+            }
+            let child = Path::new(path).parent().unwrap_or(Path::new("")).join(&relative);
+            let mut child_part = self.load(&child.to_string_lossy(), None)?;
+            if let Some(namespace) = namespace {
+                // `glob` or `re` would turn the qualified names into patterns
+                let qualified = format!("{}{}", namespace, NAMESPACE_SEPARATOR);
+                if namespace.is_empty() || namespace.contains(char::is_whitespace) || namespace.contains(':')
+                    || DomainRef::is_pattern(&qualified) {
+                    return Err(format!("{}: invalid namespace '{}' for include '{}'", path, namespace, relative));
+                }
+                child_part.qualify(&namespace);
+            }
+            part.append(child_part);
+        }
+        self.stack.pop();
+
+        let origin = |index| Origin { file, index };
+        part.object_map.extend(fragment.object_map.into_iter().enumerate().map(|(i, od)| (od, origin(i))));
+        part.subject_map.extend(fragment.subject_map.into_iter().enumerate().map(|(i, sd)| (sd, origin(i))));
+        for (i, mut privilege) in fragment.privileges.into_iter().enumerate() {
+            if self.mode == LoadMode::DenyByDefault {
+                privilege.deny_implicit_grants();
+            }
+            part.privileges.push((privilege, origin(i)));
+        }
+        Ok(part)
+    }
+}
+
+/// A policy with every include resolved, and the origin of each of its entries.
+#[derive(Debug, Clone)]
+pub struct IncludedPolicy {
+    pub priv_map: CPMPrivMap,
+    // the top-level file first
+    files: Vec<SourceFile>,
+    object_origins: Vec<Origin>,
+    subject_origins: Vec<Origin>,
+    privilege_origins: Vec<Origin>,
+}
+
+impl IncludedPolicy {
+    pub fn load(file_path: &str, mode: LoadMode) -> Result<Self, String> {
+        let yaml = fs::read_to_string(file_path)
+            .map_err(|e| format!("Failed to read YAML file: {}", e))?;
+        Self::from_yaml_str(&yaml, file_path, mode)
+    }
+
+    /// Assembles the policy with the given text for `file_path`; included files are read from disk.
+    pub fn from_yaml_str(yaml: &str, file_path: &str, mode: LoadMode) -> Result<Self, String> {
+        if !has_includes(yaml) {
+            let priv_map = CPMPrivMap::from_yaml_str(yaml, mode)?;
+            let identity = |n: usize| (0..n).map(|index| Origin { file: 0, index }).collect();
+            return Ok(IncludedPolicy {
+                object_origins: identity(priv_map.object_map.len()),
+                subject_origins: identity(priv_map.subject_map.len()),
+                privilege_origins: identity(priv_map.privileges.len()),
+                priv_map,
+                files: vec![SourceFile { path: file_path.to_string(), text: yaml.to_string() }],
+            });
+        }
+
+        let mut loader = Loader::new(mode);
+        let part = loader.load(file_path, Some(yaml))?;
+        let (object_map, object_origins) = part.object_map.into_iter().unzip();
+        let (subject_map, subject_origins) = part.subject_map.into_iter().unzip();
+        let (privileges, privilege_origins) = part.privileges.into_iter().unzip();
+        let policy = IncludedPolicy {
+            priv_map: CPMPrivMap { object_map, subject_map, privileges },
+            files: loader.files,
+            object_origins,
+            subject_origins,
+            privilege_origins,
+        };
+        policy.check_collisions()?;
+        Ok(policy)
+    }
+
+    pub fn files(&self) -> &[SourceFile] {
+        &self.files
+    }
+
+    pub fn origin(&self, section: Section, index: usize) -> Option<Origin> {
+        match section {
+            Section::ObjectMap => self.object_origins.get(index).copied(),
+            Section::SubjectMap => self.subject_origins.get(index).copied(),
+            Section::Privileges => self.privilege_origins.get(index).copied(),
+        }
+    }
+
+    /// The entry as found in its file, e.g. `net/policy.yaml: privileges[2]`.
+    pub fn describe(&self, section: Section, index: usize) -> String {
+        match self.origin(section, index) {
+            Some(origin) => format!("{}: {}[{}]", self.files[origin.file].path, section.key(), origin.index),
+            None => format!("{}[{}]", section.key(), index),
+        }
+    }
+
+    /// A semantic error of the assembled policy, naming the file and index of the privilege it is in.
+    pub fn locate_error(&self, error: &SemanticError) -> String {
+        match error.privilege {
+            Some(index) => format!("{}: {}", self.describe(Section::Privileges, index), error.message),
+            None => error.to_string(),
+        }
+    }
+
+    // a name defined in two different files cannot be told apart after assembly
+    fn check_collisions(&self) -> Result<(), String> {
+        let mut errors = vec![];
+        let names = [
+            ("object domain", Section::ObjectMap,
+             self.priv_map.object_map.iter().map(|od| od.name()).collect::<Vec<_>>()),
+            ("subject domain", Section::SubjectMap,
+             self.priv_map.subject_map.iter().map(|sd| sd.name()).collect::<Vec<_>>()),
+        ];
+        for (kind, section, names) in names {
+            let mut first: HashMap<&str, usize> = HashMap::new();
+            for (index, name) in names.into_iter().enumerate() {
+                let Some(&previous) = first.get(name) else {
+                    first.insert(name, index);
+                    continue;
+                };
+                let file = |i| self.origin(section, i).map(|o| o.file);
+                if file(previous) != file(index) {
+                    errors.push(format!("{} '{}' is defined in both {} and {}",
+                        kind, name, self.describe(section, previous), self.describe(section, index)));
+                }
+            }
+        }
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors.join("\n")),
+        }
+    }
+}
+
+/// The files a policy includes, directly or not, as far as they can be loaded.
+pub fn included_files(file_path: &str) -> Vec<String> {
+    let Ok(yaml) = fs::read_to_string(file_path) else {
+        return vec![];
+    };
+    if !has_includes(&yaml) {
+        return vec![];
+    }
+    let mut loader = Loader::new(LoadMode::AllowByDefault);
+    let _ = loader.load(file_path, Some(&yaml));
+    loader.files.into_iter().skip(1).map(|f| f.path).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOP: &str = "\
+include:
+  - kernel.yaml
+  - {path: net/policy.yaml, namespace: net}
+privileges:
+  - principal: {subject: core}
+    can_call: [net::tcp]
+";
+
+    const KERNEL: &str = "\
+object_map:
+  - {name: od_kernel, objects: [k]}
+subject_map:
+  - {name: core, subjects: [start_kernel]}
+";
+
+    // `tcp` and `skb` are the fragment's own; `core` and `od_kernel` come from the including policy
+    const NET: &str = "\
+object_map:
+  - {name: skb, objects: [s]}
+subject_map:
+  - {name: tcp, subjects: [tcp_sendmsg], parent: core}
+privileges:
+  - principal: {subject: tcp}
+    can_call: [core]
+    can_read: [{objects: [skb, od_kernel]}]
+";
+
+    fn write_tree(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("cpm_if_include_{}_{}", name, std::process::id()));
+        for (path, text) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn test_load_with_namespaces() {
+        let root = write_tree("ok", &[("top.yaml", TOP), ("kernel.yaml", KERNEL), ("net/policy.yaml", NET)]);
+        let top = root.join("top.yaml").to_string_lossy().to_string();
+        let policy = IncludedPolicy::load(&top, LoadMode::DenyByDefault).unwrap();
+        let priv_map = &policy.priv_map;
+
+        let objects: Vec<&str> = priv_map.object_map.iter().map(|od| od.name()).collect();
+        assert_eq!(objects, vec!["od_kernel", "net::skb"]);
+        assert_eq!(priv_map.subject_map[1].name(), "net::tcp");
+        assert_eq!(priv_map.subject_map[1].parent(), Some("core"));
+
+        let tcp = &priv_map.privileges[0];
+        assert_eq!(tcp.principal().subject(), "net::tcp");
+        let yaml = serde_yaml::to_string(tcp).unwrap();
+        assert!(yaml.contains("- core") && yaml.contains("- net::skb") && yaml.contains("- od_kernel"));
+
+        assert_eq!(policy.files().len(), 3);
+        assert_eq!(policy.describe(Section::Privileges, 1), format!("{}: privileges[0]", top));
+        assert!(policy.describe(Section::ObjectMap, 1).ends_with("net/policy.yaml: object_map[0]"));
+        assert_eq!(included_files(&top).len(), 2);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_diamond_includes_load_once() {
+        let root = write_tree("diamond", &[
+            ("top.yaml", "# shared kernel domains come through both subsystems\ninclude: [a.yaml, b.yaml]\n"),
+            ("a.yaml", "include: [kernel.yaml]\nsubject_map:\n  - {name: a, subjects: [fa], parent: core}\n"),
+            ("b.yaml", "include: [kernel.yaml]\nsubject_map:\n  - {name: b, subjects: [fb], parent: core}\n"),
+            ("kernel.yaml", KERNEL),
+        ]);
+        let top = root.join("top.yaml").to_string_lossy().to_string();
+        let policy = IncludedPolicy::load(&top, LoadMode::AllowByDefault).unwrap();
+        let subjects: Vec<&str> = policy.priv_map.subject_map.iter().map(|sd| sd.name()).collect();
+        assert_eq!(subjects, vec!["core", "a", "b"]);
+        assert_eq!(policy.files().len(), 4);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_has_includes() {
+        assert!(has_includes("# header\nobject_map: []\ninclude: [a.yaml]\n"));
+        assert!(has_includes("{\"include\": [a.yaml], object_map: []}"));
+        assert!(!has_includes("object_map:\n  - {name: od, objects: ['GLOBAL|include/linux/a.h|1|include']}\n"));
+        assert!(!has_includes("object_map: [\n"));
+    }
+
+    #[test]
+    fn test_cycles_and_collisions() {
+        let root = write_tree("cycle", &[
+            ("top.yaml", "include: [a.yaml]\n"),
+            ("a.yaml", "include: [sub/b.yaml]\n"),
+            ("sub/b.yaml", "include: [../a.yaml]\n"),
+        ]);
+        let top = root.join("top.yaml").to_string_lossy().to_string();
+        let error = IncludedPolicy::load(&top, LoadMode::AllowByDefault).unwrap_err();
+        let path = |p: &str| root.join(p).to_string_lossy().to_string();
+        assert_eq!(error, format!("include cycle: {} -> {} -> {}", path("a.yaml"), path("sub/b.yaml"), path("sub/../a.yaml")));
+        fs::remove_dir_all(root).unwrap();
+
+        let root = write_tree("collision", &[
+            ("top.yaml", "include: [kernel.yaml]\nsubject_map:\n  - {name: core, subjects: [main]}\n"),
+            ("kernel.yaml", KERNEL),
+        ]);
+        let top = root.join("top.yaml").to_string_lossy().to_string();
+        let error = IncludedPolicy::load(&top, LoadMode::AllowByDefault).unwrap_err();
+        assert_eq!(error, format!("subject domain 'core' is defined in both {}: subject_map[0] and {}: subject_map[0]",
+            root.join("kernel.yaml").to_string_lossy(), top));
+        fs::remove_dir_all(root).unwrap();
+    }
+}
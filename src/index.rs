@@ -0,0 +1,269 @@
+// SPDX-License-Identifier: MIT
+//
+// MIT License
+//
+// © 2024 Nathan Dautenhahn & Serenitix LLC
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+use std::collections::HashMap;
+
+use crate::cpm_priv_map::{AllocType, CPMPrivMap, ObjectDomain, ObjectID, Privilege, SubjectDomain};
+
+/*
+ * Hash indexes over a CPMPrivMap for the lookups that are linear scans on
+ * the plain vectors. The indexed map owns the CPMPrivMap and all mutation
+ * goes through it so the indexes never go stale; `into_inner` hands the map
+ * back. The `add_*` methods update the indexes in place. The edits of
+ * crate::edit remove and shift entries, so `edit` runs them on the owned map
+ * and rebuilds the indexes afterwards. When a name is defined more than
+ * once the first definition wins, matching the linear lookups on CPMPrivMap.
+ */
+
+// position of an object: (index of its domain in object_map, index in the domain)
+type ObjectPos = (usize, usize);
+
+#[derive(Debug, Default)]
+pub struct IndexedPrivMap {
+    priv_map: CPMPrivMap,
+    object_domains: HashMap<String, usize>,
+    subject_domains: HashMap<String, usize>,
+    subjects: HashMap<String, usize>,
+    objects_by_path: HashMap<String, Vec<ObjectPos>>,
+    objects_by_site: HashMap<(String, String), Vec<ObjectPos>>,
+    globals: HashMap<String, usize>,
+    privileges_by_subject: HashMap<String, Vec<usize>>,
+}
+
+impl IndexedPrivMap {
+    pub fn new(priv_map: CPMPrivMap) -> Self {
+        let mut indexed = IndexedPrivMap::default();
+        for i in 0..priv_map.object_map.len() {
+            indexed.index_object_domain(&priv_map.object_map[i], i);
+        }
+        for i in 0..priv_map.subject_map.len() {
+            indexed.index_subject_domain(&priv_map.subject_map[i], i);
+        }
+        for i in 0..priv_map.privileges.len() {
+            indexed.index_privilege(&priv_map.privileges[i], i);
+        }
+        indexed.priv_map = priv_map;
+        indexed
+    }
+
+    pub fn priv_map(&self) -> &CPMPrivMap {
+        &self.priv_map
+    }
+
+    pub fn into_inner(self) -> CPMPrivMap {
+        self.priv_map
+    }
+
+    fn index_object_domain(&mut self, domain: &ObjectDomain, d: usize) {
+        self.object_domains.entry(domain.name().to_string()).or_insert(d);
+        for (o, object) in domain.objects().iter().enumerate() {
+            self.index_object(object, (d, o));
+        }
+    }
+
+    fn index_object(&mut self, object: &ObjectID, pos: ObjectPos) {
+        self.objects_by_path.entry(object.path().to_string()).or_default().push(pos);
+        self.objects_by_site
+            .entry((object.path().to_string(), object.lineno().to_string()))
+            .or_default()
+            .push(pos);
+        if object.alloc_type() == &AllocType::Global {
+            self.globals.entry(object.name().to_string()).or_insert(pos.0);
+        }
+    }
+
+    fn index_subject_domain(&mut self, domain: &SubjectDomain, d: usize) {
+        self.subject_domains.entry(domain.name().to_string()).or_insert(d);
+        for subject in domain.subjects() {
+            self.subjects.entry(subject.to_string()).or_insert(d);
+        }
+    }
+
+    fn index_privilege(&mut self, privilege: &Privilege, p: usize) {
+        self.privileges_by_subject
+            .entry(privilege.principal().subject().to_string())
+            .or_default()
+            .push(p);
+    }
+
+    pub fn add_object_domain(&mut self, domain: ObjectDomain) {
+        let d = self.priv_map.object_map.len();
+        self.index_object_domain(&domain, d);
+        self.priv_map.add_object_domain(domain);
+    }
+
+    pub fn add_subject_domain(&mut self, domain: SubjectDomain) {
+        let d = self.priv_map.subject_map.len();
+        self.index_subject_domain(&domain, d);
+        self.priv_map.add_subject_domain(domain);
+    }
+
+    pub fn add_privilege(&mut self, privilege: Privilege) {
+        let p = self.priv_map.privileges.len();
+        self.index_privilege(&privilege, p);
+        self.priv_map.add_privilege(privilege);
+    }
+
+    /// Runs an edit (such as `CPMPrivMap::rename_object_domain`) on the map and reindexes it.
+    pub fn edit<T>(&mut self, edit: impl FnOnce(&mut CPMPrivMap) -> T) -> T {
+        let result = edit(&mut self.priv_map);
+        *self = IndexedPrivMap::new(std::mem::take(&mut self.priv_map));
+        result
+    }
+
+    /// Adds an object to the named domain; false if there is no such domain.
+    pub fn add_object(&mut self, domain_name: &str, object: ObjectID) -> bool {
+        let Some(&d) = self.object_domains.get(domain_name) else {
+            return false;
+        };
+        let o = self.priv_map.object_map[d].objects().len();
+        self.index_object(&object, (d, o));
+        self.priv_map.object_map[d].add_object(object);
+        true
+    }
+
+    /// Adds a subject to the named domain; false if there is no such domain.
+    pub fn add_subject(&mut self, domain_name: &str, subject: String) -> bool {
+        let Some(&d) = self.subject_domains.get(domain_name) else {
+            return false;
+        };
+        self.subjects.entry(subject.to_string()).or_insert(d);
+        self.priv_map.subject_map[d].add_subject(subject);
+        true
+    }
+
+    pub fn object_domain(&self, name: &str) -> Option<&ObjectDomain> {
+        self.object_domains.get(name).map(|&d| &self.priv_map.object_map[d])
+    }
+
+    pub fn subject_domain(&self, name: &str) -> Option<&SubjectDomain> {
+        self.subject_domains.get(name).map(|&d| &self.priv_map.subject_map[d])
+    }
+
+    pub fn subject_domain_for_subject(&self, subject: &str) -> Option<&SubjectDomain> {
+        self.subjects.get(subject).map(|&d| &self.priv_map.subject_map[d])
+    }
+
+    pub fn object_domain_for_global(&self, global_name: &str) -> Option<&ObjectDomain> {
+        self.globals.get(global_name).map(|&d| &self.priv_map.object_map[d])
+    }
+
+    /// Every object defined in the file, with the domain holding it.
+    pub fn objects_at_path(&self, path: &str) -> Vec<(&ObjectDomain, &ObjectID)> {
+        self.resolve(self.objects_by_path.get(path))
+    }
+
+    /// Every object defined at the given file and line, with the domain holding it.
+    pub fn objects_at_site(&self, path: &str, lineno: &str) -> Vec<(&ObjectDomain, &ObjectID)> {
+        self.resolve(self.objects_by_site.get(&(path.to_string(), lineno.to_string())))
+    }
+
+    pub fn privileges_for(&self, subject_domain: &str) -> Vec<&Privilege> {
+        self.privileges_by_subject
+            .get(subject_domain)
+            .map(|ps| ps.iter().map(|&p| &self.priv_map.privileges[p]).collect())
+            .unwrap_or_default()
+    }
+
+    fn resolve(&self, positions: Option<&Vec<ObjectPos>>) -> Vec<(&ObjectDomain, &ObjectID)> {
+        positions
+            .map(|ps| ps.iter()
+                 .map(|&(d, o)| {
+                     let domain = &self.priv_map.object_map[d];
+                     (domain, &domain.objects()[o])
+                 })
+                 .collect())
+            .unwrap_or_default()
+    }
+}
+
+impl From<CPMPrivMap> for IndexedPrivMap {
+    fn from(priv_map: CPMPrivMap) -> Self {
+        Self::new(priv_map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpm_priv_map::{CallRetPrivField, ContextField, Principal, RWPrivField};
+
+    fn object(alloc_type: AllocType, path: &str, line: &str, name: &str) -> ObjectID {
+        ObjectID::new(alloc_type, path.to_string(), line.to_string(), name.to_string())
+    }
+
+    fn privilege(subject: &str) -> Privilege {
+        Privilege::new(
+            Principal { subject: subject.to_string(), execution_context: ContextField::All },
+            CallRetPrivField::All,
+            CallRetPrivField::All,
+            RWPrivField::All,
+            RWPrivField::All,
+        )
+    }
+
+    #[test]
+    fn test_index_lookups() {
+        let mut priv_map = CPMPrivMap::new();
+        priv_map.add_object_domain(ObjectDomain::new("od1".to_string(), vec![
+            object(AllocType::Global, "net/a.c", "10", "counter"),
+            object(AllocType::Heap, "net/a.c", "20", ""),
+        ]));
+        priv_map.add_subject_domain(SubjectDomain::new("s1".to_string(), vec!["f1".to_string()]));
+        priv_map.add_privilege(privilege("s1"));
+
+        let mut indexed = IndexedPrivMap::new(priv_map);
+        assert_eq!(indexed.object_domain("od1").unwrap().name(), "od1");
+        assert!(indexed.object_domain("od2").is_none());
+        assert_eq!(indexed.subject_domain_for_subject("f1").unwrap().name(), "s1");
+        assert_eq!(indexed.object_domain_for_global("counter").unwrap().name(), "od1");
+        assert_eq!(indexed.objects_at_path("net/a.c").len(), 2);
+        assert_eq!(indexed.objects_at_site("net/a.c", "20")[0].1.alloc_type(), &AllocType::Heap);
+        assert_eq!(indexed.privileges_for("s1").len(), 1);
+
+        // the indexes follow mutations
+        indexed.add_object_domain(ObjectDomain::new("od2".to_string(), vec![
+            object(AllocType::Global, "net/b.c", "5", "jiffies"),
+        ]));
+        assert!(indexed.add_object("od1", object(AllocType::Local, "net/b.c", "7", "tmp")));
+        assert!(!indexed.add_object("missing", object(AllocType::Local, "net/b.c", "8", "x")));
+        indexed.add_subject_domain(SubjectDomain::new("s2".to_string(), vec![]));
+        assert!(indexed.add_subject("s2", "f2".to_string()));
+        indexed.add_privilege(privilege("s2"));
+        indexed.add_privilege(privilege("s1"));
+
+        assert_eq!(indexed.object_domain_for_global("jiffies").unwrap().name(), "od2");
+        let at_b: Vec<&str> = indexed.objects_at_path("net/b.c").iter().map(|(d, _)| d.name()).collect();
+        assert_eq!(at_b, vec!["od2", "od1"]);
+        assert_eq!(indexed.objects_at_site("net/b.c", "7")[0].1.name(), "tmp");
+        assert_eq!(indexed.subject_domain_for_subject("f2").unwrap().name(), "s2");
+        assert_eq!(indexed.privileges_for("s1").len(), 2);
+        assert_eq!(indexed.privileges_for("s2").len(), 1);
+
+        // and edits that shift or rename entries
+        indexed.edit(|map| map.rename_object_domain("od1", "od3")).unwrap();
+        indexed.edit(|map| map.remove_subject_domain("s1")).unwrap();
+        assert!(indexed.object_domain("od1").is_none());
+        assert_eq!(indexed.object_domain_for_global("counter").unwrap().name(), "od3");
+        assert!(indexed.subject_domain_for_subject("f1").is_none());
+        assert!(indexed.privileges_for("s1").is_empty());
+        assert_eq!(indexed.privileges_for("s2")[0].principal().subject(), "s2");
+
+        let priv_map = indexed.into_inner();
+        assert_eq!(priv_map.object_map().len(), 2);
+        assert_eq!(priv_map.object_map()[0].objects().len(), 3);
+    }
+}
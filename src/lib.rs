@@ -1,2 +1,29 @@
+pub mod algebra;
+pub mod annotate;
+pub mod assertions;
+pub mod builder;
 pub mod cpm_priv_map;
-pub mod validate_yaml;
\ No newline at end of file
+pub mod diff;
+pub mod edit;
+pub mod hierarchy;
+pub mod include;
+pub mod index;
+pub mod linked;
+pub mod lint;
+pub mod locations;
+pub mod lsp;
+pub mod metrics;
+pub mod minimize;
+pub mod parallel;
+pub mod patterns;
+pub mod permissions;
+pub mod query;
+pub mod refinement;
+pub mod semantic;
+pub mod shell;
+pub mod source_map;
+pub mod stats;
+pub mod stream;
+pub mod trace;
+pub mod validate_yaml;
+pub mod watch;
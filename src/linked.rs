@@ -0,0 +1,325 @@
+// SPDX-License-Identifier: MIT
+//
+// MIT License
+//
+// © 2024 Nathan Dautenhahn & Serenitix LLC
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+use std::collections::HashMap;
+
+use crate::cpm_priv_map::{
+    CPMPrivMap, CallContextSubField, CallRetPrivField, ContextField, Object, ObjectDomain, Privilege,
+    RWPrivField, SubjectDomain,
+};
+
+/*
+ * Resolved form of a CPMPrivMap. The YAML form names domains by string; the
+ * link step resolves every name a privilege uses against subject_map and
+ * object_map and fails if any is undefined, so a LinkedPrivMap only holds
+ * typed handles into its own arenas (the subject_map and object_map vectors
+ * of the map it owns). Handles cannot be built outside this module, which is
+ * what keeps an analysis from naming a nonexistent domain.
+ *
+ * Link after expanding patterns and flattening the hierarchy: a `glob:` or
+ * `re:` reference is just an undefined name here. As in IndexedPrivMap the
+ * first definition of a duplicated name wins. uid/gid contexts are not
+ * domain references and stay on the string form, reachable via `source`.
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SubjectDomainId(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ObjectDomainId(usize);
+
+impl SubjectDomainId {
+    /// Position of the domain in subject_map.
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+impl ObjectDomainId {
+    /// Position of the domain in object_map.
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+// a linked `[ DomainName ] | all`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Domains<Id> {
+    All,
+    List(Vec<Id>),
+}
+
+impl<Id: PartialEq> Domains<Id> {
+    pub fn contains(&self, id: Id) -> bool {
+        match self {
+            Domains::All => true,
+            Domains::List(ids) => ids.contains(&id),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkedObject {
+    pub objects: Vec<ObjectDomainId>,
+    // call context of the object context; All when there is none
+    pub call_context: Domains<SubjectDomainId>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkedPrivilege {
+    // index of the privilege in the string form
+    pub source: usize,
+    pub principal: SubjectDomainId,
+    pub call_context: Domains<SubjectDomainId>,
+    pub can_call: Domains<SubjectDomainId>,
+    pub can_return: Domains<SubjectDomainId>,
+    // None grants every object domain
+    pub can_read: Option<Vec<LinkedObject>>,
+    pub can_write: Option<Vec<LinkedObject>>,
+}
+
+impl LinkedPrivilege {
+    /// Whether the privilege reads (or writes) the object domain through any of its objects.
+    pub fn accesses(&self, id: ObjectDomainId, write: bool) -> bool {
+        let field = if write { &self.can_write } else { &self.can_read };
+        match field {
+            None => true,
+            Some(objects) => objects.iter().any(|o| o.objects.contains(&id)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct LinkedPrivMap {
+    priv_map: CPMPrivMap,
+    subjects: HashMap<String, SubjectDomainId>,
+    objects: HashMap<String, ObjectDomainId>,
+    privileges: Vec<LinkedPrivilege>,
+}
+
+struct Linker<'a> {
+    subjects: &'a HashMap<String, SubjectDomainId>,
+    objects: &'a HashMap<String, ObjectDomainId>,
+    errors: Vec<String>,
+    privilege: usize,
+}
+
+impl Linker<'_> {
+    fn subject(&mut self, name: &str, field: &str) -> Option<SubjectDomainId> {
+        let id = self.subjects.get(name).copied();
+        if id.is_none() {
+            self.errors.push(format!("privileges[{}]: {} names undefined subject domain '{}'", self.privilege, field, name));
+        }
+        id
+    }
+
+    fn object(&mut self, name: &str, field: &str) -> Option<ObjectDomainId> {
+        let id = self.objects.get(name).copied();
+        if id.is_none() {
+            self.errors.push(format!("privileges[{}]: {} names undefined object domain '{}'", self.privilege, field, name));
+        }
+        id
+    }
+
+    fn subjects(&mut self, field: &CallRetPrivField, name: &str) -> Domains<SubjectDomainId> {
+        match field {
+            CallRetPrivField::All => Domains::All,
+            CallRetPrivField::List(names) => Domains::List(names.iter().filter_map(|n| self.subject(n, name)).collect()),
+        }
+    }
+
+    fn context(&mut self, context: &ContextField, name: &str) -> Domains<SubjectDomainId> {
+        match context {
+            ContextField::Context(context) => match context.call_context() {
+                Some(CallContextSubField::List(names)) =>
+                    Domains::List(names.iter().filter_map(|n| self.subject(n, name)).collect()),
+                _ => Domains::All,
+            },
+            _ => Domains::All,
+        }
+    }
+
+    fn accesses(&mut self, field: &RWPrivField, name: &str) -> Option<Vec<LinkedObject>> {
+        match field {
+            RWPrivField::All => None,
+            RWPrivField::List(objects) => Some(objects.iter().map(|o| self.object_list(o, name)).collect()),
+        }
+    }
+
+    fn object_list(&mut self, object: &Object, name: &str) -> LinkedObject {
+        LinkedObject {
+            objects: object.objects().iter().filter_map(|n| self.object(n, name)).collect(),
+            call_context: self.context(object.object_context(), &format!("{}.object_context", name)),
+        }
+    }
+
+    fn privilege(&mut self, privilege: &Privilege) -> Option<LinkedPrivilege> {
+        let principal = self.subject(privilege.principal().subject(), "principal");
+        let linked = LinkedPrivilege {
+            source: self.privilege,
+            principal: principal.unwrap_or(SubjectDomainId(0)),
+            call_context: self.context(privilege.principal().execution_context(), "call_context"),
+            can_call: self.subjects(privilege.can_call(), "can_call"),
+            can_return: self.subjects(privilege.can_return(), "can_return"),
+            can_read: self.accesses(privilege.can_read(), "can_read"),
+            can_write: self.accesses(privilege.can_write(), "can_write"),
+        };
+        principal.map(|_| linked)
+    }
+}
+
+impl LinkedPrivMap {
+    /// Resolves every domain name; the error lists each undefined reference on its own line.
+    pub fn link(priv_map: CPMPrivMap) -> Result<Self, String> {
+        let mut subjects = HashMap::new();
+        for (i, sd) in priv_map.subject_map().iter().enumerate() {
+            subjects.entry(sd.name().to_string()).or_insert(SubjectDomainId(i));
+        }
+        let mut objects = HashMap::new();
+        for (i, od) in priv_map.object_map().iter().enumerate() {
+            objects.entry(od.name().to_string()).or_insert(ObjectDomainId(i));
+        }
+
+        let mut linker = Linker { subjects: &subjects, objects: &objects, errors: vec![], privilege: 0 };
+        let mut privileges = vec![];
+        for (i, privilege) in priv_map.privileges().iter().enumerate() {
+            linker.privilege = i;
+            privileges.extend(linker.privilege(privilege));
+        }
+        if !linker.errors.is_empty() {
+            return Err(linker.errors.join("\n"));
+        }
+        Ok(LinkedPrivMap { priv_map, subjects, objects, privileges })
+    }
+
+    pub fn priv_map(&self) -> &CPMPrivMap {
+        &self.priv_map
+    }
+
+    pub fn into_inner(self) -> CPMPrivMap {
+        self.priv_map
+    }
+
+    pub fn privileges(&self) -> &[LinkedPrivilege] {
+        &self.privileges
+    }
+
+    pub fn subject_id(&self, name: &str) -> Option<SubjectDomainId> {
+        self.subjects.get(name).copied()
+    }
+
+    pub fn object_id(&self, name: &str) -> Option<ObjectDomainId> {
+        self.objects.get(name).copied()
+    }
+
+    pub fn subject_domain(&self, id: SubjectDomainId) -> &SubjectDomain {
+        &self.priv_map.subject_map()[id.0]
+    }
+
+    pub fn object_domain(&self, id: ObjectDomainId) -> &ObjectDomain {
+        &self.priv_map.object_map()[id.0]
+    }
+
+    /// Handles of every subject domain, in subject_map order, duplicates excluded.
+    pub fn subject_ids(&self) -> impl Iterator<Item = SubjectDomainId> + '_ {
+        (0..self.priv_map.subject_map().len()).map(SubjectDomainId).filter(|&id| self.subjects[self.subject_domain(id).name()] == id)
+    }
+
+    /// Handles of every object domain, in object_map order, duplicates excluded.
+    pub fn object_ids(&self) -> impl Iterator<Item = ObjectDomainId> + '_ {
+        (0..self.priv_map.object_map().len()).map(ObjectDomainId).filter(|&id| self.objects[self.object_domain(id).name()] == id)
+    }
+
+    pub fn privileges_for(&self, subject: SubjectDomainId) -> impl Iterator<Item = &LinkedPrivilege> {
+        self.privileges.iter().filter(move |p| p.principal == subject)
+    }
+
+    /// The string form of the privilege a linked privilege was resolved from.
+    pub fn source(&self, privilege: &LinkedPrivilege) -> &Privilege {
+        &self.priv_map.privileges()[privilege.source]
+    }
+}
+
+impl TryFrom<CPMPrivMap> for LinkedPrivMap {
+    type Error = String;
+
+    fn try_from(priv_map: CPMPrivMap) -> Result<Self, String> {
+        Self::link(priv_map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link() {
+        let priv_map: CPMPrivMap = serde_yaml::from_str("
+object_map:
+  - {name: od1, objects: []}
+  - {name: od2, objects: []}
+subject_map:
+  - {name: s1, subjects: [f1]}
+  - {name: s2, subjects: [f2]}
+  - {name: s1, subjects: [f3]}
+privileges:
+  - principal: {subject: s1, execution_context: {call_context: [s2]}}
+    can_call: [s2]
+    can_return: all
+    can_read: [{objects: [od2], object_context: {call_context: [s1]}}]
+    can_write: []
+  - principal: {subject: s2}
+    can_call: []
+    can_return: [s1]
+    can_read: all
+    can_write: [{objects: [od1, od2]}]
+").unwrap();
+        let linked = LinkedPrivMap::link(priv_map).unwrap();
+        let (s1, s2) = (linked.subject_id("s1").unwrap(), linked.subject_id("s2").unwrap());
+        let od2 = linked.object_id("od2").unwrap();
+        assert_eq!(linked.subject_ids().collect::<Vec<_>>(), vec![s1, s2]);
+        assert_eq!(linked.subject_domain(s2).subjects(), &vec!["f2".to_string()]);
+        assert_eq!(linked.object_domain(od2).name(), "od2");
+
+        let p = &linked.privileges()[0];
+        assert_eq!(p.principal, s1);
+        assert_eq!(p.call_context, Domains::List(vec![s2]));
+        assert!(p.can_call.contains(s2) && p.can_return.contains(s1));
+        assert_eq!(p.can_read, Some(vec![LinkedObject { objects: vec![od2], call_context: Domains::List(vec![s1]) }]));
+        assert!(p.accesses(od2, false) && !p.accesses(od2, true));
+        assert!(linked.privileges_for(s2).all(|p| p.accesses(od2, true) && p.accesses(od2, false)));
+        assert_eq!(linked.source(&linked.privileges()[1]).principal().subject(), "s2");
+    }
+
+    #[test]
+    fn test_link_undefined() {
+        let priv_map: CPMPrivMap = serde_yaml::from_str("
+object_map: []
+subject_map:
+  - {name: s1, subjects: [f1]}
+privileges:
+  - principal: {subject: ghost}
+    can_call: [s1, nope]
+    can_return: []
+    can_read: [{objects: [od9]}]
+    can_write: []
+").unwrap();
+        assert_eq!(LinkedPrivMap::link(priv_map).unwrap_err(), "\
+privileges[0]: principal names undefined subject domain 'ghost'
+privileges[0]: can_call names undefined subject domain 'nope'
+privileges[0]: can_read names undefined object domain 'od9'");
+    }
+}
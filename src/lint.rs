@@ -0,0 +1,1109 @@
+// SPDX-License-Identifier: MIT
+//
+// MIT License
+//
+// © 2024 Nathan Dautenhahn & Serenitix LLC
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use crate::cpm_priv_map::{
+    CPMPrivMap, CallContextSubField, CallRetPrivField, ContextField, ContextSimpleString, LoadMode, ObjectDomain,
+    Privilege, RWPrivField, SubjectDomain,
+};
+use crate::include::{IncludedPolicy, Origin};
+use crate::patterns::{expand_patterns, for_each_privilege_reference, has_patterns, DomainRef, PatternCache};
+use crate::source_map::{Section, SourceMap};
+use crate::stream::{visit_policy, PolicyVisitor};
+
+/*
+ * Style and risk lints over a CPMPrivMap.
+ *
+ * Each rule implements `LintRule` and reports findings against an entry of
+ * the policy. Most rules also provide a `LintPass`, which sees one entry
+ * at a time, so `Linter::lint_file` can stream the policy from disk; only
+ * overlapping-objects, which compares every object of every domain, needs
+ * the whole map and is left out there. The `Linter` applies the configured
+ * severity of each rule and drops findings suppressed inline with a comment
+ * on the first line of the offending entry, or on a comment line directly
+ * above it:
+ *
+ *   - name: GlobalObjectDomain7.jiffies   # cpm-lint: allow(unused-object-domain)
+ *
+ * A rule set to `allow` in the configuration is not run at all.
+ */
+
+const SUPPRESSION_MARKER: &str = "cpm-lint: allow(";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Allow,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Allow => write!(f, "allow"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// The policy entry a finding is about, by index into the `CPMPrivMap` vectors.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(tag = "kind", content = "index", rename_all = "snake_case")]
+pub enum LintTarget {
+    ObjectDomain(usize),
+    SubjectDomain(usize),
+    Privilege(usize),
+}
+
+impl LintTarget {
+    fn section(&self) -> (Section, usize) {
+        match *self {
+            LintTarget::ObjectDomain(i) => (Section::ObjectMap, i),
+            LintTarget::SubjectDomain(i) => (Section::SubjectMap, i),
+            LintTarget::Privilege(i) => (Section::Privileges, i),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub target: LintTarget,
+    // the line the entry starts on, for a streamed policy
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diagnostic {
+    pub rule: &'static str,
+    pub severity: Severity,
+    pub target: LintTarget,
+    pub message: String,
+    // 1-based line of the offending entry, when the source text is known
+    pub line: Option<usize>,
+    // file of the offending entry, for policies assembled from includes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+}
+
+// the text of one policy file, scanned for entry lines and suppressions
+struct LintSource<'a> {
+    file: Option<&'a str>,
+    map: SourceMap,
+    lines: Vec<&'a str>,
+}
+
+impl<'a> LintSource<'a> {
+    fn new(file: Option<&'a str>, text: &'a str) -> Self {
+        LintSource { file, map: SourceMap::parse(text), lines: text.lines().collect() }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(line) = self.line {
+            write!(f, "{}:", line)?;
+        }
+        write!(f, " {}[{}]: {}", self.severity, self.rule, self.message)
+    }
+}
+
+pub trait LintRule {
+    /// Stable kebab-case identifier used in configuration and suppressions.
+    fn id(&self) -> &'static str;
+    fn description(&self) -> &'static str;
+    fn default_severity(&self) -> Severity;
+
+    /// Checks a loaded policy; for a rule with a pass, by feeding it every entry.
+    fn check(&self, priv_map: &CPMPrivMap, findings: &mut Vec<Finding>) {
+        if let Some(mut pass) = self.pass() {
+            run_pass(pass.as_mut(), priv_map, findings);
+        }
+    }
+
+    /// A pass checking the policy one entry at a time, so it can be streamed;
+    /// `None` for rules that need the whole map, which then override `check`.
+    fn pass(&self) -> Option<Box<dyn LintPass>> {
+        None
+    }
+
+    /// Rules that inspect domain patterns themselves are given the map as
+    /// written; every other rule sees patterns expanded to the domains they match.
+    fn before_expansion(&self) -> bool {
+        false
+    }
+}
+
+/// An entry's index in its section and, when streamed, the line it starts on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub index: usize,
+    pub line: Option<usize>,
+}
+
+/*
+ * A rule's walk over a policy, entry by entry in file order. What it keeps
+ * across entries is limited to domain names and the few entries it cannot
+ * judge yet; `finish` reports those once every entry has been seen. A
+ * streamed policy keeps its pattern references, so a pass matches them
+ * against the domain names itself.
+ */
+pub trait LintPass {
+    fn object_domain(&mut self, _at: Position, _domain: &ObjectDomain, _findings: &mut Vec<Finding>) {}
+    fn subject_domain(&mut self, _at: Position, _domain: &SubjectDomain, _findings: &mut Vec<Finding>) {}
+    fn privilege(&mut self, _at: Position, _privilege: &Privilege, _findings: &mut Vec<Finding>) {}
+    fn finish(&mut self, _findings: &mut Vec<Finding>) {}
+}
+
+// feeds every entry of a loaded map through the pass
+fn run_pass(pass: &mut dyn LintPass, priv_map: &CPMPrivMap, findings: &mut Vec<Finding>) {
+    let at = |index| Position { index, line: None };
+    for (i, od) in priv_map.object_map().iter().enumerate() {
+        pass.object_domain(at(i), od, findings);
+    }
+    for (i, sd) in priv_map.subject_map().iter().enumerate() {
+        pass.subject_domain(at(i), sd, findings);
+    }
+    for (i, privilege) in priv_map.privileges().iter().enumerate() {
+        pass.privilege(at(i), privilege, findings);
+    }
+    pass.finish(findings);
+}
+
+// an enabled rule's pass and what it found so far
+struct RulePass<'a> {
+    rule: &'a dyn LintRule,
+    severity: Severity,
+    pass: Box<dyn LintPass>,
+    findings: Vec<Finding>,
+}
+
+// the passes of the enabled rules, fed by a streamed policy
+struct PassVisitor<'a> {
+    passes: Vec<RulePass<'a>>,
+    // entries visited so far in each section
+    counts: [usize; 3],
+}
+
+impl PassVisitor<'_> {
+    fn next(&mut self, slot: usize, line: usize) -> Position {
+        self.counts[slot] += 1;
+        Position { index: self.counts[slot] - 1, line: Some(line) }
+    }
+}
+
+impl PolicyVisitor for PassVisitor<'_> {
+    fn visit_object_domain(&mut self, domain: ObjectDomain, line: usize) -> Result<(), String> {
+        let at = self.next(0, line);
+        for p in self.passes.iter_mut() {
+            p.pass.object_domain(at, &domain, &mut p.findings);
+        }
+        Ok(())
+    }
+
+    fn visit_subject_domain(&mut self, domain: SubjectDomain, line: usize) -> Result<(), String> {
+        let at = self.next(1, line);
+        for p in self.passes.iter_mut() {
+            p.pass.subject_domain(at, &domain, &mut p.findings);
+        }
+        Ok(())
+    }
+
+    fn visit_privilege(&mut self, privilege: Privilege, line: usize) -> Result<(), String> {
+        let at = self.next(2, line);
+        for p in self.passes.iter_mut() {
+            p.pass.privilege(at, &privilege, &mut p.findings);
+        }
+        Ok(())
+    }
+}
+
+/*
+ * Per-rule severity overrides, loaded from YAML:
+ *
+ *   rules:
+ *     root-uid: error
+ *     unused-object-domain: allow
+ */
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct LintConfig {
+    #[serde(default)]
+    pub rules: HashMap<String, Severity>,
+}
+
+impl LintConfig {
+    pub fn from_yaml_str(yaml: &str) -> Result<Self, String> {
+        serde_yaml::from_str(yaml).map_err(|e| format!("Failed to parse lint config: {}", e))
+    }
+
+    pub fn load(config_file: &str) -> Result<Self, String> {
+        let yaml = std::fs::read_to_string(config_file)
+            .map_err(|e| format!("Failed to read lint config: {}", e))?;
+        Self::from_yaml_str(&yaml)
+    }
+
+    pub fn set_severity(&mut self, rule: &str, severity: Severity) {
+        self.rules.insert(rule.to_string(), severity);
+    }
+}
+
+pub struct Linter {
+    rules: Vec<Box<dyn LintRule + Send + Sync>>,
+    config: LintConfig,
+}
+
+impl Linter {
+    /// A linter running every built-in rule under the given configuration.
+    pub fn new(config: LintConfig) -> Self {
+        Self { rules: builtin_rules(), config }
+    }
+
+    pub fn with_rules(rules: Vec<Box<dyn LintRule + Send + Sync>>, config: LintConfig) -> Self {
+        Self { rules, config }
+    }
+
+    pub fn add_rule(&mut self, rule: Box<dyn LintRule + Send + Sync>) {
+        self.rules.push(rule);
+    }
+
+    pub fn rules(&self) -> &Vec<Box<dyn LintRule + Send + Sync>> {
+        &self.rules
+    }
+
+    /// Rejects configuration entries naming rules this linter does not have.
+    pub fn check_config(&self) -> Result<(), String> {
+        let known: HashSet<&str> = self.rules.iter().map(|r| r.id()).collect();
+        let mut unknown: Vec<&String> = self.config.rules.keys().filter(|k| !known.contains(k.as_str())).collect();
+        unknown.sort();
+        match unknown.first() {
+            Some(rule) => Err(format!("Unknown lint rule in config: {}", rule)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn severity(&self, rule: &dyn LintRule) -> Severity {
+        self.config.rules.get(rule.id()).copied().unwrap_or_else(|| rule.default_severity())
+    }
+
+    /// Runs every enabled rule. With the policy's source text, diagnostics
+    /// carry line numbers and inline suppressions are honored.
+    pub fn lint(&self, priv_map: &CPMPrivMap, source: Option<&str>) -> Vec<Diagnostic> {
+        let sources: Vec<LintSource> = source.map(|text| LintSource::new(None, text)).into_iter().collect();
+        self.lint_sources(priv_map, &sources, &|_, index| Some(Origin { file: 0, index }))
+    }
+
+    /// Runs every enabled rule over a multi-file policy; diagnostics name
+    /// the file of the offending entry and its line there.
+    pub fn lint_included(&self, policy: &IncludedPolicy) -> Vec<Diagnostic> {
+        let sources: Vec<LintSource> = policy.files().iter()
+            .map(|file| LintSource::new(Some(&file.path), &file.text))
+            .collect();
+        self.lint_sources(&policy.priv_map, &sources, &|section, index| policy.origin(section, index))
+    }
+
+    /// Streams a single policy file through the enabled rules that have a
+    /// pass, keeping only the domain names they need rather than the whole
+    /// policy. Rules that need the whole map are not run, and include
+    /// directives are not followed.
+    pub fn lint_file(&self, file_path: &str, mode: LoadMode) -> Result<Vec<Diagnostic>, String> {
+        let read_error = |e: std::io::Error| format!("Failed to read YAML file: {}", e);
+        let mut visitor = PassVisitor { passes: vec![], counts: [0; 3] };
+        for rule in &self.rules {
+            let severity = self.severity(rule.as_ref());
+            if let (false, Some(pass)) = (severity == Severity::Allow, rule.pass()) {
+                visitor.passes.push(RulePass { rule: rule.as_ref(), severity, pass, findings: vec![] });
+            }
+        }
+        let file = File::open(file_path).map_err(read_error)?;
+        visit_policy(BufReader::new(file), mode, &mut visitor)?;
+        for p in visitor.passes.iter_mut() {
+            p.pass.finish(&mut p.findings);
+        }
+
+        // reread only the lines that can hold a suppression of some finding
+        let wanted: HashSet<usize> = visitor.passes.iter()
+            .flat_map(|p| p.findings.iter().filter_map(|f| f.line))
+            .flat_map(|line| [line - 1, line])
+            .collect();
+        let mut lines: HashMap<usize, String> = HashMap::new();
+        let file = File::open(file_path).map_err(read_error)?;
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            if wanted.contains(&(i + 1)) {
+                lines.insert(i + 1, line.map_err(read_error)?);
+            }
+        }
+
+        let mut diagnostics = vec![];
+        for RulePass { rule, severity, findings, .. } in visitor.passes {
+            for finding in findings {
+                let line_at = |line: usize| lines.get(&line).map(|l| l.as_str());
+                if finding.line.is_some_and(|line| entry_suppresses(line_at, line, rule.id())) {
+                    continue;
+                }
+                diagnostics.push(Diagnostic {
+                    rule: rule.id(),
+                    severity,
+                    target: finding.target,
+                    message: finding.message,
+                    line: finding.line,
+                    file: None,
+                });
+            }
+        }
+        Ok(diagnostics)
+    }
+
+    fn lint_sources(&self, priv_map: &CPMPrivMap, sources: &[LintSource],
+                    origin: &dyn Fn(Section, usize) -> Option<Origin>) -> Vec<Diagnostic> {
+
+        // an invalid pattern is reported by unmatched-pattern; the others then see it unexpanded
+        let expanded = match has_patterns(priv_map) {
+            true => {
+                let mut expanded = priv_map.clone();
+                let _ = expand_patterns(&mut expanded);
+                Cow::Owned(expanded)
+            }
+            false => Cow::Borrowed(priv_map),
+        };
+
+        let mut diagnostics = vec![];
+        for rule in &self.rules {
+            let severity = self.severity(rule.as_ref());
+            if severity == Severity::Allow {
+                continue;
+            }
+            let mut findings = vec![];
+            rule.check(if rule.before_expansion() { priv_map } else { &expanded }, &mut findings);
+            for finding in findings {
+                let (section, index) = finding.target.section();
+                let located = origin(section, index).and_then(|o| sources.get(o.file).map(|s| (s, o.index)));
+                let span = located.and_then(|(source, index)| source.map.entry(section, index));
+                if let (Some(span), Some((source, _))) = (span, located) {
+                    let line_at = |line: usize| line.checked_sub(1).and_then(|i| source.lines.get(i)).copied();
+                    if entry_suppresses(line_at, span.first_line, rule.id()) {
+                        continue;
+                    }
+                }
+                diagnostics.push(Diagnostic {
+                    rule: rule.id(),
+                    severity,
+                    target: finding.target,
+                    message: finding.message,
+                    line: span.map(|s| s.first_line),
+                    file: located.and_then(|(source, _)| source.file.map(String::from)),
+                });
+            }
+        }
+        diagnostics
+    }
+}
+
+// true if a comment on the entry's first line, or on a comment line directly above it, names the rule
+fn entry_suppresses<'a>(line_at: impl Fn(usize) -> Option<&'a str>, first_line: usize, rule_id: &str) -> bool {
+    // a trailing comment on the line above belongs to the previous entry
+    let above = first_line.checked_sub(1)
+        .and_then(&line_at)
+        .filter(|line| line.trim_start().starts_with('#'));
+    line_at(first_line).into_iter().chain(above).any(|line| suppresses(line, rule_id))
+}
+
+// true if the line carries a suppression comment naming the rule
+fn suppresses(line: &str, rule_id: &str) -> bool {
+    let Some(comment) = line.find('#').map(|i| &line[i..]) else {
+        return false;
+    };
+    let Some(start) = comment.find(SUPPRESSION_MARKER) else {
+        return false;
+    };
+    let rest = &comment[start + SUPPRESSION_MARKER.len()..];
+    let list = rest.split(')').next().unwrap_or("");
+    list.split(',').any(|r| r.trim() == rule_id)
+}
+
+pub fn builtin_rules() -> Vec<Box<dyn LintRule + Send + Sync>> {
+    vec![
+        Box::new(UnusedObjectDomain),
+        Box::new(UnusedSubjectDomain),
+        Box::new(EmptyDomain),
+        Box::new(DuplicateDomainName),
+        Box::new(UnknownPrincipal),
+        Box::new(OverlappingObjects),
+        Box::new(RootUid),
+        Box::new(SelfCall),
+        Box::new(ImplicitAll),
+        Box::new(UnmatchedPatternRule),
+    ]
+}
+
+// the domain names privileges refer to, with patterns matched once every domain is known
+#[derive(Default)]
+struct References {
+    // an `all` grant refers to every domain
+    all: bool,
+    names: HashSet<String>,
+    patterns: PatternCache,
+}
+
+impl References {
+    fn add(&mut self, reference: &str) {
+        if DomainRef::is_pattern(reference) {
+            // an invalid pattern refers to nothing; unmatched-pattern reports it
+            let _ = self.patterns.get_or_parse(reference);
+        } else if !self.names.contains(reference) {
+            self.names.insert(reference.to_string());
+        }
+    }
+
+    fn refer_to(&self, name: &str) -> bool {
+        self.all || self.names.contains(name) || self.patterns.any_matches(name)
+    }
+}
+
+/// Object domains no privilege can read or write.
+pub struct UnusedObjectDomain;
+
+#[derive(Default)]
+struct UnusedObjectDomainPass {
+    defined: Vec<(Position, String)>,
+    used: References,
+}
+
+impl LintRule for UnusedObjectDomain {
+    fn id(&self) -> &'static str { "unused-object-domain" }
+    fn description(&self) -> &'static str { "object domain not named by any can_read or can_write" }
+    fn default_severity(&self) -> Severity { Severity::Warning }
+
+    fn pass(&self) -> Option<Box<dyn LintPass>> {
+        Some(Box::<UnusedObjectDomainPass>::default())
+    }
+}
+
+impl LintPass for UnusedObjectDomainPass {
+    fn object_domain(&mut self, at: Position, domain: &ObjectDomain, _findings: &mut Vec<Finding>) {
+        self.defined.push((at, domain.name().to_string()));
+    }
+
+    fn privilege(&mut self, _at: Position, privilege: &Privilege, _findings: &mut Vec<Finding>) {
+        for field in [privilege.can_read(), privilege.can_write()] {
+            match field {
+                RWPrivField::All => self.used.all = true,
+                RWPrivField::List(objects) => objects.iter()
+                    .flat_map(|o| o.objects().iter())
+                    .for_each(|name| self.used.add(name)),
+            }
+        }
+    }
+
+    fn finish(&mut self, findings: &mut Vec<Finding>) {
+        for (at, name) in &self.defined {
+            if !self.used.refer_to(name) {
+                findings.push(Finding {
+                    target: LintTarget::ObjectDomain(at.index),
+                    line: at.line,
+                    message: format!("object domain '{}' is never read or written", name),
+                });
+            }
+        }
+    }
+}
+
+/// Subject domains that hold no privilege and are never called or returned to.
+pub struct UnusedSubjectDomain;
+
+#[derive(Default)]
+struct UnusedSubjectDomainPass {
+    defined: Vec<(Position, String)>,
+    used: References,
+}
+
+impl LintRule for UnusedSubjectDomain {
+    fn id(&self) -> &'static str { "unused-subject-domain" }
+    fn description(&self) -> &'static str { "subject domain with no privileges that is never called" }
+    fn default_severity(&self) -> Severity { Severity::Warning }
+
+    fn pass(&self) -> Option<Box<dyn LintPass>> {
+        Some(Box::<UnusedSubjectDomainPass>::default())
+    }
+}
+
+impl LintPass for UnusedSubjectDomainPass {
+    fn subject_domain(&mut self, at: Position, domain: &SubjectDomain, _findings: &mut Vec<Finding>) {
+        self.defined.push((at, domain.name().to_string()));
+    }
+
+    fn privilege(&mut self, _at: Position, privilege: &Privilege, _findings: &mut Vec<Finding>) {
+        self.used.add(privilege.principal().subject());
+        // `can_call: all` uses every domain, `can_return: all` only permits returns
+        match privilege.can_call() {
+            CallRetPrivField::All => self.used.all = true,
+            CallRetPrivField::List(names) => names.iter().for_each(|name| self.used.add(name)),
+        }
+        if let CallRetPrivField::List(names) = privilege.can_return() {
+            names.iter().for_each(|name| self.used.add(name));
+        }
+    }
+
+    fn finish(&mut self, findings: &mut Vec<Finding>) {
+        for (at, name) in &self.defined {
+            if !self.used.refer_to(name) {
+                findings.push(Finding {
+                    target: LintTarget::SubjectDomain(at.index),
+                    line: at.line,
+                    message: format!("subject domain '{}' has no privileges and is never called", name),
+                });
+            }
+        }
+    }
+}
+
+/// Domains without any objects or subjects.
+pub struct EmptyDomain;
+
+impl LintRule for EmptyDomain {
+    fn id(&self) -> &'static str { "empty-domain" }
+    fn description(&self) -> &'static str { "object domain without objects or subject domain without subjects" }
+    fn default_severity(&self) -> Severity { Severity::Warning }
+
+    fn pass(&self) -> Option<Box<dyn LintPass>> {
+        Some(Box::new(EmptyDomain))
+    }
+}
+
+impl LintPass for EmptyDomain {
+    fn object_domain(&mut self, at: Position, domain: &ObjectDomain, findings: &mut Vec<Finding>) {
+        if domain.objects().is_empty() {
+            findings.push(Finding {
+                target: LintTarget::ObjectDomain(at.index),
+                line: at.line,
+                message: format!("object domain '{}' has no objects", domain.name()),
+            });
+        }
+    }
+
+    fn subject_domain(&mut self, at: Position, domain: &SubjectDomain, findings: &mut Vec<Finding>) {
+        if domain.subjects().is_empty() {
+            findings.push(Finding {
+                target: LintTarget::SubjectDomain(at.index),
+                line: at.line,
+                message: format!("subject domain '{}' has no subjects", domain.name()),
+            });
+        }
+    }
+}
+
+/// Domain names defined more than once, within or across the two maps.
+pub struct DuplicateDomainName;
+
+#[derive(Default)]
+struct DuplicateDomainNamePass {
+    seen: HashSet<String>,
+}
+
+impl LintRule for DuplicateDomainName {
+    fn id(&self) -> &'static str { "duplicate-domain-name" }
+    fn description(&self) -> &'static str { "domain name defined more than once" }
+    fn default_severity(&self) -> Severity { Severity::Error }
+
+    fn pass(&self) -> Option<Box<dyn LintPass>> {
+        Some(Box::<DuplicateDomainNamePass>::default())
+    }
+}
+
+impl DuplicateDomainNamePass {
+    fn define(&mut self, target: LintTarget, line: Option<usize>, name: &str, findings: &mut Vec<Finding>) {
+        if !self.seen.insert(name.to_string()) {
+            findings.push(Finding {
+                target,
+                line,
+                message: format!("domain name '{}' is already defined", name),
+            });
+        }
+    }
+}
+
+impl LintPass for DuplicateDomainNamePass {
+    fn object_domain(&mut self, at: Position, domain: &ObjectDomain, findings: &mut Vec<Finding>) {
+        self.define(LintTarget::ObjectDomain(at.index), at.line, domain.name(), findings);
+    }
+
+    fn subject_domain(&mut self, at: Position, domain: &SubjectDomain, findings: &mut Vec<Finding>) {
+        self.define(LintTarget::SubjectDomain(at.index), at.line, domain.name(), findings);
+    }
+}
+
+/// Privileges whose principal is not a defined subject domain.
+pub struct UnknownPrincipal;
+
+#[derive(Default)]
+struct UnknownPrincipalPass {
+    subjects: HashSet<String>,
+    // principals not defined yet when their privilege came
+    pending: Vec<(Position, String)>,
+}
+
+impl LintRule for UnknownPrincipal {
+    fn id(&self) -> &'static str { "unknown-principal" }
+    fn description(&self) -> &'static str { "privilege whose principal subject domain is not defined" }
+    fn default_severity(&self) -> Severity { Severity::Error }
+
+    fn pass(&self) -> Option<Box<dyn LintPass>> {
+        Some(Box::<UnknownPrincipalPass>::default())
+    }
+}
+
+impl LintPass for UnknownPrincipalPass {
+    fn subject_domain(&mut self, _at: Position, domain: &SubjectDomain, _findings: &mut Vec<Finding>) {
+        self.subjects.insert(domain.name().to_string());
+    }
+
+    fn privilege(&mut self, at: Position, privilege: &Privilege, _findings: &mut Vec<Finding>) {
+        let subject = privilege.principal().subject();
+        if !self.subjects.contains(subject.as_str()) {
+            self.pending.push((at, subject.to_string()));
+        }
+    }
+
+    fn finish(&mut self, findings: &mut Vec<Finding>) {
+        for (at, subject) in &self.pending {
+            if !self.subjects.contains(subject) {
+                findings.push(Finding {
+                    target: LintTarget::Privilege(at.index),
+                    line: at.line,
+                    message: format!("principal '{}' is not a subject domain", subject),
+                });
+            }
+        }
+    }
+}
+
+/// Objects placed in more than one object domain.
+///
+/// This compares every object of every domain, so it has no pass and runs
+/// only on a policy loaded whole.
+pub struct OverlappingObjects;
+
+impl LintRule for OverlappingObjects {
+    fn id(&self) -> &'static str { "overlapping-objects" }
+    fn description(&self) -> &'static str { "object contained in more than one object domain" }
+    fn default_severity(&self) -> Severity { Severity::Warning }
+
+    fn check(&self, priv_map: &CPMPrivMap, findings: &mut Vec<Finding>) {
+        let mut owner: HashMap<String, &str> = HashMap::new();
+        for (i, od) in priv_map.object_map().iter().enumerate() {
+            let mut reported = HashSet::new();
+            for object in od.objects() {
+                let key = object.to_string();
+                match owner.get(&key) {
+                    Some(&first) if first != od.name() => {
+                        if reported.insert(key.to_string()) {
+                            findings.push(Finding {
+                                target: LintTarget::ObjectDomain(i),
+                                line: None,
+                                message: format!("object '{}' is also in object domain '{}'", key, first),
+                            });
+                        }
+                    }
+                    Some(_) => (),
+                    None => {
+                        owner.insert(key, od.name());
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Privileges granted to a principal running as root.
+pub struct RootUid;
+
+impl LintRule for RootUid {
+    fn id(&self) -> &'static str { "root-uid" }
+    fn description(&self) -> &'static str { "privilege granted under uid root" }
+    fn default_severity(&self) -> Severity { Severity::Warning }
+
+    fn pass(&self) -> Option<Box<dyn LintPass>> {
+        Some(Box::new(RootUid))
+    }
+}
+
+impl LintPass for RootUid {
+    fn privilege(&mut self, at: Position, privilege: &Privilege, findings: &mut Vec<Finding>) {
+        if let ContextField::Context(context) = privilege.principal().execution_context() {
+            if let Some(ContextSimpleString::String(uid)) = context.uid() {
+                if uid == "root" {
+                    findings.push(Finding {
+                        target: LintTarget::Privilege(at.index),
+                        line: at.line,
+                        message: format!("privilege of '{}' is granted under uid root",
+                                         privilege.principal().subject()),
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Subjects listing their own domain in `can_call` or their call context.
+pub struct SelfCall;
+
+#[derive(Default)]
+struct SelfCallPass {
+    patterns: PatternCache,
+}
+
+impl LintRule for SelfCall {
+    fn id(&self) -> &'static str { "self-call" }
+    fn description(&self) -> &'static str { "subject domain granted a call to itself" }
+    fn default_severity(&self) -> Severity { Severity::Warning }
+
+    fn pass(&self) -> Option<Box<dyn LintPass>> {
+        Some(Box::<SelfCallPass>::default())
+    }
+}
+
+impl LintPass for SelfCallPass {
+    fn privilege(&mut self, at: Position, privilege: &Privilege, findings: &mut Vec<Finding>) {
+        let subject = privilege.principal().subject();
+        let mut names_self = |names: &Vec<String>| names.iter().any(|name| match DomainRef::is_pattern(name) {
+            true => self.patterns.get_or_parse(name).is_ok_and(|pattern| pattern.matches(subject)),
+            false => name == subject,
+        });
+        let in_can_call = matches!(privilege.can_call(), CallRetPrivField::List(names) if names_self(names));
+        let in_context = match privilege.principal().execution_context() {
+            ContextField::Context(context) => matches!(
+                context.call_context(), Some(CallContextSubField::List(names)) if names_self(names)),
+            ContextField::All => false,
+        };
+        if in_can_call || in_context {
+            findings.push(Finding {
+                target: LintTarget::Privilege(at.index),
+                line: at.line,
+                message: format!("subject domain '{}' is granted a call to itself", subject),
+            });
+        }
+    }
+}
+
+/// `all` grants that come from omitted or empty fields.
+pub struct ImplicitAll;
+
+impl LintRule for ImplicitAll {
+    fn id(&self) -> &'static str { "implicit-all" }
+    fn description(&self) -> &'static str { "field omitted or empty and therefore defaulted to all" }
+    fn default_severity(&self) -> Severity { Severity::Warning }
+
+    fn pass(&self) -> Option<Box<dyn LintPass>> {
+        Some(Box::new(ImplicitAll))
+    }
+}
+
+impl LintPass for ImplicitAll {
+    fn privilege(&mut self, at: Position, privilege: &Privilege, findings: &mut Vec<Finding>) {
+        for field in privilege.implicit_all_fields() {
+            findings.push(Finding {
+                target: LintTarget::Privilege(at.index),
+                line: at.line,
+                message: format!("{} of '{}' is omitted and defaults to all", field, privilege.principal().subject()),
+            });
+        }
+    }
+}
+
+/// Pattern references that are invalid or match no domain.
+pub struct UnmatchedPatternRule;
+
+#[derive(Default)]
+struct UnmatchedPatternPass {
+    subjects: Vec<String>,
+    objects: Vec<String>,
+    patterns: PatternCache,
+    // references that were invalid or matched no domain defined so far: entry, field, reference
+    pending: Vec<(Position, &'static str, String)>,
+}
+
+impl LintRule for UnmatchedPatternRule {
+    fn id(&self) -> &'static str { "unmatched-pattern" }
+    fn description(&self) -> &'static str { "glob or regex domain reference that is invalid or matches nothing" }
+    fn default_severity(&self) -> Severity { Severity::Error }
+
+    fn before_expansion(&self) -> bool {
+        true
+    }
+
+    fn pass(&self) -> Option<Box<dyn LintPass>> {
+        Some(Box::<UnmatchedPatternPass>::default())
+    }
+}
+
+impl UnmatchedPatternPass {
+    fn matches_any(&mut self, field: &str, reference: &str) -> Result<bool, String> {
+        let universe = match field {
+            "can_read" | "can_write" => &self.objects,
+            _ => &self.subjects,
+        };
+        let pattern = self.patterns.get_or_parse(reference)?;
+        Ok(universe.iter().any(|name| pattern.matches(name)))
+    }
+}
+
+impl LintPass for UnmatchedPatternPass {
+    fn object_domain(&mut self, _at: Position, domain: &ObjectDomain, _findings: &mut Vec<Finding>) {
+        self.objects.push(domain.name().to_string());
+    }
+
+    fn subject_domain(&mut self, _at: Position, domain: &SubjectDomain, _findings: &mut Vec<Finding>) {
+        self.subjects.push(domain.name().to_string());
+    }
+
+    fn privilege(&mut self, at: Position, privilege: &Privilege, _findings: &mut Vec<Finding>) {
+        for_each_privilege_reference(privilege, |field, reference| {
+            if DomainRef::is_pattern(reference) && self.matches_any(field, reference) != Ok(true) {
+                self.pending.push((at, field, reference.to_string()));
+            }
+        });
+    }
+
+    fn finish(&mut self, findings: &mut Vec<Finding>) {
+        for (at, field, reference) in std::mem::take(&mut self.pending) {
+            let message = match self.matches_any(field, &reference) {
+                Err(e) => format!("{} has an {}", field, e),
+                Ok(false) => format!("{} pattern '{}' matches no domain", field, reference),
+                Ok(true) => continue,
+            };
+            findings.push(Finding { target: LintTarget::Privilege(at.index), line: at.line, message });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpm_priv_map::LoadMode;
+
+    const POLICY: &str = "\
+object_map:
+  - name: od1
+    objects: [a]
+  - name: od2   # cpm-lint: allow(empty-domain, unused-object-domain)
+    objects: []
+  - name: od3
+    objects: [a]
+subject_map:
+  - name: s1
+    subjects: [f1]
+  - name: s2
+    subjects: [f2]
+  - name: od1
+    subjects: []
+privileges:
+  - principal:
+      subject: s1
+      execution_context:
+        uid: root
+    can_call: [s1]
+    can_return: []
+    can_read:
+      - objects: [od1]
+    can_write: []
+  # cpm-lint: allow(unknown-principal)
+  - principal:
+      subject: ghost
+      execution_context: all
+    can_call: []
+    can_return: []
+    can_read: []
+    can_write: []
+  - principal:
+      subject: nobody
+    can_call: []
+    can_return: []
+    can_read: []
+    can_write: []
+";
+
+    fn rules_hit(diagnostics: &[Diagnostic]) -> Vec<(&'static str, Option<usize>)> {
+        diagnostics.iter().map(|d| (d.rule, d.line)).collect()
+    }
+
+    #[test]
+    fn test_builtin_rules() {
+        let priv_map: CPMPrivMap = serde_yaml::from_str(POLICY).unwrap();
+        let linter = Linter::new(LintConfig::default());
+        let diagnostics = linter.lint(&priv_map, Some(POLICY));
+
+        assert_eq!(rules_hit(&diagnostics), vec![
+            ("unused-object-domain", Some(6)),
+            ("unused-subject-domain", Some(11)),
+            ("unused-subject-domain", Some(13)),
+            ("empty-domain", Some(13)),
+            ("duplicate-domain-name", Some(13)),
+            ("unknown-principal", Some(33)),
+            ("overlapping-objects", Some(6)),
+            ("root-uid", Some(16)),
+            ("self-call", Some(16)),
+            ("implicit-all", Some(33)),
+        ]);
+        assert_eq!(diagnostics[5].severity, Severity::Error);
+        assert_eq!(diagnostics[5].to_string(), "33: error[unknown-principal]: principal 'nobody' is not a subject domain");
+    }
+
+    #[test]
+    fn test_all_grants_and_suppression_scope() {
+        let policy = "\
+object_map:
+  - name: od1
+    objects: [a]   # cpm-lint: allow(empty-domain)
+  - name: od2
+    objects: []
+subject_map:
+  - name: s1
+    subjects: [f1]
+  - name: s2
+    subjects: [f2]
+privileges:
+  - principal:
+      subject: s1
+      execution_context: all
+    can_call: []
+    can_return: all
+    can_read: all
+    can_write: []
+";
+        let priv_map: CPMPrivMap = serde_yaml::from_str(policy).unwrap();
+        let diagnostics = Linter::new(LintConfig::default()).lint(&priv_map, Some(policy));
+        // can_read: all uses every object domain, can_return: all uses no subject domain,
+        // and the comment on od1's last line does not reach od2
+        assert_eq!(rules_hit(&diagnostics), vec![
+            ("unused-subject-domain", Some(9)),
+            ("empty-domain", Some(4)),
+        ]);
+    }
+
+    #[test]
+    fn test_implicit_all() {
+        let policy = "\
+object_map: []
+subject_map:
+  - name: s1
+    subjects: [f1]
+privileges:
+  - principal:
+      subject: s1
+    can_call: []
+    can_read: all
+    can_write:
+";
+        let rule = |mode| {
+            let priv_map = CPMPrivMap::from_yaml_str(policy, mode).unwrap();
+            let mut findings = vec![];
+            ImplicitAll.check(&priv_map, &mut findings);
+            findings.into_iter().map(|f| f.message).collect::<Vec<_>>()
+        };
+        // an explicit `all` is not flagged, an empty field is
+        assert_eq!(rule(LoadMode::AllowByDefault), vec![
+            "execution_context of 's1' is omitted and defaults to all",
+            "can_return of 's1' is omitted and defaults to all",
+            "can_write of 's1' is omitted and defaults to all",
+        ]);
+        // deny-by-default leaves only the execution context
+        assert_eq!(rule(LoadMode::DenyByDefault), vec!["execution_context of 's1' is omitted and defaults to all"]);
+    }
+
+    #[test]
+    fn test_patterns() {
+        let policy = "\
+object_map:
+  - name: HeapObjectDomain1.kmalloc
+    objects: [a]
+subject_map:
+  - name: s1
+    subjects: [f1]
+privileges:
+  - principal:
+      subject: s1
+    can_call: []
+    can_return: []
+    can_read:
+      - objects: ['glob:HeapObjectDomain*']
+    can_write:
+      - objects: ['glob:Stack*', 're:(']
+";
+        let priv_map: CPMPrivMap = serde_yaml::from_str(policy).unwrap();
+        let config = LintConfig::from_yaml_str("rules:\n  implicit-all: allow\n").unwrap();
+        let diagnostics = Linter::new(config).lint(&priv_map, Some(policy));
+        // the read pattern keeps the object domain used
+        let messages: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0], "can_write pattern 'glob:Stack*' matches no domain");
+        assert_eq!(messages[1], "can_write has an invalid regex in 're:(': unclosed group");
+        assert!(diagnostics.iter().all(|d| d.rule == "unmatched-pattern" && d.line == Some(8)));
+    }
+
+    #[test]
+    fn test_lint_file_streams() {
+        let path = std::env::temp_dir().join(format!("cpm_if_lint_stream_{}.yaml", std::process::id()));
+        let linter = Linter::new(LintConfig::default());
+        let pattern_policy = "\
+object_map:
+  - name: od1
+    objects: [a]
+subject_map:
+  - name: s1
+    subjects: [f1]
+privileges:
+  - principal: {subject: s1}
+    can_call: ['glob:s*']
+    can_read: [{objects: ['glob:od*', 're:(']}]
+";
+        for policy in [POLICY, pattern_policy] {
+            std::fs::write(&path, policy).unwrap();
+            let streamed = linter.lint_file(&path.to_string_lossy(), LoadMode::AllowByDefault).unwrap();
+            let priv_map: CPMPrivMap = serde_yaml::from_str(policy).unwrap();
+            let mut loaded = linter.lint(&priv_map, Some(policy));
+            // the only rule that needs the whole map
+            loaded.retain(|d| d.rule != "overlapping-objects");
+            assert_eq!(streamed, loaded);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_configured_severity() {
+        let priv_map: CPMPrivMap = serde_yaml::from_str(POLICY).unwrap();
+        let config = LintConfig::from_yaml_str("rules:\n  root-uid: error\n  unused-subject-domain: allow\n").unwrap();
+        let linter = Linter::new(config);
+        assert!(linter.check_config().is_ok());
+
+        let diagnostics = linter.lint(&priv_map, None);
+        assert!(diagnostics.iter().all(|d| d.rule != "unused-subject-domain"));
+        let root = diagnostics.iter().find(|d| d.rule == "root-uid").unwrap();
+        assert_eq!(root.severity, Severity::Error);
+        assert_eq!(root.line, None);
+        // without source text nothing is suppressed
+        assert!(diagnostics.iter().any(|d| d.rule == "empty-domain" && d.target == LintTarget::ObjectDomain(1)));
+
+        let bad = Linter::new(LintConfig::from_yaml_str("rules:\n  no-such-rule: warning\n").unwrap());
+        assert!(bad.check_config().is_err());
+    }
+}
@@ -0,0 +1,223 @@
+// SPDX-License-Identifier: MIT
+//
+// MIT License
+//
+// © 2024 Nathan Dautenhahn & Serenitix LLC
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::cpm_priv_map::{CPMPrivMap, ObjectDomain, ObjectID};
+
+/*
+ * Queries over the source locations of objects (`ObjectID` path and line).
+ *
+ * A location is `<path>[:<line>[-<line>]]`. The path names a file or a
+ * directory: `net/ipv4` and `net/ipv4/` both match every object defined
+ * below net/ipv4, and an empty path matches everything. A leading `./` is
+ * ignored on both sides. Objects whose line is not a number never fall in
+ * a line range.
+ */
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub path: String,
+    // inclusive
+    pub lines: Option<(u32, u32)>,
+}
+
+pub(crate) fn normalize(path: &str) -> &str {
+    let mut path = path;
+    while let Some(rest) = path.strip_prefix("./") {
+        path = rest;
+    }
+    path
+}
+
+impl SourceLocation {
+    pub fn new(path: &str) -> Self {
+        SourceLocation { path: path.to_string(), lines: None }
+    }
+
+    pub fn with_lines(mut self, from: u32, to: u32) -> Self {
+        self.lines = Some((from, to));
+        self
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let Some((path, range)) = text.rsplit_once(':') else {
+            return Ok(Self::new(text));
+        };
+        let number = |s: &str| s.trim().parse::<u32>()
+            .map_err(|_| format!("invalid line range in location '{}'", text));
+        let (from, to) = match range.split_once('-') {
+            Some((from, to)) => (number(from)?, number(to)?),
+            None => (number(range)?, number(range)?),
+        };
+        if from > to {
+            return Err(format!("invalid line range in location '{}': {} > {}", text, from, to));
+        }
+        Ok(Self::new(path).with_lines(from, to))
+    }
+
+    pub fn contains_path(&self, path: &str) -> bool {
+        let prefix = normalize(&self.path).trim_end_matches('/');
+        let path = normalize(path);
+        prefix.is_empty()
+            || path == prefix
+            || (path.starts_with(prefix) && path[prefix.len()..].starts_with('/'))
+    }
+
+    pub fn matches(&self, object: &ObjectID) -> bool {
+        if !self.contains_path(object.path()) {
+            return false;
+        }
+        match (self.lines, object.lineno().parse::<u32>()) {
+            (None, _) => true,
+            (Some((from, to)), Ok(line)) => from <= line && line <= to,
+            (Some(_), Err(_)) => false,
+        }
+    }
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.lines {
+            None => write!(f, "{}", self.path),
+            Some((from, to)) if from == to => write!(f, "{}:{}", self.path, from),
+            Some((from, to)) => write!(f, "{}:{}-{}", self.path, from, to),
+        }
+    }
+}
+
+/// Objects defined at the location with their domains, in object map order.
+pub fn objects_at<'a>(priv_map: &'a CPMPrivMap, location: &SourceLocation) -> Vec<(&'a ObjectDomain, &'a ObjectID)> {
+    priv_map.object_map().iter()
+        .flat_map(|od| od.objects().iter().map(move |o| (od, o)))
+        .filter(|(_, o)| location.matches(o))
+        .collect()
+}
+
+/// Object domains with an object defined at the location, in object map order.
+pub fn domains_at<'a>(priv_map: &'a CPMPrivMap, location: &SourceLocation) -> Vec<&'a ObjectDomain> {
+    priv_map.object_map().iter()
+        .filter(|od| od.objects().iter().any(|o| location.matches(o)))
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DomainLines {
+    pub domain: String,
+    // lines of the domain's objects in the file, numeric ones first in order
+    pub lines: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileDomains {
+    pub path: String,
+    pub domains: Vec<DomainLines>,
+}
+
+impl fmt::Display for FileDomains {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.path)?;
+        for domain in &self.domains {
+            writeln!(f, "  {}: {}", domain.domain, domain.lines.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/*
+ * Reverse map from source files to the object domains defined in them,
+ * sorted by path, for objects within `location` (everything if None).
+ * Objects without a path are left out.
+ */
+pub fn domains_by_file(priv_map: &CPMPrivMap, location: Option<&SourceLocation>) -> Vec<FileDomains> {
+    let mut files: BTreeMap<&str, Vec<DomainLines>> = BTreeMap::new();
+    for od in priv_map.object_map() {
+        for object in od.objects() {
+            if object.path().is_empty() || location.is_some_and(|l| !l.matches(object)) {
+                continue;
+            }
+            let domains = files.entry(normalize(object.path())).or_default();
+            let entry = match domains.iter().position(|d| d.domain == od.name()) {
+                Some(i) => &mut domains[i],
+                None => {
+                    domains.push(DomainLines { domain: od.name().to_string(), lines: vec![] });
+                    domains.last_mut().unwrap()
+                }
+            };
+            if !entry.lines.iter().any(|l| l == object.lineno()) {
+                entry.lines.push(object.lineno().to_string());
+            }
+        }
+    }
+    files.into_iter().map(|(path, mut domains)| {
+        for domain in domains.iter_mut() {
+            domain.lines.sort_by_key(|l| l.parse::<u32>().map_or((1, 0, l.to_string()), |n| (0, n, String::new())));
+        }
+        FileDomains { path: path.to_string(), domains }
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: &str = "
+object_map:
+  - name: od1
+    objects: ['HEAP|net/ipv4/tcp.c|120|', 'GLOBAL|./net/ipv4/tcp.c|30|tcp_hashinfo', 'LOCAL|net/core/dev.c|7|x']
+  - name: od2
+    objects: ['HEAP|net/ipv4/udp.c|250|', 'HEAP|net/ipv4/tcp.c|400|', 'OTHER|net/ipv4/tcp.c|?|y']
+  - name: od3
+    objects: ['HEAP|net/ipv6/tcp.c|100|', unnamed]
+subject_map: []
+privileges: []
+";
+
+    #[test]
+    fn test_location_queries() {
+        let priv_map: CPMPrivMap = serde_yaml::from_str(POLICY).unwrap();
+        let location = SourceLocation::parse("net/ipv4/:100-300").unwrap();
+        assert_eq!(location, SourceLocation::new("net/ipv4/").with_lines(100, 300));
+        let lines: Vec<&str> = objects_at(&priv_map, &location).iter().map(|(_, o)| o.lineno()).collect();
+        assert_eq!(lines, vec!["120", "250"]);
+
+        let names = |l: &str| domains_at(&priv_map, &SourceLocation::parse(l).unwrap())
+            .iter().map(|od| od.name()).collect::<Vec<_>>();
+        assert_eq!(names("net/ipv4/tcp.c"), vec!["od1", "od2"]);
+        assert_eq!(names("net/ipv4/tcp.c:30"), vec!["od1"]);
+        assert_eq!(names("net/ipv"), Vec::<&str>::new());
+        assert_eq!(names(""), vec!["od1", "od2", "od3"]);
+        assert!(SourceLocation::parse("a.c:9-3").is_err());
+        assert!(SourceLocation::parse("a.c:x").is_err());
+    }
+
+    #[test]
+    fn test_domains_by_file() {
+        let priv_map: CPMPrivMap = serde_yaml::from_str(POLICY).unwrap();
+        let report: String = domains_by_file(&priv_map, Some(&SourceLocation::new("net/ipv4")))
+            .iter().map(|f| f.to_string()).collect();
+        assert_eq!(report, "\
+net/ipv4/tcp.c
+  od1: 30, 120
+  od2: 400, ?
+net/ipv4/udp.c
+  od2: 250
+");
+        assert_eq!(domains_by_file(&priv_map, None).len(), 4);
+    }
+}
@@ -0,0 +1,545 @@
+// SPDX-License-Identifier: MIT
+//
+// MIT License
+//
+// © 2024 Nathan Dautenhahn & Serenitix LLC
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+use jsonschema::JSONSchema;
+use serde::Serialize;
+
+use crate::cpm_priv_map::{CPMPrivMap, LoadMode, PrivField};
+use crate::include::{has_includes, IncludedPolicy};
+use crate::hierarchy::flatten_hierarchy;
+use crate::parallel::Parallelism;
+use crate::patterns::expand_patterns;
+use crate::permissions::{effective_permissions, who_can};
+use crate::semantic::validate_semantics;
+use crate::source_map::{Section, SourceMap};
+
+/*
+ * Editor features for policy files, behind the `cpm_if_lsp` language server.
+ *
+ * The text is scanned once into occurrences: every plain token with the
+ * top-level section and the last `key:` preceding it. That is enough to
+ * tell a reference from a definition without a position-preserving YAML
+ * parser: subject domains are defined by `name` in subject_map and
+ * referenced by `parent`, `subject`, `can_call`, `can_return` and
+ * `call_context`; object domains are defined by `name` in object_map and
+ * referenced by `objects` in privileges. Positions follow the protocol:
+ * 0-based lines and UTF-16 columns.
+ *
+ * A document with includes is analyzed with its fragments read from disk,
+ * so their domains resolve, hover and complete; navigation stays within
+ * the document.
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Position {
+    pub line: u32,
+    pub character: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Range {
+    pub start: Position,
+    pub end: Position,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum DomainKind {
+    Subject,
+    Object,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DocumentDiagnostic {
+    pub range: Range,
+    // 1 error, 2 warning, as in the protocol
+    pub severity: u8,
+    pub source: &'static str,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CompletionItem {
+    pub label: String,
+    // protocol CompletionItemKind: 9 module for subject domains, 22 struct for object domains
+    pub kind: u8,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Occurrence {
+    token: String,
+    range: Range,
+    section: Option<Section>,
+    key: Option<String>,
+}
+
+impl Occurrence {
+    // the kind of domain this token defines or references, if any
+    fn role(&self) -> Option<(DomainKind, bool)> {
+        let key = self.key.as_deref()?;
+        match (self.section?, key) {
+            (Section::SubjectMap, "name") => Some((DomainKind::Subject, true)),
+            (Section::SubjectMap, "parent") => Some((DomainKind::Subject, false)),
+            (Section::ObjectMap, "name") => Some((DomainKind::Object, true)),
+            (Section::Privileges, "subject" | "can_call" | "can_return" | "call_context") =>
+                Some((DomainKind::Subject, false)),
+            (Section::Privileges, "objects") => Some((DomainKind::Object, false)),
+            _ => None,
+        }
+    }
+}
+
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || ",[]{}'\"".contains(c)
+}
+
+fn utf16_len(s: &str) -> u32 {
+    s.encode_utf16().count() as u32
+}
+
+// byte offset of a UTF-16 column, clamped to the line
+fn byte_offset(line: &str, character: u32) -> usize {
+    let mut units = 0;
+    for (i, c) in line.char_indices() {
+        if units >= character {
+            return i;
+        }
+        units += c.len_utf16() as u32;
+    }
+    line.len()
+}
+
+fn section_of(key: &str) -> Option<Section> {
+    match key {
+        "object_map" => Some(Section::ObjectMap),
+        "subject_map" => Some(Section::SubjectMap),
+        "privileges" => Some(Section::Privileges),
+        _ => None,
+    }
+}
+
+// the `include:` line, or the first line
+fn include_line(lines: &[String]) -> Range {
+    let line = lines.iter().position(|l| l.starts_with("include:")).unwrap_or(0);
+    line_range(line as u32, lines.get(line).map_or("", |l| l.as_str()))
+}
+
+fn line_range(line: u32, text: &str) -> Range {
+    Range {
+        start: Position { line, character: 0 },
+        end: Position { line, character: utf16_len(text) },
+    }
+}
+
+// plain tokens of a line with their byte spans, up to a comment
+fn tokens(line: &str) -> Vec<(usize, usize)> {
+    let mut spans = vec![];
+    let mut start: Option<usize> = None;
+    for (i, c) in line.char_indices() {
+        if c == '#' && start.is_none() && (i == 0 || line[..i].ends_with(char::is_whitespace)) {
+            break;
+        }
+        match (is_delimiter(c), start) {
+            (true, Some(s)) => {
+                spans.push((s, i));
+                start = None;
+            }
+            (false, None) => start = Some(i),
+            _ => (),
+        }
+    }
+    if let Some(s) = start {
+        spans.push((s, line.len()));
+    }
+    spans
+}
+
+pub struct PolicyDocument {
+    lines: Vec<String>,
+    source_map: SourceMap,
+    occurrences: Vec<Occurrence>,
+    priv_map: Option<CPMPrivMap>,
+    // expanded and flattened, for hover
+    analysis_map: Option<CPMPrivMap>,
+    // origins of the entries, when priv_map was assembled from includes
+    included: Option<IncludedPolicy>,
+    parse_error: Option<DocumentDiagnostic>,
+}
+
+impl PolicyDocument {
+    pub fn new(text: &str) -> Self {
+        Self::open(text, None)
+    }
+
+    /// A document read from `path`; a policy with includes is analyzed together with its fragments.
+    pub fn open(text: &str, path: Option<&str>) -> Self {
+        let lines: Vec<String> = text.lines().map(String::from).collect();
+        let mut included = None;
+        let (priv_map, parse_error) = match (has_includes(text), path) {
+            (true, Some(path)) => match IncludedPolicy::from_yaml_str(text, path, LoadMode::AllowByDefault) {
+                Ok(policy) => {
+                    let priv_map = policy.priv_map.clone();
+                    included = Some(policy);
+                    (Some(priv_map), None)
+                }
+                Err(e) => (None, Some(DocumentDiagnostic {
+                    range: include_line(&lines),
+                    severity: 1,
+                    source: "cpm_if",
+                    message: e,
+                })),
+            },
+            (true, None) => (None, Some(DocumentDiagnostic {
+                range: include_line(&lines),
+                severity: 1,
+                source: "cpm_if",
+                message: "include directives need the document's file path".to_string(),
+            })),
+            (false, _) => match serde_yaml::from_str::<CPMPrivMap>(text) {
+                Ok(priv_map) => (Some(priv_map), None),
+                Err(e) => {
+                    let position = e.location().map_or(Position { line: 0, character: 0 }, |l| Position {
+                        line: l.line().saturating_sub(1) as u32,
+                        character: l.column().saturating_sub(1) as u32,
+                    });
+                    let diagnostic = DocumentDiagnostic {
+                        range: Range { start: position, end: position },
+                        severity: 1,
+                        source: "cpm_if",
+                        message: format!("Failed to parse privilege map: {}", e),
+                    };
+                    (None, Some(diagnostic))
+                }
+            },
+        };
+        let analysis_map = priv_map.as_ref().map(|priv_map| {
+            let mut expanded = priv_map.clone();
+            let _ = expand_patterns(&mut expanded);
+            flatten_hierarchy(&expanded).unwrap_or(expanded)
+        });
+        PolicyDocument {
+            occurrences: Self::scan(&lines),
+            source_map: SourceMap::parse(text),
+            lines,
+            priv_map,
+            analysis_map,
+            included,
+            parse_error,
+        }
+    }
+
+    fn scan(lines: &[String]) -> Vec<Occurrence> {
+        let mut occurrences = vec![];
+        let mut section = None;
+        let mut key: Option<String> = None;
+        for (n, line) in lines.iter().enumerate() {
+            for (start, end) in tokens(line) {
+                let token = &line[start..end];
+                if let Some(name) = token.strip_suffix(':') {
+                    if start == 0 {
+                        section = section_of(name);
+                    }
+                    key = Some(name.to_string());
+                    continue;
+                }
+                let position = |byte: usize| Position { line: n as u32, character: utf16_len(&line[..byte]) };
+                occurrences.push(Occurrence {
+                    token: token.to_string(),
+                    range: Range { start: position(start), end: position(end) },
+                    section,
+                    key: key.clone(),
+                });
+            }
+        }
+        occurrences
+    }
+
+    fn occurrence_at(&self, position: Position) -> Option<&Occurrence> {
+        self.occurrences.iter().find(|o| {
+            o.range.start.line == position.line
+                && o.range.start.character <= position.character
+                && position.character <= o.range.end.character
+        })
+    }
+
+    fn kind_of(&self, name: &str) -> Option<DomainKind> {
+        let priv_map = self.priv_map.as_ref()?;
+        if priv_map.subject_map().iter().any(|sd| sd.name() == name) {
+            Some(DomainKind::Subject)
+        } else if priv_map.object_map().iter().any(|od| od.name() == name) {
+            Some(DomainKind::Object)
+        } else {
+            None
+        }
+    }
+
+    // the domain named at a position: a definition or reference of a defined domain
+    fn domain_at(&self, position: Position) -> Option<(&str, DomainKind)> {
+        let occurrence = self.occurrence_at(position)?;
+        let (kind, _) = occurrence.role()?;
+        (self.kind_of(&occurrence.token) == Some(kind)).then_some((occurrence.token.as_str(), kind))
+    }
+
+    /// Where the domain named at the position is defined.
+    pub fn definition(&self, position: Position) -> Option<Range> {
+        let (name, kind) = self.domain_at(position)?;
+        self.occurrences.iter()
+            .find(|o| o.token == name && o.role() == Some((kind, true)))
+            .map(|o| o.range)
+    }
+
+    /// Every definition and reference of the domain named at the position, in document order.
+    pub fn references(&self, position: Position, include_declaration: bool) -> Vec<Range> {
+        let Some((name, kind)) = self.domain_at(position) else {
+            return vec![];
+        };
+        self.occurrences.iter()
+            .filter(|o| o.token == name)
+            .filter(|o| match o.role() {
+                Some((k, definition)) => k == kind && (include_declaration || !definition),
+                None => false,
+            })
+            .map(|o| o.range)
+            .collect()
+    }
+
+    /// Markdown describing the domain named at the position and its effective privileges.
+    pub fn hover(&self, position: Position) -> Option<String> {
+        let (name, kind) = self.domain_at(position)?;
+        let priv_map = self.analysis_map.as_ref()?;
+        let mut text = String::new();
+        match kind {
+            DomainKind::Subject => {
+                let domain = priv_map.subject_map().iter().find(|sd| sd.name() == name)?;
+                text.push_str(&format!("**subject domain** `{}`\n\n", name));
+                text.push_str(&format!("subjects: {}\n\n", domain.subjects().join(", ")));
+                if let Some(parent) = self.priv_map.as_ref()
+                    .and_then(|m| m.subject_map().iter().find(|sd| sd.name() == name))
+                    .and_then(|sd| sd.parent()) {
+                    text.push_str(&format!("parent: `{}`\n\n", parent));
+                }
+                let permissions = effective_permissions(priv_map, name);
+                text.push_str("effective privileges:\n");
+                for field in PrivField::ALL {
+                    text.push_str(&format!("- {}: {}\n", field, permissions.field(field)));
+                }
+            }
+            DomainKind::Object => {
+                let domain = priv_map.object_map().iter().find(|od| od.name() == name)?;
+                text.push_str(&format!("**object domain** `{}` ({} objects)\n\n", name, domain.objects().len()));
+                for (label, field) in [("read by", PrivField::CanRead), ("written by", PrivField::CanWrite)] {
+                    let subjects = who_can(priv_map, field, name, Parallelism::Sequential);
+                    text.push_str(&format!("{}: {}\n\n", label, match subjects.is_empty() {
+                        true => "none".to_string(),
+                        false => subjects.join(", "),
+                    }));
+                }
+            }
+        }
+        Some(text.trim_end().to_string())
+    }
+
+    // the key whose value is being typed at the position
+    fn context_at(&self, position: Position) -> (Option<Section>, Option<String>) {
+        let mut context = (None, None);
+        let mut section = None;
+        for (n, line) in self.lines.iter().enumerate().take(position.line as usize + 1) {
+            let limit = match n as u32 == position.line {
+                true => byte_offset(line, position.character),
+                false => line.len(),
+            };
+            for (start, end) in tokens(line).into_iter().filter(|(s, _)| *s < limit) {
+                if let Some(name) = line[start..end].strip_suffix(':') {
+                    if start == 0 {
+                        section = section_of(name);
+                    }
+                    context = (section, Some(name.to_string()));
+                }
+            }
+        }
+        context
+    }
+
+    /// Domain names that fit at the position: subject or object domains depending on the key.
+    pub fn completions(&self, position: Position) -> Vec<CompletionItem> {
+        let Some(priv_map) = &self.priv_map else {
+            return vec![];
+        };
+        let (section, key) = self.context_at(position);
+        let probe = Occurrence { token: String::new(), range: Range { start: position, end: position }, section, key };
+        let subjects = priv_map.subject_map().iter().map(|sd| CompletionItem {
+            label: sd.name().to_string(),
+            kind: 9,
+            detail: "subject domain".to_string(),
+        });
+        let objects = priv_map.object_map().iter().map(|od| CompletionItem {
+            label: od.name().to_string(),
+            kind: 22,
+            detail: "object domain".to_string(),
+        });
+        match probe.role() {
+            Some((_, true)) => vec![],
+            Some((DomainKind::Subject, false)) => subjects.collect(),
+            Some((DomainKind::Object, false)) => objects.collect(),
+            None => vec![],
+        }
+    }
+
+    // the lines of an entry, or the first line when the entry has no block-style span
+    fn entry_range(&self, section: Section, index: usize) -> Range {
+        match self.source_map.entry(section, index) {
+            Some(span) => {
+                let line = (span.first_line - 1) as u32;
+                line_range(line, self.lines.get(line as usize).map_or("", |l| l.as_str()))
+            }
+            None => self.first_line(),
+        }
+    }
+
+    fn first_line(&self) -> Range {
+        line_range(0, self.lines.first().map_or("", |l| l.as_str()))
+    }
+
+    // narrows an entry range to the first occurrence of the name quoted in a message
+    fn narrow(&self, range: Range, message: &str, section: Section, index: usize) -> Range {
+        let Some(name) = message.split('\'').nth(1) else {
+            return range;
+        };
+        let last_line = self.source_map.entry(section, index).map_or(range.end.line, |s| (s.last_line - 1) as u32);
+        self.occurrences.iter()
+            .find(|o| o.token == name && o.range.start.line >= range.start.line && o.range.start.line <= last_line)
+            .map_or(range, |o| o.range)
+    }
+
+    /// Parse, schema and semantic errors of the document.
+    pub fn diagnostics(&self, schema: Option<&JSONSchema>) -> Vec<DocumentDiagnostic> {
+        if let Some(error) = &self.parse_error {
+            return vec![error.clone()];
+        }
+        let error = |range, message: String| DocumentDiagnostic { range, severity: 1, source: "cpm_if", message };
+        let mut diagnostics = vec![];
+
+        if let Some(schema) = schema {
+            let text = self.lines.join("\n");
+            if let Ok(value) = serde_yaml::from_str::<serde_json::Value>(&text) {
+                if let Err(errors) = schema.validate(&value) {
+                    for e in errors {
+                        let pointer = e.instance_path.to_string();
+                        // with includes, required sections may come from the fragments
+                        if self.included.is_some() && pointer.is_empty() {
+                            continue;
+                        }
+                        let mut parts = pointer.split('/').skip(1);
+                        let section = parts.next().and_then(section_of);
+                        let index = parts.next().and_then(|i| i.parse::<usize>().ok());
+                        let range = match (section, index) {
+                            (Some(section), Some(index)) => self.entry_range(section, index),
+                            _ => self.first_line(),
+                        };
+                        diagnostics.push(error(range, format!("schema: {} at {}", e, pointer)));
+                    }
+                }
+            }
+        }
+
+        if let Some(priv_map) = &self.priv_map {
+            for e in validate_semantics(priv_map, Parallelism::Sequential) {
+                // errors in fragments are shown on the include directive, naming the fragment
+                let (local, message) = match (&self.included, e.privilege) {
+                    (Some(policy), Some(index)) => match policy.origin(Section::Privileges, index) {
+                        Some(origin) if origin.file == 0 =>
+                            (Some(origin.index), format!("privileges[{}]: {}", origin.index, e.message)),
+                        _ => (None, policy.locate_error(&e)),
+                    },
+                    (None, index) => (index, e.to_string()),
+                    (Some(_), None) => (None, e.to_string()),
+                };
+                let range = match local {
+                    Some(index) => {
+                        let range = self.entry_range(Section::Privileges, index);
+                        self.narrow(range, &e.message, Section::Privileges, index)
+                    }
+                    None if self.included.is_some() => include_line(&self.lines),
+                    None => self.first_line(),
+                };
+                diagnostics.push(error(range, message));
+            }
+        }
+        diagnostics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: &str = "\
+object_map:
+  - name: od1
+    objects: ['GLOBAL|net/x.c|1|od1']
+subject_map:
+  - name: net
+    subjects: [net]
+  - {name: fs, subjects: [iput], parent: net}
+privileges:
+  - principal:
+      subject: net
+    can_call:
+      - fs
+    can_return: []
+    can_read: [{objects: [od1]}]
+    can_write: [{objects: [od1, ghost]}]
+";
+
+    fn at(line: u32, character: u32) -> Position {
+        Position { line, character }
+    }
+
+    #[test]
+    fn test_navigation() {
+        let document = PolicyDocument::new(POLICY);
+        // `fs` under can_call resolves to its flow-style definition
+        assert_eq!(document.definition(at(11, 8)), Some(Range { start: at(6, 11), end: at(6, 13) }));
+        // the subject `net` is a name, not a reference
+        assert_eq!(document.definition(at(5, 15)), None);
+        let lines: Vec<u32> = document.references(at(4, 11), true).iter().map(|r| r.start.line).collect();
+        assert_eq!(lines, vec![4, 6, 9]);
+        assert_eq!(document.references(at(14, 28), false).len(), 2);
+
+        let hover = document.hover(at(9, 16)).unwrap();
+        assert!(hover.starts_with("**subject domain** `net`"));
+        assert!(hover.contains("- can_call: [fs]"));
+        assert!(document.hover(at(6, 11)).unwrap().contains("parent: `net`"));
+        assert_eq!(document.hover(at(14, 28)).unwrap(), "**object domain** `od1` (1 objects)\n\nread by: net, fs\n\nwritten by: net, fs");
+    }
+
+    #[test]
+    fn test_completions_and_diagnostics() {
+        let document = PolicyDocument::new(POLICY);
+        let labels = |p| document.completions(p).iter().map(|c| c.label.to_string()).collect::<Vec<_>>();
+        assert_eq!(labels(at(11, 8)), vec!["net", "fs"]);
+        assert_eq!(labels(at(13, 25)), vec!["od1"]);
+        assert!(labels(at(1, 10)).is_empty());
+
+        let diagnostics = document.diagnostics(None);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range, Range { start: at(14, 32), end: at(14, 37) });
+
+        let broken = PolicyDocument::new("object_map: [\nsubject_map: []\n");
+        assert_eq!(broken.diagnostics(None).len(), 1);
+        assert!(broken.completions(at(0, 0)).is_empty());
+    }
+}
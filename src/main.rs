@@ -14,35 +14,501 @@
 // The above copyright notice and this permission notice shall be included in all
 // copies or substantial portions of the Software.
 
-use cpm_if::validate_yaml::{validate_yaml};
+use cpm_if::annotate::{annotate_tree, annotations, annotations_json};
+use cpm_if::assertions::{check_assertions, AssertionFile};
+use cpm_if::cpm_priv_map::{CPMPrivMap, LoadMode};
+use cpm_if::hierarchy::load_flattened;
+use cpm_if::include::{declares_includes, included_files, IncludedPolicy};
+use cpm_if::lint::{LintConfig, Linter, Severity};
+use cpm_if::locations::{domains_by_file, SourceLocation};
+use cpm_if::parallel::Parallelism;
+use cpm_if::query::run_query;
+use cpm_if::refinement::{check_refinement, RefinementMapping};
+use cpm_if::semantic::validate_semantics;
+use cpm_if::shell::{Shell, ShellOutput};
+use cpm_if::stats::policy_stats_file;
+use cpm_if::validate_yaml::{validate_yaml, validate_yaml_included, validate_yaml_streaming};
+use cpm_if::watch::{diff_lines, FileWatcher};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::FileHistory;
+use rustyline::validate::Validator;
+use rustyline::{Editor, Helper};
+use std::cell::RefCell;
 use std::env;
+use std::fs;
+use std::path::PathBuf;
 use std::process;
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+
+const USAGE: &str = "\
+Usage: cpm_if validate [--stream] [--semantic] [--deny-by-default] [--jobs <n>] [--watch] <schema.json> <file.yaml>
+       cpm_if lint <file.yaml> [--config <lint.yaml>] [--deny-by-default] [--full] [--watch]
+       cpm_if stats <file.yaml> [--format text|json]
+       cpm_if query <file.yaml> '<query>' [--format text|json]
+       cpm_if locate <file.yaml> [<path>[:<line>[-<line>]]] [--format text|json]
+       cpm_if annotate <file.yaml> <annotations.json> [--source <dir> --out <dir>]
+       cpm_if shell <file.yaml>
+       cpm_if check <file.yaml> <assertions.yaml> [--watch]
+       cpm_if refine <concrete.yaml> <abstract.yaml> <mapping.yaml>";
+
+// flags that take a value
+const VALUE_FLAGS: &[&str] = &["--config", "--format", "--jobs", "--out", "--source"];
+
+/*
+ * Command line arguments split into positional arguments and `--flag` or
+ * `--flag <value>` options, in any order.
+ */
+struct Args {
+    positional: Vec<String>,
+    flags: Vec<(String, Option<String>)>,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut positional = vec![];
+        let mut flags = vec![];
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if !arg.starts_with("--") {
+                positional.push(arg.to_string());
+            } else if VALUE_FLAGS.contains(&arg.as_str()) {
+                let value = iter.next().ok_or_else(|| format!("Missing value for {}", arg))?;
+                flags.push((arg.to_string(), Some(value.to_string())));
+            } else {
+                flags.push((arg.to_string(), None));
+            }
+        }
+        Ok(Self { positional, flags })
+    }
+
+    // reject flags the command does not understand
+    fn allow(&self, known: &[&str]) -> Result<(), String> {
+        match self.flags.iter().find(|(f, _)| !known.contains(&f.as_str())) {
+            Some((flag, _)) => Err(format!("Unknown option: {}\n{}", flag, USAGE)),
+            None => Ok(()),
+        }
+    }
+
+    fn has(&self, flag: &str) -> bool {
+        self.flags.iter().any(|(f, _)| f == flag)
+    }
+
+    fn value(&self, flag: &str) -> Option<&str> {
+        self.flags.iter().rev().find(|(f, _)| f == flag).and_then(|(_, v)| v.as_deref())
+    }
+
+    // how omitted privilege fields are read
+    fn load_mode(&self) -> LoadMode {
+        match self.has("--deny-by-default") {
+            true => LoadMode::DenyByDefault,
+            false => LoadMode::AllowByDefault,
+        }
+    }
+
+    fn parallelism(&self) -> Result<Parallelism, String> {
+        match self.value("--jobs") {
+            None => Ok(Parallelism::Sequential),
+            Some("auto") => Ok(Parallelism::available()),
+            Some(n) => n.parse::<usize>()
+                .map(Parallelism::Threads)
+                .map_err(|_| format!("Invalid value for --jobs: {}", n)),
+        }
+    }
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() != 4 {
-        eprintln!("Usage: cpm_if validate <schema.json> <file.yaml>");
+    if args.len() < 2 {
+        eprintln!("{}", USAGE);
         process::exit(1);
     }
 
     let command = &args[1];
-    let schema_file = &args[2];
-    let yaml_file = &args[3];
-
-    match command.as_str() {
-        "validate" => {
-            if let Err(e) = validate_yaml(schema_file, yaml_file) {
-                eprintln!("Validation failed: {}", e);
-                process::exit(1);
-            } else {
-                println!("Validation succeeded!");
-            }
+    let rest = match Args::parse(&args[2..]) {
+        Ok(rest) => rest,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
         }
+    };
+
+    let result = match command.as_str() {
+        "validate" => run_validate(&rest),
+        "lint" => run_lint(&rest),
+        "stats" => run_stats(&rest),
+        "query" => run_query_command(&rest),
+        "locate" => run_locate(&rest),
+        "annotate" => run_annotate(&rest),
+        "shell" => run_shell(&rest),
+        "check" => run_check(&rest),
+        "refine" => run_refine(&rest),
         _ => {
             eprintln!("Unknown command: {}", command);
-            eprintln!("Usage: cpm_if validate <schema.json> <file.yaml>");
+            eprintln!("{}", USAGE);
             process::exit(1);
         }
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+// loads a policy for analysis, warning about patterns that match nothing
+fn load_policy(yaml_file: &str) -> Result<CPMPrivMap, String> {
+    let (priv_map, unmatched) = load_flattened(yaml_file, LoadMode::AllowByDefault)?;
+    for pattern in unmatched {
+        eprintln!("warning: {}: {}", yaml_file, pattern);
+    }
+    Ok(priv_map)
+}
+
+// interval between polls of the watched files
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/*
+ * Runs a command that reports through output lines and a final error,
+ * once or, with `--watch`, again whenever one of its input files changes.
+ * The first input is the policy file, whose included fragments are watched
+ * too, as they are found. Reruns print only the lines that appeared or
+ * disappeared since the previous run, prefixed with `+` or `-`.
+ */
+fn run_watchable<F>(args: &Args, inputs: &[&str], mut run: F) -> Result<(), String>
+where
+    F: FnMut(&mut Vec<String>) -> Result<(), String>,
+{
+    if !args.has("--watch") {
+        let mut out = vec![];
+        let result = run(&mut out);
+        out.iter().for_each(|line| println!("{}", line));
+        return result;
+    }
+
+    // in watch mode a failure is part of the output, not the end of the command
+    let mut run_lines = || {
+        let mut out = vec![];
+        if let Err(e) = run(&mut out) {
+            out.extend(e.lines().map(String::from));
+        }
+        out
+    };
+
+    let mut watcher = FileWatcher::new(inputs);
+    let mut previous = run_lines();
+    included_files(inputs[0]).iter().for_each(|path| watcher.add(path));
+    previous.iter().for_each(|line| println!("{}", line));
+    let names: Vec<String> = watcher.paths().map(|p| p.display().to_string()).collect();
+    eprintln!("Watching {} for changes (Ctrl-C to stop)", names.join(", "));
+    loop {
+        thread::sleep(WATCH_INTERVAL);
+        let changed = watcher.poll();
+        if changed.is_empty() {
+            continue;
+        }
+        let names: Vec<String> = changed.iter().map(|p| p.display().to_string()).collect();
+        println!("\n{} changed", names.join(", "));
+        let current = run_lines();
+        included_files(inputs[0]).iter().for_each(|path| watcher.add(path));
+        let diff = diff_lines(&previous, &current);
+        if diff.is_empty() {
+            println!("(no changes in output)");
+        }
+        diff.iter().for_each(|line| println!("{}", line));
+        previous = current;
+    }
+}
+
+fn run_validate(args: &Args) -> Result<(), String> {
+    args.allow(&["--stream", "--semantic", "--deny-by-default", "--jobs", "--watch"])?;
+    let [schema_file, yaml_file] = args.positional.as_slice() else {
+        return Err(USAGE.to_string());
+    };
+    if args.has("--stream") && args.has("--semantic") {
+        return Err("--stream cannot be combined with --semantic, which needs the whole policy".to_string());
+    }
+    let par = args.parallelism()?;
+    run_watchable(args, &[yaml_file, schema_file], |out| validate(args, schema_file, yaml_file, par, out))
+}
+
+fn validate(args: &Args, schema_file: &str, yaml_file: &str, par: Parallelism, out: &mut Vec<String>)
+    -> Result<(), String>
+{
+    // multi-file policies are checked file by file, so errors name the file they are in
+    let included = match declares_includes(yaml_file)? {
+        true if args.has("--stream") => {
+            return Err(format!("--stream cannot validate {}, which has include directives", yaml_file));
+        }
+        true => Some(IncludedPolicy::load(yaml_file, args.load_mode())?),
+        false => None,
+    };
+    let result = if let Some(policy) = &included {
+        validate_yaml_included(schema_file, policy)
+    } else if args.has("--stream") {
+        validate_yaml_streaming(schema_file, yaml_file)
+    } else {
+        validate_yaml(schema_file, yaml_file)
+    };
+    result.map_err(|e| format!("Validation failed: {}", e))?;
+
+    if args.has("--semantic") {
+        let policy = match included {
+            Some(policy) => policy,
+            None => IncludedPolicy::load(yaml_file, args.load_mode())?,
+        };
+        let errors = validate_semantics(&policy.priv_map, par);
+        if !errors.is_empty() {
+            let messages: Vec<String> = errors.iter().map(|e| policy.locate_error(e)).collect();
+            return Err(format!(
+                "Validation failed with the following semantic errors:\n{}",
+                messages.join("\n")
+            ));
+        }
+    }
+    out.push("Validation succeeded!".to_string());
+    Ok(())
+}
+
+fn run_lint(args: &Args) -> Result<(), String> {
+    args.allow(&["--config", "--deny-by-default", "--full", "--watch"])?;
+    let [yaml_file] = args.positional.as_slice() else {
+        return Err(USAGE.to_string());
+    };
+    let mut inputs = vec![yaml_file.as_str()];
+    inputs.extend(args.value("--config"));
+    run_watchable(args, &inputs, |out| lint(args, yaml_file, out))
+}
+
+fn lint(args: &Args, yaml_file: &str, out: &mut Vec<String>) -> Result<(), String> {
+    let config = match args.value("--config") {
+        Some(config_file) => LintConfig::load(config_file)?,
+        None => LintConfig::default(),
+    };
+
+    let linter = Linter::new(config);
+    linter.check_config()?;
+
+    // streamed unless the rules that need the whole policy are asked for
+    let diagnostics = if args.has("--full") {
+        linter.lint_included(&IncludedPolicy::load(yaml_file, args.load_mode())?)
+    } else if declares_includes(yaml_file)? {
+        return Err(format!("{} has include directives, which are only linted with --full", yaml_file));
+    } else {
+        linter.lint_file(yaml_file, args.load_mode())?
+    };
+    for diagnostic in &diagnostics {
+        out.push(format!("{}:{}", diagnostic.file.as_deref().unwrap_or(yaml_file), diagnostic));
+    }
+
+    let errors = diagnostics.iter().filter(|d| d.severity == Severity::Error).count();
+    if errors > 0 {
+        return Err(format!("Lint failed with {} error(s)", errors));
+    }
+    Ok(())
+}
+
+fn run_stats(args: &Args) -> Result<(), String> {
+    args.allow(&["--format"])?;
+    let [yaml_file] = args.positional.as_slice() else {
+        return Err(USAGE.to_string());
+    };
+    let stats = policy_stats_file(yaml_file)?;
+    match args.value("--format").unwrap_or("text") {
+        "text" => print!("{}", stats),
+        "json" => println!("{}", stats.to_json()?),
+        other => return Err(format!("Unknown format: {}", other)),
+    }
+    Ok(())
+}
+
+fn run_query_command(args: &Args) -> Result<(), String> {
+    args.allow(&["--format"])?;
+    let [yaml_file, query] = args.positional.as_slice() else {
+        return Err(USAGE.to_string());
+    };
+    let priv_map = load_policy(yaml_file)?;
+    let result = run_query(&priv_map, query).map_err(|e| format!("Invalid query: {}", e))?;
+    match args.value("--format").unwrap_or("text") {
+        "text" => print!("{}", result),
+        "json" => println!("{}", serde_json::to_string_pretty(&result)
+            .map_err(|e| format!("Failed to serialize query result: {}", e))?),
+        other => return Err(format!("Unknown format: {}", other)),
+    }
+    Ok(())
+}
+
+fn run_check(args: &Args) -> Result<(), String> {
+    args.allow(&["--watch"])?;
+    let [yaml_file, assertions_file] = args.positional.as_slice() else {
+        return Err(USAGE.to_string());
+    };
+    run_watchable(args, &[yaml_file, assertions_file], |out| check(yaml_file, assertions_file, out))
+}
+
+fn check(yaml_file: &str, assertions_file: &str, out: &mut Vec<String>) -> Result<(), String> {
+    let assertions = AssertionFile::load(assertions_file)?;
+    let priv_map = load_policy(yaml_file)?;
+
+    let results = check_assertions(&priv_map, &assertions);
+    let mut failed = 0;
+    for result in &results {
+        if result.vacuous {
+            eprintln!("warning: assertion '{}' matches no domain", result.name);
+        }
+        if !result.holds() {
+            failed += 1;
+            for violation in &result.violations {
+                out.push(format!("{}:{}", yaml_file, violation));
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{} of {} assertion(s) violated", failed, results.len()));
+    }
+    out.push(format!("All {} assertion(s) hold", results.len()));
+    Ok(())
+}
+
+fn run_refine(args: &Args) -> Result<(), String> {
+    args.allow(&[])?;
+    let [concrete_file, abstract_file, mapping_file] = args.positional.as_slice() else {
+        return Err(USAGE.to_string());
+    };
+    let mapping = RefinementMapping::load(mapping_file)?;
+    let concrete = load_policy(concrete_file)?;
+    let abstract_map = load_policy(abstract_file)?;
+
+    let violations = check_refinement(&concrete, &abstract_map, &mapping)?;
+    for violation in &violations {
+        println!("{}:{}", concrete_file, violation);
+    }
+    if !violations.is_empty() {
+        return Err(format!("{} privilege(s) not permitted by {}", violations.len(), abstract_file));
+    }
+    println!("{} refines {}", concrete_file, abstract_file);
+    Ok(())
+}
+
+fn run_locate(args: &Args) -> Result<(), String> {
+    args.allow(&["--format"])?;
+    let (yaml_file, location) = match args.positional.as_slice() {
+        [yaml_file] => (yaml_file, None),
+        [yaml_file, location] => (yaml_file, Some(SourceLocation::parse(location)?)),
+        _ => return Err(USAGE.to_string()),
+    };
+    let priv_map = load_policy(yaml_file)?;
+    let files = domains_by_file(&priv_map, location.as_ref());
+    match args.value("--format").unwrap_or("text") {
+        "text" => files.iter().for_each(|file| print!("{}", file)),
+        "json" => println!("{}", serde_json::to_string_pretty(&files)
+            .map_err(|e| format!("Failed to serialize locations: {}", e))?),
+        other => return Err(format!("Unknown format: {}", other)),
+    }
+    Ok(())
+}
+
+fn run_annotate(args: &Args) -> Result<(), String> {
+    args.allow(&["--source", "--out"])?;
+    let [yaml_file, json_file] = args.positional.as_slice() else {
+        return Err(USAGE.to_string());
+    };
+    let tree = match (args.value("--source"), args.value("--out")) {
+        (None, None) => None,
+        (Some(source), Some(out)) => Some((source, out)),
+        _ => return Err("--source and --out must be given together".to_string()),
+    };
+    let priv_map = load_policy(yaml_file)?;
+    let files = annotations(&priv_map, Parallelism::Sequential);
+    fs::write(json_file, annotations_json(&files)?)
+        .map_err(|e| format!("Failed to write {}: {}", json_file, e))?;
+    println!("Wrote annotations for {} file(s) to {}", files.len(), json_file);
+
+    if let Some((source, out)) = tree {
+        let report = annotate_tree(&files, source, out)?;
+        for path in &report.skipped_files {
+            eprintln!("warning: {} not found under {}", path, source);
+        }
+        for site in &report.skipped_sites {
+            eprintln!("warning: {}: no such line", site);
+        }
+        println!("Annotated {} site(s) in {} file(s) under {}", report.sites_annotated, report.files_written, out);
+    }
+    Ok(())
+}
+
+// completes shell commands and domain names from the loaded policy
+struct ShellHelper {
+    shell: Rc<RefCell<Shell>>,
+}
+
+impl Completer for ShellHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &rustyline::Context<'_>)
+        -> rustyline::Result<(usize, Vec<String>)>
+    {
+        Ok(self.shell.borrow().complete(line, pos))
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+impl Highlighter for ShellHelper {}
+impl Validator for ShellHelper {}
+impl Helper for ShellHelper {}
+
+fn history_file() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".cpm_if_history"))
+}
+
+fn run_shell(args: &Args) -> Result<(), String> {
+    args.allow(&[])?;
+    let [yaml_file] = args.positional.as_slice() else {
+        return Err(USAGE.to_string());
+    };
+    let shell = Rc::new(RefCell::new(Shell::load(yaml_file)?));
+    let mut editor: Editor<ShellHelper, FileHistory> = Editor::new()
+        .map_err(|e| format!("Failed to start shell: {}", e))?;
+    editor.set_helper(Some(ShellHelper { shell: Rc::clone(&shell) }));
+    let history = history_file();
+    if let Some(history) = &history {
+        // a missing history file is expected on first use
+        let _ = editor.load_history(history);
+    }
+
+    {
+        let shell = shell.borrow();
+        let priv_map = shell.priv_map();
+        println!("Loaded {}: {} object domains, {} subject domains, {} privileges. Type `help` for commands.",
+                 yaml_file, priv_map.object_map().len(), priv_map.subject_map().len(), priv_map.privileges().len());
+    }
+    loop {
+        let line = match editor.readline("cpm> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(format!("Failed to read input: {}", e)),
+        };
+        if !line.trim().is_empty() {
+            let _ = editor.add_history_entry(line.as_str());
+        }
+        match shell.borrow_mut().execute(&line) {
+            Ok(ShellOutput::Text(text)) if text.is_empty() => (),
+            Ok(ShellOutput::Text(text)) => println!("{}", text),
+            Ok(ShellOutput::Quit) => break,
+            Err(e) => eprintln!("error: {}", e),
+        }
+    }
+
+    if let Some(history) = &history {
+        editor.save_history(history).map_err(|e| format!("Failed to save history: {}", e))?;
     }
-}
\ No newline at end of file
+    Ok(())
+}
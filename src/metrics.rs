@@ -0,0 +1,297 @@
+// SPDX-License-Identifier: MIT
+//
+// MIT License
+//
+// © 2024 Nathan Dautenhahn & Serenitix LLC
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::cpm_priv_map::{CPMPrivMap, PrivField};
+use crate::parallel::Parallelism;
+use crate::permissions::{all_effective_permissions, EffectivePermissions};
+
+/*
+ * Compartmentalization quality metrics.
+ *
+ * All metrics are computed over subject domains (every entry of subject_map,
+ * whether or not it has privileges) from their effective permissions, i.e.
+ * the union of their privileges with contexts ignored and `all` expanded to
+ * every domain of the map. Objects are identified by their full ObjectID, so
+ * an object listed in several object domains counts once.
+ *
+ * Reachable fraction
+ *   |objects in domains S can read or write| / |objects|, per subject domain
+ *   S; 0 when the map has no objects.
+ *
+ * Privilege overlap
+ *   The privilege set of S is the set of (field, domain) pairs it is granted
+ *   across can_call, can_return, can_read and can_write. The overlap of two
+ *   subject domains is the Jaccard index |A ∩ B| / |A ∪ B| of their
+ *   privilege sets (0 when both are empty). Mean and max are taken over all
+ *   unordered pairs of distinct subject domains.
+ *
+ * Writable shared objects
+ *   Objects writable by two or more subject domains.
+ *
+ * Call-graph cut
+ *   The call graph has an edge S -> T whenever S may call T. The cut of S is
+ *   the number of edges crossing its boundary, split into outgoing (S -> T)
+ *   and incoming (T -> S) with T != S. The total cut counts every edge
+ *   between distinct domains once.
+ */
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Reachability {
+    pub subject: String,
+    pub reachable_objects: usize,
+    pub fraction: f64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Overlap {
+    pub mean: f64,
+    pub max: f64,
+    // the first pair reaching `max`, in subject map order
+    pub max_pair: Option<(String, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CallCut {
+    pub subject: String,
+    pub outgoing: usize,
+    pub incoming: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QualityMetrics {
+    pub objects: usize,
+    pub reachability: Vec<Reachability>,
+    pub mean_reachable_fraction: f64,
+    pub overlap: Overlap,
+    pub writable_shared_objects: usize,
+    pub call_cuts: Vec<CallCut>,
+    pub total_call_cut: usize,
+}
+
+/// Computes every metric of the module; see the module comment for definitions.
+pub fn quality_metrics(priv_map: &CPMPrivMap, par: Parallelism) -> QualityMetrics {
+    let permissions = all_effective_permissions(priv_map, par);
+    let objects = objects_by_domain(priv_map);
+    let total_objects = objects.values().flatten().collect::<HashSet<_>>().len();
+
+    let reachability = reachability(priv_map, &permissions, &objects, total_objects);
+    let mean_reachable_fraction = if reachability.is_empty() {
+        0.0
+    } else {
+        reachability.iter().map(|r| r.fraction).sum::<f64>() / reachability.len() as f64
+    };
+    let (call_cuts, total_call_cut) = call_cuts(priv_map, &permissions);
+
+    QualityMetrics {
+        objects: total_objects,
+        reachability,
+        mean_reachable_fraction,
+        overlap: overlap(priv_map, &permissions),
+        writable_shared_objects: writable_shared_objects(priv_map, &permissions, &objects),
+        call_cuts,
+        total_call_cut,
+    }
+}
+
+// object domain name -> keys of its objects
+fn objects_by_domain(priv_map: &CPMPrivMap) -> HashMap<String, Vec<String>> {
+    let mut objects: HashMap<String, Vec<String>> = HashMap::new();
+    for od in priv_map.object_map() {
+        objects.entry(od.name().to_string()).or_default().extend(od.objects().iter().map(|o| o.to_string()));
+    }
+    objects
+}
+
+// keys of the objects in the given domains
+fn objects_in<'a>(domains: &[&str], objects: &'a HashMap<String, Vec<String>>) -> HashSet<&'a str> {
+    domains.iter()
+        .filter_map(|d| objects.get(*d))
+        .flatten()
+        .map(|o| o.as_str())
+        .collect()
+}
+
+fn object_domain_names(priv_map: &CPMPrivMap) -> impl Iterator<Item = &str> {
+    priv_map.object_map().iter().map(|od| od.name())
+}
+
+fn subject_domain_names(priv_map: &CPMPrivMap) -> impl Iterator<Item = &str> {
+    priv_map.subject_map().iter().map(|sd| sd.name())
+}
+
+fn reachability(priv_map: &CPMPrivMap, permissions: &[(String, EffectivePermissions)],
+                objects: &HashMap<String, Vec<String>>, total_objects: usize) -> Vec<Reachability> {
+    permissions.iter()
+        .map(|(subject, perms)| {
+            let mut domains = perms.can_read.expand(object_domain_names(priv_map));
+            domains.extend(perms.can_write.expand(object_domain_names(priv_map)));
+            let reachable_objects = objects_in(&domains, objects).len();
+            let fraction = if total_objects == 0 {
+                0.0
+            } else {
+                reachable_objects as f64 / total_objects as f64
+            };
+            Reachability { subject: subject.to_string(), reachable_objects, fraction }
+        })
+        .collect()
+}
+
+fn privilege_set<'a>(priv_map: &'a CPMPrivMap, perms: &'a EffectivePermissions) -> BTreeSet<(PrivField, &'a str)> {
+    let mut set = BTreeSet::new();
+    for field in PrivField::ALL {
+        let domains = match field {
+            PrivField::CanCall | PrivField::CanReturn => perms.field(field).expand(subject_domain_names(priv_map)),
+            PrivField::CanRead | PrivField::CanWrite => perms.field(field).expand(object_domain_names(priv_map)),
+        };
+        set.extend(domains.into_iter().map(|d| (field, d)));
+    }
+    set
+}
+
+fn jaccard<T: Ord>(a: &BTreeSet<T>, b: &BTreeSet<T>) -> f64 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(b).count() as f64 / union as f64
+}
+
+fn overlap(priv_map: &CPMPrivMap, permissions: &[(String, EffectivePermissions)]) -> Overlap {
+    let sets: Vec<_> = permissions.iter().map(|(_, perms)| privilege_set(priv_map, perms)).collect();
+    let mut result = Overlap::default();
+    let mut sum = 0.0;
+    let mut pairs = 0;
+    for i in 0..sets.len() {
+        for j in (i + 1)..sets.len() {
+            let index = jaccard(&sets[i], &sets[j]);
+            sum += index;
+            pairs += 1;
+            if result.max_pair.is_none() || index > result.max {
+                result.max = index;
+                result.max_pair = Some((permissions[i].0.to_string(), permissions[j].0.to_string()));
+            }
+        }
+    }
+    if pairs > 0 {
+        result.mean = sum / pairs as f64;
+    }
+    result
+}
+
+fn writable_shared_objects(priv_map: &CPMPrivMap, permissions: &[(String, EffectivePermissions)],
+                           objects: &HashMap<String, Vec<String>>) -> usize {
+    let mut writers: HashMap<&str, usize> = HashMap::new();
+    for (_, perms) in permissions {
+        let domains = perms.can_write.expand(object_domain_names(priv_map));
+        for object in objects_in(&domains, objects) {
+            *writers.entry(object).or_default() += 1;
+        }
+    }
+    writers.values().filter(|&&n| n >= 2).count()
+}
+
+fn call_cuts(priv_map: &CPMPrivMap, permissions: &[(String, EffectivePermissions)]) -> (Vec<CallCut>, usize) {
+    let mut cuts: Vec<CallCut> = permissions.iter()
+        .map(|(subject, _)| CallCut { subject: subject.to_string(), outgoing: 0, incoming: 0 })
+        .collect();
+    let index: HashMap<&str, usize> = permissions.iter().enumerate().map(|(i, (s, _))| (s.as_str(), i)).collect();
+
+    let mut total = 0;
+    for (caller, (subject, perms)) in permissions.iter().enumerate() {
+        let callees: BTreeSet<&str> = perms.can_call.expand(subject_domain_names(priv_map)).into_iter().collect();
+        for callee in callees.into_iter().filter(|c| *c != subject) {
+            // calls to undefined domains are reported by the semantic validator
+            let Some(&callee) = index.get(callee) else { continue };
+            cuts[caller].outgoing += 1;
+            cuts[callee].incoming += 1;
+            total += 1;
+        }
+    }
+    (cuts, total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: &str = "
+object_map:
+  - name: od1
+    objects: [GLOBAL|a.c|1|x, GLOBAL|a.c|2|y]
+  - name: od2
+    objects: [GLOBAL|a.c|2|y, HEAP|b.c|3|z]
+  - name: od3
+    objects: [GLOBAL|c.c|4|w]
+subject_map:
+  - name: s1
+    subjects: [f1]
+  - name: s2
+    subjects: [f2]
+  - name: s3
+    subjects: [f3]
+privileges:
+  - principal:
+      subject: s1
+    can_call: [s1, s2]
+    can_return: []
+    can_read:
+      - objects: [od1]
+    can_write:
+      - objects: [od1]
+  - principal:
+      subject: s2
+    can_call: []
+    can_return: [s1]
+    can_read:
+      - objects: [od1]
+    can_write:
+      - objects: [od2]
+  - principal:
+      subject: s3
+    can_call: all
+    can_return: []
+    can_read: []
+    can_write: []
+";
+
+    #[test]
+    fn test_quality_metrics() {
+        let priv_map: CPMPrivMap = serde_yaml::from_str(POLICY).unwrap();
+        let metrics = quality_metrics(&priv_map, Parallelism::Sequential);
+        assert_eq!(metrics.objects, 4);
+
+        let reachable: Vec<usize> = metrics.reachability.iter().map(|r| r.reachable_objects).collect();
+        assert_eq!(reachable, vec![2, 3, 0]);
+        assert_eq!(metrics.reachability[1].fraction, 0.75);
+        assert!((metrics.mean_reachable_fraction - (0.5 + 0.75) / 3.0).abs() < 1e-9);
+
+        // s1 = {call s1, call s2, read od1, write od1}, s2 = {return s1, read od1, write od2},
+        // s3 = {call s1, call s2, call s3}
+        assert!((metrics.overlap.max - 2.0 / 5.0).abs() < 1e-9);
+        assert_eq!(metrics.overlap.max_pair, Some(("s1".to_string(), "s3".to_string())));
+        assert!((metrics.overlap.mean - (1.0 / 6.0 + 2.0 / 5.0) / 3.0).abs() < 1e-9);
+
+        // y is in od1 (written by s1) and od2 (written by s2)
+        assert_eq!(metrics.writable_shared_objects, 1);
+
+        let cuts: Vec<(usize, usize)> = metrics.call_cuts.iter().map(|c| (c.outgoing, c.incoming)).collect();
+        assert_eq!(cuts, vec![(1, 1), (0, 2), (2, 0)]);
+        assert_eq!(metrics.total_call_cut, 3);
+    }
+}
@@ -0,0 +1,400 @@
+// SPDX-License-Identifier: MIT
+//
+// MIT License
+//
+// © 2024 Nathan Dautenhahn & Serenitix LLC
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+
+use crate::cpm_priv_map::{CPMPrivMap, CallRetPrivField, Object, PrivField, Principal, Privilege, RWPrivField};
+
+/*
+ * Least-privilege tightening of `all` grants.
+ *
+ * Given the privileges actually exercised (from another policy or from a
+ * trace via `trace::synthesize_privileges`), every `all` in `can_call`,
+ * `can_return`, `can_read` or `can_write` of the policy is replaced by the
+ * list of domains the same principal (subject and execution context) was
+ * observed using. Observed reads and writes keep their object context, and
+ * only objects with equal contexts are merged. Fields that are already lists
+ * are left alone, as are fields whose observed use is itself `all`. The size
+ * of an `all` grant is the number of subject domains (for call/return) or
+ * object domains (for read/write) in the policy.
+ */
+
+/// A proposed replacement of one `all` field of one privilege.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct FieldProposal {
+    pub field: PrivField,
+    pub domains: Vec<String>,
+    pub granted: usize,
+    // read and write grants as they will be written, one per object context
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub objects: Vec<Object>,
+}
+
+impl FieldProposal {
+    pub fn removed(&self) -> usize {
+        self.granted.saturating_sub(self.domains.len())
+    }
+}
+
+/// All proposals for a single privilege, identified by its index in the policy.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct PrivilegeProposal {
+    pub index: usize,
+    pub subject: String,
+    pub fields: Vec<FieldProposal>,
+}
+
+/// Privilege removed per principal, summed over all of its privileges.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct PrincipalReduction {
+    pub subject: String,
+    pub granted: usize,
+    pub proposed: usize,
+}
+
+impl PrincipalReduction {
+    pub fn removed(&self) -> usize {
+        self.granted.saturating_sub(self.proposed)
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct MinimizationReport {
+    pub proposals: Vec<PrivilegeProposal>,
+}
+
+impl MinimizationReport {
+    /// Per principal totals, in the order principals first appear in the proposals.
+    pub fn reductions(&self) -> Vec<PrincipalReduction> {
+        let mut reductions: Vec<PrincipalReduction> = vec![];
+        for proposal in &self.proposals {
+            let idx = match reductions.iter().position(|r| r.subject == proposal.subject) {
+                Some(idx) => idx,
+                None => {
+                    reductions.push(PrincipalReduction {
+                        subject: proposal.subject.to_string(),
+                        granted: 0,
+                        proposed: 0,
+                    });
+                    reductions.len() - 1
+                }
+            };
+            for field in &proposal.fields {
+                reductions[idx].granted += field.granted;
+                reductions[idx].proposed += field.domains.len();
+            }
+        }
+        reductions
+    }
+
+    pub fn total_removed(&self) -> usize {
+        self.proposals.iter()
+            .flat_map(|p| p.fields.iter())
+            .map(|f| f.removed())
+            .sum()
+    }
+
+    /// Rewrites the proposed fields of `policy` in place.
+    pub fn apply(&self, policy: &mut CPMPrivMap) {
+        for proposal in &self.proposals {
+            let Some(privilege) = policy.privileges.get_mut(proposal.index) else {
+                continue;
+            };
+            for field in &proposal.fields {
+                match field.field {
+                    PrivField::CanCall => privilege.can_call = CallRetPrivField::List(field.domains.clone()),
+                    PrivField::CanReturn => privilege.can_return = CallRetPrivField::List(field.domains.clone()),
+                    PrivField::CanRead => privilege.can_read = RWPrivField::List(field.objects.clone()),
+                    PrivField::CanWrite => privilege.can_write = RWPrivField::List(field.objects.clone()),
+                }
+            }
+        }
+    }
+}
+
+/*
+ * Domains a principal was observed using per field; None means observed
+ * `all`. Entries are grouped by object context, in the order each context
+ * was first seen; call and return targets have no context of their own and
+ * are kept as a single group.
+ */
+#[derive(Default)]
+struct ObservedUse {
+    fields: HashMap<PrivField, Option<Vec<Object>>>,
+}
+
+impl ObservedUse {
+    fn record(&mut self, field: PrivField, objects: Option<Vec<Object>>) {
+        let entry = self.fields.entry(field).or_insert_with(|| Some(vec![]));
+        match (entry.as_mut(), objects) {
+            (Some(groups), Some(objects)) => {
+                for object in objects {
+                    let idx = match groups.iter().position(|g| g.object_context() == object.object_context()) {
+                        Some(idx) => idx,
+                        None => {
+                            groups.push(Object::with_context(vec![], object.object_context().clone()));
+                            groups.len() - 1
+                        }
+                    };
+                    let names: BTreeSet<String> = groups[idx].objects().iter()
+                        .chain(object.objects())
+                        .cloned()
+                        .collect();
+                    groups[idx] = Object::with_context(names.into_iter().collect(), object.object_context().clone());
+                }
+            }
+            _ => *entry = None,
+        }
+    }
+
+    // the distinct domain names and the grouped objects, or None for `all`
+    fn get(&self, field: PrivField) -> Option<(Vec<String>, Vec<Object>)> {
+        let groups = match self.fields.get(&field) {
+            Some(Some(groups)) => groups.clone(),
+            Some(None) => return None,
+            None => vec![],
+        };
+        let groups: Vec<Object> = groups.into_iter().filter(|g| !g.objects().is_empty()).collect();
+        let domains: BTreeSet<String> = groups.iter().flat_map(|g| g.objects().iter().cloned()).collect();
+        Some((domains.into_iter().collect(), groups))
+    }
+}
+
+/// Proposes `List` replacements for every `all` grant of `policy` from the
+/// privileges in `observed`.
+pub fn propose_minimization(policy: &CPMPrivMap, observed: &[Privilege]) -> MinimizationReport {
+    let mut usage: HashMap<&Principal, ObservedUse> = HashMap::new();
+    for privilege in observed {
+        let entry = usage.entry(privilege.principal()).or_default();
+        for field in PrivField::ALL {
+            entry.record(field, field_objects(privilege, field));
+        }
+    }
+
+    let no_use = ObservedUse::default();
+    let subject_domains = policy.subject_map().len();
+    let object_domains = policy.object_map().len();
+
+    let proposals = policy.privileges().iter()
+        .enumerate()
+        .filter_map(|(index, privilege)| {
+            let subject = privilege.principal().subject();
+            let used = usage.get(privilege.principal()).unwrap_or(&no_use);
+            let fields: Vec<FieldProposal> = PrivField::ALL.into_iter()
+                .filter(|&field| privilege.grants_all(field))
+                .filter_map(|field| used.get(field).map(|(domains, objects)| match field {
+                    PrivField::CanCall | PrivField::CanReturn =>
+                        FieldProposal { field, domains, granted: subject_domains, objects: vec![] },
+                    PrivField::CanRead | PrivField::CanWrite =>
+                        FieldProposal { field, domains, granted: object_domains, objects },
+                }))
+                .collect();
+            if fields.is_empty() {
+                None
+            } else {
+                Some(PrivilegeProposal { index, subject: subject.to_string(), fields })
+            }
+        })
+        .collect();
+
+    MinimizationReport { proposals }
+}
+
+// the entries of a field, or None for `all`
+fn field_objects(privilege: &Privilege, field: PrivField) -> Option<Vec<Object>> {
+    let callret = |f: &CallRetPrivField| match f {
+        CallRetPrivField::List(list) => Some(vec![Object::new(list.clone())]),
+        CallRetPrivField::All => None,
+    };
+    let rw = |f: &RWPrivField| match f {
+        RWPrivField::List(list) => Some(list.clone()),
+        RWPrivField::All => None,
+    };
+    match field {
+        PrivField::CanCall => callret(privilege.can_call()),
+        PrivField::CanReturn => callret(privilege.can_return()),
+        PrivField::CanRead => rw(privilege.can_read()),
+        PrivField::CanWrite => rw(privilege.can_write()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpm_priv_map::{Context, ContextField};
+
+    const POLICY: &str = "
+object_map:
+  - name: od1
+    objects: [a]
+  - name: od2
+    objects: [b]
+  - name: od3
+    objects: [c]
+subject_map:
+  - name: s1
+    subjects: [f1]
+  - name: s2
+    subjects: [f2]
+privileges:
+  - principal:
+      subject: s1
+    can_call: [s2]
+  - principal:
+      subject: s2
+    can_call: []
+    can_return: []
+    can_read: all
+    can_write: []
+";
+
+    const OBSERVED: &str = "
+object_map: []
+subject_map: []
+privileges:
+  - principal:
+      subject: s1
+    can_call: [s2]
+    can_return: []
+    can_read:
+      - objects: [od2, od1]
+    can_write:
+      - objects: [od1]
+  - principal:
+      subject: s1
+    can_call: []
+    can_return: all
+    can_read:
+      - objects: [od1]
+    can_write: []
+";
+
+    #[test]
+    fn test_propose_minimization() {
+        let policy: CPMPrivMap = serde_yaml::from_str(POLICY).unwrap();
+        let observed: CPMPrivMap = serde_yaml::from_str(OBSERVED).unwrap();
+        let report = propose_minimization(&policy, observed.privileges());
+
+        assert_eq!(report.proposals.len(), 2);
+        let s1 = &report.proposals[0];
+        assert_eq!(s1.index, 0);
+        // can_return was observed as all, so it stays
+        assert_eq!(s1.fields, vec![
+            FieldProposal {
+                field: PrivField::CanRead,
+                domains: vec!["od1".to_string(), "od2".to_string()],
+                granted: 3,
+                objects: vec![Object::new(vec!["od1".to_string(), "od2".to_string()])],
+            },
+            FieldProposal {
+                field: PrivField::CanWrite,
+                domains: vec!["od1".to_string()],
+                granted: 3,
+                objects: vec![Object::new(vec!["od1".to_string()])],
+            },
+        ]);
+
+        // s2 was never observed, so its `all` read shrinks to nothing
+        let s2 = &report.proposals[1];
+        assert_eq!(s2.fields, vec![
+            FieldProposal { field: PrivField::CanRead, domains: vec![], granted: 3, objects: vec![] },
+        ]);
+
+        assert_eq!(report.reductions(), vec![
+            PrincipalReduction { subject: "s1".to_string(), granted: 6, proposed: 3 },
+            PrincipalReduction { subject: "s2".to_string(), granted: 3, proposed: 0 },
+        ]);
+        assert_eq!(report.total_removed(), 6);
+    }
+
+    #[test]
+    fn test_apply_minimization() {
+        let mut policy: CPMPrivMap = serde_yaml::from_str(POLICY).unwrap();
+        let observed: CPMPrivMap = serde_yaml::from_str(OBSERVED).unwrap();
+        let report = propose_minimization(&policy, observed.privileges());
+        report.apply(&mut policy);
+
+        let s1 = &policy.privileges()[0];
+        assert_eq!(s1.can_call(), &CallRetPrivField::List(vec!["s2".to_string()]));
+        assert_eq!(s1.can_return(), &CallRetPrivField::All);
+        assert_eq!(s1.can_write(), &RWPrivField::List(vec![Object::new(vec!["od1".to_string()])]));
+        assert_eq!(policy.privileges()[1].can_read(), &RWPrivField::List(vec![]));
+
+        // nothing left to tighten except the field observed as all
+        let again = propose_minimization(&policy, observed.privileges());
+        assert!(again.proposals.is_empty());
+    }
+
+    #[test]
+    fn test_minimization_keeps_contexts() {
+        let policy: CPMPrivMap = serde_yaml::from_str("
+object_map: []
+subject_map:
+  - name: s1
+    subjects: [f1]
+  - name: s2
+    subjects: [f2]
+privileges:
+  - principal:
+      subject: s1
+      execution_context:
+        uid: root
+    can_call: []
+    can_return: []
+    can_read: all
+    can_write: []
+").unwrap();
+        let observed: CPMPrivMap = serde_yaml::from_str("
+object_map: []
+subject_map: []
+privileges:
+  - principal:
+      subject: s1
+      execution_context:
+        uid: root
+    can_call: []
+    can_return: []
+    can_read:
+      - objects: [od2]
+        object_context:
+          call_context: [s2]
+      - objects: [od1]
+      - objects: [od3]
+        object_context:
+          call_context: [s2]
+    can_write: []
+  # a different execution context is a different principal
+  - principal:
+      subject: s1
+    can_call: []
+    can_return: []
+    can_read:
+      - objects: [od4]
+    can_write: []
+").unwrap();
+        let mut minimized = policy.clone();
+        propose_minimization(&policy, observed.privileges()).apply(&mut minimized);
+
+        let s1 = &minimized.privileges()[0];
+        assert_eq!(s1.principal(), policy.privileges()[0].principal());
+        let call_context = ContextField::Context(Context::with_call_context(vec!["s2".to_string()]));
+        assert_eq!(s1.can_read(), &RWPrivField::List(vec![
+            Object::with_context(vec!["od2".to_string(), "od3".to_string()], call_context),
+            Object::new(vec!["od1".to_string()]),
+        ]));
+    }
+}
@@ -0,0 +1,89 @@
+// SPDX-License-Identifier: MIT
+//
+// MIT License
+//
+// © 2024 Nathan Dautenhahn & Serenitix LLC
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+use std::thread;
+
+/*
+ * How per-privilege and per-domain work is scheduled. `Threads(n)` splits
+ * the items into n contiguous chunks on scoped threads and stitches the
+ * results back together in input order, so the output is identical to the
+ * sequential run whatever the thread count.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Parallelism {
+    #[default]
+    Sequential,
+    Threads(usize),
+}
+
+impl Parallelism {
+    /// One thread per available CPU.
+    pub fn available() -> Self {
+        Parallelism::Threads(thread::available_parallelism().map(|n| n.get()).unwrap_or(1))
+    }
+
+    pub fn threads(&self) -> usize {
+        match *self {
+            Parallelism::Sequential => 1,
+            Parallelism::Threads(n) => n.max(1),
+        }
+    }
+
+    /// Maps `f` over the items (with their index), returning results in item order.
+    pub fn map<T, R, F>(&self, items: &[T], f: F) -> Vec<R>
+    where
+        T: Sync,
+        R: Send,
+        F: Fn(usize, &T) -> R + Sync,
+    {
+        let threads = self.threads().min(items.len());
+        if threads <= 1 {
+            return items.iter().enumerate().map(|(i, item)| f(i, item)).collect();
+        }
+
+        let chunk_size = items.len().div_ceil(threads);
+        let f = &f;
+        thread::scope(|scope| {
+            let handles: Vec<_> = items.chunks(chunk_size)
+                .enumerate()
+                .map(|(c, chunk)| scope.spawn(move || {
+                    chunk.iter()
+                        .enumerate()
+                        .map(|(i, item)| f(c * chunk_size + i, item))
+                        .collect::<Vec<R>>()
+                }))
+                .collect();
+            handles.into_iter()
+                .flat_map(|h| h.join().expect("worker thread panicked"))
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_preserves_order() {
+        let items: Vec<usize> = (0..1003).collect();
+        let expected: Vec<(usize, usize)> = items.iter().map(|&x| (x, x * 2)).collect();
+        for par in [Parallelism::Sequential, Parallelism::Threads(1), Parallelism::Threads(7), Parallelism::Threads(5000)] {
+            assert_eq!(par.map(&items, |i, &x| (i, x * 2)), expected);
+        }
+        assert!(Parallelism::Threads(4).map(&Vec::<usize>::new(), |_, &x| x).is_empty());
+    }
+}
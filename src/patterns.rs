@@ -0,0 +1,349 @@
+// SPDX-License-Identifier: MIT
+//
+// MIT License
+//
+// © 2024 Nathan Dautenhahn & Serenitix LLC
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::cpm_priv_map::{
+    CPMPrivMap, CallContextSubField, CallRetPrivField, ContextField, LoadMode, Privilege, RWPrivField,
+};
+use crate::include::load_policy;
+use crate::query::glob_match;
+
+/*
+ * Pattern references to domains.
+ *
+ * Wherever a privilege names domains (can_call, can_return, call_context and
+ * the `objects` of can_read/can_write), an entry may be a pattern instead of
+ * a literal name, marked by its prefix:
+ *
+ *   glob:HeapObjectDomain*.kmalloc_reserve    `*` any run of characters, `?` one
+ *   re:HeapObjectDomain[0-9]+\.kmalloc_.*     a regex matching the whole name
+ *
+ * Call, return and call_context patterns match subject domain names; object
+ * patterns match object domain names. `expand_patterns` replaces every
+ * pattern with the names it matches, in map order and without duplicates,
+ * so the rest of the library only ever sees literal names.
+ */
+
+pub const GLOB_PREFIX: &str = "glob:";
+pub const REGEX_PREFIX: &str = "re:";
+
+#[derive(Debug, Clone)]
+pub enum DomainRef {
+    Name(String),
+    Glob(String),
+    Regex(Regex),
+}
+
+impl DomainRef {
+    pub fn is_pattern(reference: &str) -> bool {
+        reference.starts_with(GLOB_PREFIX) || reference.starts_with(REGEX_PREFIX)
+    }
+
+    pub fn parse(reference: &str) -> Result<Self, String> {
+        if let Some(glob) = reference.strip_prefix(GLOB_PREFIX) {
+            Ok(DomainRef::Glob(glob.to_string()))
+        } else if let Some(re) = reference.strip_prefix(REGEX_PREFIX) {
+            Regex::new(&format!("^(?:{})$", re))
+                .map(DomainRef::Regex)
+                .map_err(|e| {
+                    // keep the last line of the multi-line parse error, which names the problem
+                    let message = e.to_string();
+                    let reason = message.lines().last().unwrap_or("").trim_start_matches("error: ").to_string();
+                    format!("invalid regex in '{}': {}", reference, reason)
+                })
+        } else {
+            Ok(DomainRef::Name(reference.to_string()))
+        }
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            DomainRef::Name(n) => n == name,
+            DomainRef::Glob(glob) => glob_match(glob, name),
+            DomainRef::Regex(re) => re.is_match(name),
+        }
+    }
+
+    /// The names among `names` this reference stands for.
+    pub fn expand<'a>(&self, names: impl Iterator<Item = &'a str>) -> Vec<&'a str> {
+        names.filter(|n| self.matches(n)).collect()
+    }
+}
+
+/*
+ * The pattern references of one map, each distinct reference parsed (and
+ * its regex compiled) once. Large generated policies repeat the same few
+ * patterns across thousands of privileges. Literal names are not stored;
+ * callers check `DomainRef::is_pattern` first.
+ */
+#[derive(Debug, Default)]
+pub struct PatternCache {
+    parsed: HashMap<String, Result<DomainRef, String>>,
+}
+
+impl PatternCache {
+    pub fn new(priv_map: &CPMPrivMap) -> Self {
+        let mut parsed = HashMap::new();
+        for_each_reference(priv_map, |_, _, reference| {
+            if DomainRef::is_pattern(reference) && !parsed.contains_key(reference) {
+                parsed.insert(reference.to_string(), DomainRef::parse(reference));
+            }
+        });
+        Self { parsed }
+    }
+
+    /// The parsed pattern; cloning is cheap as compiled regexes are shared.
+    ///
+    /// A reference the map did not hold is parsed on the spot.
+    pub fn get(&self, reference: &str) -> Result<DomainRef, String> {
+        match self.parsed.get(reference) {
+            Some(parsed) => parsed.clone(),
+            None => DomainRef::parse(reference),
+        }
+    }
+
+    /// Like `get`, but keeps a reference it had to parse, for callers that
+    /// fill the cache as they go instead of from a whole map.
+    pub fn get_or_parse(&mut self, reference: &str) -> Result<DomainRef, String> {
+        if let Some(parsed) = self.parsed.get(reference) {
+            return parsed.clone();
+        }
+        let parsed = DomainRef::parse(reference);
+        self.parsed.insert(reference.to_string(), parsed.clone());
+        parsed
+    }
+
+    /// True if a valid pattern in the cache matches the name.
+    pub fn any_matches(&self, name: &str) -> bool {
+        self.parsed.values().any(|parsed| parsed.as_ref().is_ok_and(|pattern| pattern.matches(name)))
+    }
+}
+
+/// A pattern in a privilege that matches no domain.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnmatchedPattern {
+    pub privilege: usize,
+    // can_call, can_return, call_context, can_read or can_write
+    pub field: &'static str,
+    pub pattern: String,
+}
+
+impl fmt::Display for UnmatchedPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.field {
+            "can_read" | "can_write" => "object",
+            _ => "subject",
+        };
+        write!(f, "privileges[{}]: {} pattern '{}' matches no {} domain", self.privilege, self.field,
+               self.pattern, kind)
+    }
+}
+
+/*
+ * Expands the references of one list in place. Unmatched patterns are
+ * recorded, and the list is left untouched when it holds no pattern.
+ */
+fn expand_list(list: &mut Vec<String>, universe: &[&str], patterns: &PatternCache, privilege: usize,
+               field: &'static str, unmatched: &mut Vec<UnmatchedPattern>) -> Result<(), String> {
+    if !list.iter().any(|r| DomainRef::is_pattern(r)) {
+        return Ok(());
+    }
+    let mut seen = HashSet::new();
+    let mut expanded = vec![];
+    for reference in list.iter() {
+        let names = match patterns.get(reference)? {
+            DomainRef::Name(name) => vec![name],
+            pattern => {
+                let names: Vec<String> = pattern.expand(universe.iter().copied()).into_iter()
+                    .map(|n| n.to_string())
+                    .collect();
+                if names.is_empty() {
+                    unmatched.push(UnmatchedPattern { privilege, field, pattern: reference.to_string() });
+                }
+                names
+            }
+        };
+        expanded.extend(names.into_iter().filter(|n| seen.insert(n.to_string())));
+    }
+    *list = expanded;
+    Ok(())
+}
+
+pub fn has_patterns(priv_map: &CPMPrivMap) -> bool {
+    let mut found = false;
+    for_each_reference(priv_map, |_, _, reference| found |= DomainRef::is_pattern(reference));
+    found
+}
+
+/// Calls `f(privilege index, field, reference)` for every domain reference of the privileges.
+pub fn for_each_reference(priv_map: &CPMPrivMap, mut f: impl FnMut(usize, &'static str, &str)) {
+    for (i, privilege) in priv_map.privileges().iter().enumerate() {
+        for_each_privilege_reference(privilege, |field, reference| f(i, field, reference));
+    }
+}
+
+/// Calls `f(field, reference)` for every domain reference of one privilege.
+pub fn for_each_privilege_reference(privilege: &Privilege, mut f: impl FnMut(&'static str, &str)) {
+    if let ContextField::Context(context) = privilege.principal().execution_context() {
+        if let Some(CallContextSubField::List(names)) = context.call_context() {
+            names.iter().for_each(|n| f("call_context", n));
+        }
+    }
+    for (field, value) in [("can_call", privilege.can_call()), ("can_return", privilege.can_return())] {
+        if let CallRetPrivField::List(names) = value {
+            names.iter().for_each(|n| f(field, n));
+        }
+    }
+    for (field, value) in [("can_read", privilege.can_read()), ("can_write", privilege.can_write())] {
+        if let RWPrivField::List(objects) = value {
+            objects.iter().flat_map(|o| o.objects().iter()).for_each(|n| f(field, n));
+        }
+    }
+}
+
+/// Replaces every pattern reference by the domains it matches, returning the patterns that matched none.
+pub fn expand_patterns(priv_map: &mut CPMPrivMap) -> Result<Vec<UnmatchedPattern>, String> {
+    let subjects: Vec<String> = priv_map.subject_map().iter().map(|sd| sd.name().to_string()).collect();
+    let objects: Vec<String> = priv_map.object_map().iter().map(|od| od.name().to_string()).collect();
+    let subjects: Vec<&str> = subjects.iter().map(|s| s.as_str()).collect();
+    let objects: Vec<&str> = objects.iter().map(|s| s.as_str()).collect();
+
+    let patterns = PatternCache::new(priv_map);
+    let mut unmatched = vec![];
+    for (i, privilege) in priv_map.privileges.iter_mut().enumerate() {
+        if let ContextField::Context(context) = &mut privilege.principal.execution_context {
+            if let Some(CallContextSubField::List(names)) = context.call_context().clone() {
+                let mut names = names;
+                expand_list(&mut names, &subjects, &patterns, i, "call_context", &mut unmatched)?;
+                context.set_call_context(Some(CallContextSubField::List(names)));
+            }
+        }
+        if let CallRetPrivField::List(names) = &mut privilege.can_call {
+            expand_list(names, &subjects, &patterns, i, "can_call", &mut unmatched)?;
+        }
+        if let CallRetPrivField::List(names) = &mut privilege.can_return {
+            expand_list(names, &subjects, &patterns, i, "can_return", &mut unmatched)?;
+        }
+        for (field, value) in [("can_read", &mut privilege.can_read), ("can_write", &mut privilege.can_write)] {
+            if let RWPrivField::List(list) = value {
+                for object in list.iter_mut() {
+                    let mut names = object.objects().clone();
+                    expand_list(&mut names, &objects, &patterns, i, field, &mut unmatched)?;
+                    object.set_objects(names);
+                }
+            }
+        }
+    }
+    Ok(unmatched)
+}
+
+/// Loads a policy file with its patterns expanded, and the patterns that matched nothing.
+pub fn load_expanded(file_path: &str, mode: LoadMode) -> Result<(CPMPrivMap, Vec<UnmatchedPattern>), String> {
+    let mut priv_map = load_policy(file_path, mode)?;
+    let unmatched = expand_patterns(&mut priv_map)?;
+    Ok((priv_map, unmatched))
+}
+
+/// Patterns that match no domain, without changing the map.
+pub fn unmatched_patterns(priv_map: &CPMPrivMap) -> Result<Vec<UnmatchedPattern>, String> {
+    let subjects: Vec<&str> = priv_map.subject_map().iter().map(|sd| sd.name()).collect();
+    let objects: Vec<&str> = priv_map.object_map().iter().map(|od| od.name()).collect();
+    let patterns = PatternCache::new(priv_map);
+    let mut unmatched = vec![];
+    let mut error = None;
+    for_each_reference(priv_map, |i, field, reference| {
+        if error.is_some() || !DomainRef::is_pattern(reference) {
+            return;
+        }
+        let universe = match field {
+            "can_read" | "can_write" => &objects,
+            _ => &subjects,
+        };
+        match patterns.get(reference) {
+            Ok(pattern) if !universe.iter().any(|n| pattern.matches(n)) => {
+                unmatched.push(UnmatchedPattern { privilege: i, field, pattern: reference.to_string() })
+            }
+            Ok(_) => (),
+            Err(e) => error = Some(e),
+        }
+    });
+    match error {
+        Some(e) => Err(e),
+        None => Ok(unmatched),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: &str = r#"
+object_map:
+  - {name: HeapObjectDomain1.kmalloc_reserve, objects: [a]}
+  - {name: HeapObjectDomain22.kmalloc_reserve, objects: [b]}
+  - {name: HeapObjectDomain3.dst_alloc, objects: [c]}
+subject_map:
+  - {name: net_rx, subjects: [f1]}
+  - {name: net_tx, subjects: [f2]}
+  - {name: fs, subjects: [f3]}
+privileges:
+  - principal:
+      subject: fs
+      execution_context:
+        call_context: ["glob:net_*"]
+    can_call: ["glob:net_*", net_rx]
+    can_return: ["re:fs|nothing"]
+    can_read:
+      - objects: ['re:HeapObjectDomain[0-9]+\.kmalloc_reserve']
+    can_write:
+      - objects: ["glob:Stack*", HeapObjectDomain3.dst_alloc]
+"#;
+
+    #[test]
+    fn test_expand_patterns() {
+        let mut priv_map: CPMPrivMap = serde_yaml::from_str(POLICY).unwrap();
+        assert!(has_patterns(&priv_map));
+        let unmatched = unmatched_patterns(&priv_map).unwrap();
+        assert_eq!(unmatched.iter().map(|u| u.to_string()).collect::<Vec<_>>(),
+                   vec!["privileges[0]: can_write pattern 'glob:Stack*' matches no object domain"]);
+
+        assert_eq!(expand_patterns(&mut priv_map).unwrap(), unmatched);
+        assert!(!has_patterns(&priv_map));
+        let privilege = &priv_map.privileges()[0];
+        assert_eq!(privilege.can_call(), &CallRetPrivField::List(vec!["net_rx".to_string(), "net_tx".to_string()]));
+        assert_eq!(privilege.can_return(), &CallRetPrivField::List(vec!["fs".to_string()]));
+        assert!(privilege.can_read().contains_domain("HeapObjectDomain22.kmalloc_reserve"));
+        assert!(!privilege.can_read().contains_domain("HeapObjectDomain3.dst_alloc"));
+        let RWPrivField::List(written) = privilege.can_write() else { panic!() };
+        assert_eq!(written[0].objects(), &vec!["HeapObjectDomain3.dst_alloc".to_string()]);
+        let ContextField::Context(context) = privilege.principal().execution_context() else { panic!() };
+        assert_eq!(context.call_context(), &Some(CallContextSubField::List(vec!["net_rx".to_string(), "net_tx".to_string()])));
+    }
+
+    #[test]
+    fn test_invalid_regex() {
+        let mut priv_map: CPMPrivMap = serde_yaml::from_str(
+            "object_map: []\nsubject_map: []\nprivileges:\n  - principal: {subject: s}\n    can_call: ['re:(']\n").unwrap();
+        assert!(unmatched_patterns(&priv_map).unwrap_err().starts_with("invalid regex in 're:('"));
+        assert!(expand_patterns(&mut priv_map).is_err());
+        assert!(PatternCache::new(&priv_map).get("re:(").is_err());
+        assert!(DomainRef::parse("re:a+").unwrap().matches("aaa"));
+        assert!(!DomainRef::parse("re:a+").unwrap().matches("baaa"));
+    }
+}
@@ -0,0 +1,286 @@
+// SPDX-License-Identifier: MIT
+//
+// MIT License
+//
+// © 2024 Nathan Dautenhahn & Serenitix LLC
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+use serde::{Serialize, Serializer};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use crate::cpm_priv_map::{CPMPrivMap, CallRetPrivField, PrivField, Privilege, RWPrivField};
+use crate::parallel::Parallelism;
+
+/*
+ * Effective permissions of subject domains.
+ *
+ * A subject's effective permission for a field is the union of that field
+ * over every privilege naming the subject as principal. Execution and
+ * object contexts are not evaluated: a grant counts if it holds in some
+ * context, which over-approximates what the subject can do.
+ */
+
+/// A set of domain names, or every domain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DomainSet {
+    All,
+    Only(BTreeSet<String>),
+}
+
+impl Default for DomainSet {
+    fn default() -> Self {
+        DomainSet::Only(BTreeSet::new())
+    }
+}
+
+impl DomainSet {
+    pub fn is_all(&self) -> bool {
+        matches!(self, DomainSet::All)
+    }
+
+    pub fn contains(&self, domain: &str) -> bool {
+        match self {
+            DomainSet::All => true,
+            DomainSet::Only(set) => set.contains(domain),
+        }
+    }
+
+    pub fn insert(&mut self, domain: &str) {
+        if let DomainSet::Only(set) = self {
+            set.insert(domain.to_string());
+        }
+    }
+
+    pub fn union_with(&mut self, other: &DomainSet) {
+        match (self, other) {
+            (DomainSet::All, _) => (),
+            (this, DomainSet::All) => *this = DomainSet::All,
+            (DomainSet::Only(a), DomainSet::Only(b)) => a.extend(b.iter().cloned()),
+        }
+    }
+
+    /// Number of domains, with `All` standing for `universe` domains.
+    pub fn size(&self, universe: usize) -> usize {
+        match self {
+            DomainSet::All => universe,
+            DomainSet::Only(set) => set.len(),
+        }
+    }
+
+    /// The named domains; `All` expands to the given universe.
+    pub fn expand<'a>(&'a self, universe: impl Iterator<Item = &'a str>) -> Vec<&'a str> {
+        match self {
+            DomainSet::All => universe.collect(),
+            DomainSet::Only(set) => set.iter().map(|s| s.as_str()).collect(),
+        }
+    }
+
+    pub fn from_callret(field: &CallRetPrivField) -> Self {
+        match field {
+            CallRetPrivField::All => DomainSet::All,
+            CallRetPrivField::List(names) => DomainSet::Only(names.iter().cloned().collect()),
+        }
+    }
+
+    pub fn from_rw(field: &RWPrivField) -> Self {
+        match field {
+            RWPrivField::All => DomainSet::All,
+            RWPrivField::List(objects) => DomainSet::Only(
+                objects.iter().flat_map(|o| o.objects().iter().cloned()).collect()),
+        }
+    }
+
+    pub fn of_field(privilege: &Privilege, field: PrivField) -> Self {
+        match field {
+            PrivField::CanCall => Self::from_callret(privilege.can_call()),
+            PrivField::CanReturn => Self::from_callret(privilege.can_return()),
+            PrivField::CanRead => Self::from_rw(privilege.can_read()),
+            PrivField::CanWrite => Self::from_rw(privilege.can_write()),
+        }
+    }
+}
+
+// serializes as "all" or a list, like the privilege fields themselves
+impl Serialize for DomainSet {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            DomainSet::All => serializer.serialize_str("all"),
+            DomainSet::Only(set) => set.serialize(serializer),
+        }
+    }
+}
+
+impl fmt::Display for DomainSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DomainSet::All => write!(f, "all"),
+            DomainSet::Only(set) => {
+                let names: Vec<&str> = set.iter().map(|s| s.as_str()).collect();
+                write!(f, "[{}]", names.join(", "))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct EffectivePermissions {
+    pub can_call: DomainSet,
+    pub can_return: DomainSet,
+    pub can_read: DomainSet,
+    pub can_write: DomainSet,
+}
+
+impl EffectivePermissions {
+    pub fn field(&self, field: PrivField) -> &DomainSet {
+        match field {
+            PrivField::CanCall => &self.can_call,
+            PrivField::CanReturn => &self.can_return,
+            PrivField::CanRead => &self.can_read,
+            PrivField::CanWrite => &self.can_write,
+        }
+    }
+
+    fn field_mut(&mut self, field: PrivField) -> &mut DomainSet {
+        match field {
+            PrivField::CanCall => &mut self.can_call,
+            PrivField::CanReturn => &mut self.can_return,
+            PrivField::CanRead => &mut self.can_read,
+            PrivField::CanWrite => &mut self.can_write,
+        }
+    }
+
+    pub fn add_privilege(&mut self, privilege: &Privilege) {
+        for field in PrivField::ALL {
+            self.field_mut(field).union_with(&DomainSet::of_field(privilege, field));
+        }
+    }
+
+    pub fn allows(&self, field: PrivField, domain: &str) -> bool {
+        self.field(field).contains(domain)
+    }
+}
+
+/// Effective permissions of a single subject domain.
+pub fn effective_permissions(priv_map: &CPMPrivMap, subject_domain: &str) -> EffectivePermissions {
+    let mut permissions = EffectivePermissions::default();
+    for privilege in priv_map.privileges().iter().filter(|p| p.principal().subject() == subject_domain) {
+        permissions.add_privilege(privilege);
+    }
+    permissions
+}
+
+/// Effective permissions of every subject domain, in subject map order.
+pub fn all_effective_permissions(priv_map: &CPMPrivMap, par: Parallelism) -> Vec<(String, EffectivePermissions)> {
+    let mut by_subject: HashMap<&str, Vec<&Privilege>> = HashMap::new();
+    for privilege in priv_map.privileges() {
+        by_subject.entry(privilege.principal().subject().as_str()).or_default().push(privilege);
+    }
+    par.map(priv_map.subject_map(), |_, sd| {
+        let mut permissions = EffectivePermissions::default();
+        for privilege in by_subject.get(sd.name()).into_iter().flatten() {
+            permissions.add_privilege(privilege);
+        }
+        (sd.name().to_string(), permissions)
+    })
+}
+
+/// Subject domains granted `field` on `domain` by some privilege, in first-grant order.
+pub fn who_can(priv_map: &CPMPrivMap, field: PrivField, domain: &str, par: Parallelism) -> Vec<String> {
+    let granted = par.map(priv_map.privileges(), |_, privilege| {
+        DomainSet::of_field(privilege, field).contains(domain)
+    });
+    let mut seen = BTreeSet::new();
+    priv_map.privileges().iter()
+        .zip(granted)
+        .filter(|(_, granted)| *granted)
+        .map(|(privilege, _)| privilege.principal().subject().to_string())
+        .filter(|subject| seen.insert(subject.to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: &str = "
+object_map:
+  - name: od1
+    objects: [a]
+  - name: od2
+    objects: [b]
+subject_map:
+  - name: s1
+    subjects: [f1]
+  - name: s2
+    subjects: [f2]
+  - name: s3
+    subjects: [f3]
+privileges:
+  - principal:
+      subject: s1
+    can_call: [s2]
+    can_return: []
+    can_read:
+      - objects: [od1]
+    can_write: []
+  - principal:
+      subject: s1
+      execution_context:
+        uid: root
+    can_call: [s3]
+    can_return: []
+    can_read: []
+    can_write:
+      - objects: [od2]
+  - principal:
+      subject: s2
+    can_call: []
+    can_return: all
+    can_read: all
+    can_write: []
+";
+
+    #[test]
+    fn test_effective_permissions() {
+        let priv_map: CPMPrivMap = serde_yaml::from_str(POLICY).unwrap();
+        let s1 = effective_permissions(&priv_map, "s1");
+        assert_eq!(s1.can_call.to_string(), "[s2, s3]");
+        assert!(s1.allows(PrivField::CanWrite, "od2"));
+        assert!(!s1.allows(PrivField::CanWrite, "od1"));
+        assert_eq!(s1.can_read.size(2), 1);
+
+        let s2 = effective_permissions(&priv_map, "s2");
+        assert!(s2.can_read.is_all());
+        assert_eq!(s2.can_read.size(2), 2);
+
+        assert_eq!(effective_permissions(&priv_map, "s3"), EffectivePermissions::default());
+    }
+
+    #[test]
+    fn test_parallel_queries_match_sequential() {
+        let priv_map: CPMPrivMap = serde_yaml::from_str(POLICY).unwrap();
+        for par in [Parallelism::Sequential, Parallelism::Threads(3)] {
+            assert_eq!(who_can(&priv_map, PrivField::CanRead, "od1", par), vec!["s1", "s2"]);
+            assert_eq!(who_can(&priv_map, PrivField::CanWrite, "od2", par), vec!["s1"]);
+            assert!(who_can(&priv_map, PrivField::CanCall, "s1", par).is_empty());
+
+            let all = all_effective_permissions(&priv_map, par);
+            let names: Vec<&str> = all.iter().map(|(n, _)| n.as_str()).collect();
+            assert_eq!(names, vec!["s1", "s2", "s3"]);
+            assert_eq!(all[0].1, effective_permissions(&priv_map, "s1"));
+        }
+    }
+}
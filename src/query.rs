@@ -0,0 +1,815 @@
+// SPDX-License-Identifier: MIT
+//
+// MIT License
+//
+// © 2024 Nathan Dautenhahn & Serenitix LLC
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::cpm_priv_map::{
+    CPMPrivMap, CallContextSubField, CallRetPrivField, ContextField, ContextSimpleString, ObjectDomain,
+    ObjectID, PrivField, Privilege, RWPrivField, SubjectDomain,
+};
+
+/*
+ * A small declarative query language over privilege maps.
+ *
+ *   Query  ::= from Entity [ where Expr ] [ select [ distinct ] Path { , Path } ]
+ *   Entity ::= subjects | objects | object_ids | privileges
+ *   Expr   ::= Term { or Term }
+ *   Term   ::= Factor { and Factor }
+ *   Factor ::= not Factor | ( Expr ) | Path has ( Expr )
+ *            | Path ( = | != | like ) Literal | Path in ( Literal { , Literal } )
+ *            | Path
+ *   Path   ::= field { . field }
+ *
+ * A path navigates from the current entity along the fields below, joining
+ * through domain names the way the policy references them, and evaluates to
+ * every entity or value it reaches. `all` grants expand to every domain.
+ * Comparisons hold if some reached value matches (`!=` is the negation of
+ * `=`); a bare path holds if it reaches anything. `Path has (Expr)` holds if
+ * some reached entity satisfies Expr, which correlates several conditions on
+ * the same privilege or object. `like` takes a glob with `*` and `?`.
+ * Literals are bare words or double-quoted strings.
+ *
+ *   subjects    name, subjects (functions), privileges, callers
+ *   objects     name, objects (object ids), readers, writers
+ *   object_ids  alloc_type, path, line, name, domain
+ *   privileges  index, principal, uid, gid, call_context, can_call,
+ *               can_return, can_read, can_write, all_grants, implicit
+ *
+ * Entities compare by name; privileges by index. For example, subjects with
+ * uid user that can write a GLOBAL object under net:
+ *
+ *   from subjects where privileges has (uid = user and
+ *       can_write.objects has (alloc_type = GLOBAL and path like "net*"))
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Entity {
+    Subjects,
+    Objects,
+    ObjectIds,
+    Privileges,
+}
+
+impl Entity {
+    fn kind(&self) -> Kind {
+        match self {
+            Entity::Subjects => Kind::Subject,
+            Entity::Objects => Kind::Object,
+            Entity::ObjectIds => Kind::ObjectId,
+            Entity::Privileges => Kind::Privilege,
+        }
+    }
+}
+
+// what a path segment evaluates to, checked when parsing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Subject,
+    Object,
+    ObjectId,
+    Privilege,
+    Value,
+}
+
+impl Kind {
+    fn fields(&self) -> &'static [(&'static str, Kind)] {
+        match self {
+            Kind::Subject => &[
+                ("name", Kind::Value),
+                ("subjects", Kind::Value),
+                ("privileges", Kind::Privilege),
+                ("callers", Kind::Subject),
+            ],
+            Kind::Object => &[
+                ("name", Kind::Value),
+                ("objects", Kind::ObjectId),
+                ("readers", Kind::Subject),
+                ("writers", Kind::Subject),
+            ],
+            Kind::ObjectId => &[
+                ("alloc_type", Kind::Value),
+                ("path", Kind::Value),
+                ("line", Kind::Value),
+                ("name", Kind::Value),
+                ("domain", Kind::Object),
+            ],
+            Kind::Privilege => &[
+                ("index", Kind::Value),
+                ("principal", Kind::Subject),
+                ("uid", Kind::Value),
+                ("gid", Kind::Value),
+                ("call_context", Kind::Subject),
+                ("can_call", Kind::Subject),
+                ("can_return", Kind::Subject),
+                ("can_read", Kind::Object),
+                ("can_write", Kind::Object),
+                ("all_grants", Kind::Value),
+                ("implicit", Kind::Value),
+            ],
+            Kind::Value => &[],
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Kind::Subject => "subject",
+            Kind::Object => "object",
+            Kind::ObjectId => "object_id",
+            Kind::Privilege => "privilege",
+            Kind::Value => "value",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Path {
+    pub fields: Vec<String>,
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.fields.join("."))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Like,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Exists(Path),
+    Compare(Path, CmpOp, String),
+    In(Path, Vec<String>),
+    Has(Path, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    pub entity: Entity,
+    pub filter: Option<Expr>,
+    pub select: Vec<Path>,
+    pub distinct: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct QueryResult {
+    pub columns: Vec<String>,
+    // one string per column, multiple values joined with ", "
+    pub rows: Vec<Vec<String>>,
+}
+
+impl fmt::Display for QueryResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.columns.join("\t"))?;
+        for row in &self.rows {
+            writeln!(f, "{}", row.join("\t"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses and runs a query in one step.
+pub fn run_query(priv_map: &CPMPrivMap, text: &str) -> Result<QueryResult, String> {
+    Ok(parse_query(text)?.evaluate(priv_map))
+}
+
+/*
+ * Lexer
+ */
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Punct(&'static str),
+}
+
+// `:` for namespaced domain names (see crate::include)
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || "_./*?-:".contains(c)
+}
+
+// tokens with the 1-based column they start at
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '"' {
+            let mut value = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err(format!("column {}: unterminated string", column)),
+                    Some('"') => break,
+                    Some('\\') if i + 1 < chars.len() => {
+                        value.push(chars[i + 1]);
+                        i += 2;
+                    }
+                    Some(&c) => {
+                        value.push(c);
+                        i += 1;
+                    }
+                }
+            }
+            i += 1;
+            tokens.push((column, Token::Quoted(value)));
+        } else if c == '!' && chars.get(i + 1) == Some(&'=') {
+            tokens.push((column, Token::Punct("!=")));
+            i += 2;
+        } else if let Some(p) = ["(", ")", ",", "="].into_iter().find(|p| p.starts_with(c)) {
+            tokens.push((column, Token::Punct(p)));
+            i += 1;
+        } else if is_word_char(c) {
+            let start = i;
+            while i < chars.len() && is_word_char(chars[i]) {
+                i += 1;
+            }
+            tokens.push((column, Token::Word(chars[start..i].iter().collect())));
+        } else {
+            return Err(format!("column {}: unexpected character '{}'", column, c));
+        }
+    }
+    Ok(tokens)
+}
+
+/*
+ * Parser: recursive descent over the grammar above, type checking paths
+ * against the kind of the entity they start from.
+ */
+
+const KEYWORDS: &[&str] = &["from", "where", "select", "distinct", "and", "or", "not", "has", "like", "in"];
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn column(&self) -> usize {
+        self.tokens.get(self.pos).map(|(c, _)| *c).unwrap_or(self.end)
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, String> {
+        Err(format!("column {}: {}", self.column(), message))
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, t)| t)
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(w)) if w == keyword)
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.peek_keyword(keyword);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn eat_punct(&mut self, punct: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Punct(p)) if *p == punct);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect_punct(&mut self, punct: &str) -> Result<(), String> {
+        if self.eat_punct(punct) {
+            Ok(())
+        } else {
+            self.error(format!("expected '{}'", punct))
+        }
+    }
+
+    fn query(&mut self) -> Result<Query, String> {
+        if !self.eat_keyword("from") {
+            return self.error("expected 'from'".to_string());
+        }
+        let entity = match self.peek() {
+            Some(Token::Word(w)) => match w.as_str() {
+                "subjects" => Entity::Subjects,
+                "objects" => Entity::Objects,
+                "object_ids" => Entity::ObjectIds,
+                "privileges" => Entity::Privileges,
+                _ => return self.error(format!(
+                    "unknown entity '{}'; expected subjects, objects, object_ids or privileges", w)),
+            },
+            _ => return self.error("expected an entity".to_string()),
+        };
+        self.pos += 1;
+
+        let filter = if self.eat_keyword("where") {
+            Some(self.expr(entity.kind())?)
+        } else {
+            None
+        };
+
+        let mut select = vec![];
+        let mut distinct = false;
+        if self.eat_keyword("select") {
+            distinct = self.eat_keyword("distinct");
+            select.push(self.path(entity.kind())?.0);
+            while self.eat_punct(",") {
+                select.push(self.path(entity.kind())?.0);
+            }
+        }
+        if self.pos < self.tokens.len() {
+            return self.error("unexpected input after query".to_string());
+        }
+        Ok(Query { entity, filter, select, distinct })
+    }
+
+    fn expr(&mut self, kind: Kind) -> Result<Expr, String> {
+        let mut expr = self.term(kind)?;
+        while self.eat_keyword("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.term(kind)?));
+        }
+        Ok(expr)
+    }
+
+    fn term(&mut self, kind: Kind) -> Result<Expr, String> {
+        let mut expr = self.factor(kind)?;
+        while self.eat_keyword("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.factor(kind)?));
+        }
+        Ok(expr)
+    }
+
+    fn factor(&mut self, kind: Kind) -> Result<Expr, String> {
+        if self.eat_keyword("not") {
+            return Ok(Expr::Not(Box::new(self.factor(kind)?)));
+        }
+        if self.eat_punct("(") {
+            let expr = self.expr(kind)?;
+            self.expect_punct(")")?;
+            return Ok(expr);
+        }
+
+        let (path, target) = self.path(kind)?;
+        if self.peek_keyword("has") {
+            if target == Kind::Value {
+                return self.error(format!("'{}' is a value; 'has' needs an entity", path));
+            }
+            self.pos += 1;
+            self.expect_punct("(")?;
+            let expr = self.expr(target)?;
+            self.expect_punct(")")?;
+            return Ok(Expr::Has(path, Box::new(expr)));
+        }
+        if self.eat_keyword("in") {
+            self.expect_punct("(")?;
+            let mut values = vec![self.literal()?];
+            while self.eat_punct(",") {
+                values.push(self.literal()?);
+            }
+            self.expect_punct(")")?;
+            return Ok(Expr::In(path, values));
+        }
+        let op = if self.eat_punct("=") {
+            CmpOp::Eq
+        } else if self.eat_punct("!=") {
+            CmpOp::Ne
+        } else if self.eat_keyword("like") {
+            CmpOp::Like
+        } else {
+            return Ok(Expr::Exists(path));
+        };
+        Ok(Expr::Compare(path, op, self.literal()?))
+    }
+
+    fn literal(&mut self) -> Result<String, String> {
+        match self.peek().cloned() {
+            Some(Token::Quoted(value)) | Some(Token::Word(value)) => {
+                self.pos += 1;
+                Ok(value)
+            }
+            _ => self.error("expected a value".to_string()),
+        }
+    }
+
+    // a path and the kind it evaluates to
+    fn path(&mut self, kind: Kind) -> Result<(Path, Kind), String> {
+        let word = match self.peek() {
+            Some(Token::Word(w)) if !KEYWORDS.contains(&w.as_str()) => w.to_string(),
+            _ => return self.error("expected a field".to_string()),
+        };
+        let mut current = kind;
+        let fields: Vec<String> = word.split('.').map(|s| s.to_string()).collect();
+        for field in &fields {
+            let known = current.fields();
+            match known.iter().find(|(name, _)| name == field) {
+                Some((_, next)) => current = *next,
+                None if known.is_empty() => return self.error(format!(
+                    "'{}' is a value and has no field '{}'", word, field)),
+                None => {
+                    let names: Vec<&str> = known.iter().map(|(n, _)| *n).collect();
+                    return self.error(format!("unknown field '{}' of {}; expected one of {}",
+                                              field, current.name(), names.join(", ")));
+                }
+            }
+        }
+        self.pos += 1;
+        Ok((Path { fields }, current))
+    }
+}
+
+/// Parses a query, checking every path against the entity it applies to.
+pub fn parse_query(text: &str) -> Result<Query, String> {
+    let tokens = tokenize(text)?;
+    let end = text.chars().count() + 1;
+    Parser { tokens, pos: 0, end }.query()
+}
+
+/*
+ * Evaluation
+ */
+
+#[derive(Debug, Clone)]
+enum Node<'a> {
+    Subject(&'a SubjectDomain),
+    Object(&'a ObjectDomain),
+    ObjectId(&'a ObjectDomain, &'a ObjectID),
+    Privilege(usize, &'a Privilege),
+    // a plain value, or a reference to an undefined domain
+    Value(String),
+}
+
+impl Node<'_> {
+    // the value compared against literals
+    fn value(&self) -> String {
+        match self {
+            Node::Subject(sd) => sd.name().to_string(),
+            Node::Object(od) => od.name().to_string(),
+            Node::ObjectId(_, id) => id.to_string(),
+            Node::Privilege(i, _) => i.to_string(),
+            Node::Value(v) => v.to_string(),
+        }
+    }
+
+    // how the node is shown as a row of its own
+    fn label(&self) -> String {
+        match self {
+            Node::Privilege(i, p) => format!("privileges[{}] {}", i, p.principal().subject()),
+            _ => self.value(),
+        }
+    }
+}
+
+struct Evaluator<'a> {
+    priv_map: &'a CPMPrivMap,
+    subjects: HashMap<&'a str, &'a SubjectDomain>,
+    objects: HashMap<&'a str, &'a ObjectDomain>,
+}
+
+impl<'a> Evaluator<'a> {
+    fn new(priv_map: &'a CPMPrivMap) -> Self {
+        let mut subjects = HashMap::new();
+        for sd in priv_map.subject_map() {
+            subjects.entry(sd.name()).or_insert(sd);
+        }
+        let mut objects = HashMap::new();
+        for od in priv_map.object_map() {
+            objects.entry(od.name()).or_insert(od);
+        }
+        Self { priv_map, subjects, objects }
+    }
+
+    fn rows(&self, entity: Entity) -> Vec<Node<'a>> {
+        let priv_map = self.priv_map;
+        match entity {
+            Entity::Subjects => priv_map.subject_map().iter().map(Node::Subject).collect(),
+            Entity::Objects => priv_map.object_map().iter().map(Node::Object).collect(),
+            Entity::ObjectIds => priv_map.object_map().iter()
+                .flat_map(|od| od.objects().iter().map(move |id| Node::ObjectId(od, id)))
+                .collect(),
+            Entity::Privileges => priv_map.privileges().iter().enumerate()
+                .map(|(i, p)| Node::Privilege(i, p))
+                .collect(),
+        }
+    }
+
+    fn subject(&self, name: &str) -> Node<'a> {
+        match self.subjects.get(name) {
+            Some(sd) => Node::Subject(sd),
+            None => Node::Value(name.to_string()),
+        }
+    }
+
+    fn object(&self, name: &str) -> Node<'a> {
+        match self.objects.get(name) {
+            Some(od) => Node::Object(od),
+            None => Node::Value(name.to_string()),
+        }
+    }
+
+    fn subjects_of(&self, field: &CallRetPrivField) -> Vec<Node<'a>> {
+        match field {
+            CallRetPrivField::All => self.priv_map.subject_map().iter().map(Node::Subject).collect(),
+            CallRetPrivField::List(names) => names.iter().map(|n| self.subject(n)).collect(),
+        }
+    }
+
+    fn objects_of(&self, field: &RWPrivField) -> Vec<Node<'a>> {
+        match field {
+            RWPrivField::All => self.priv_map.object_map().iter().map(Node::Object).collect(),
+            RWPrivField::List(objects) => objects.iter()
+                .flat_map(|o| o.objects().iter())
+                .map(|n| self.object(n))
+                .collect(),
+        }
+    }
+
+    // principals of the privileges matching `grants`
+    fn principals_where(&self, grants: impl Fn(&Privilege) -> bool) -> Vec<Node<'a>> {
+        self.priv_map.privileges().iter()
+            .filter(|p| grants(p))
+            .map(|p| self.subject(p.principal().subject()))
+            .collect()
+    }
+
+    fn field(&self, node: &Node<'a>, field: &str) -> Vec<Node<'a>> {
+        let value = |s: &str| vec![Node::Value(s.to_string())];
+        match (node, field) {
+            (Node::Subject(sd), "name") => value(sd.name()),
+            (Node::Subject(sd), "subjects") => sd.subjects().iter().map(|s| Node::Value(s.to_string())).collect(),
+            (Node::Subject(sd), "privileges") => self.priv_map.privileges().iter().enumerate()
+                .filter(|(_, p)| p.principal().subject() == sd.name())
+                .map(|(i, p)| Node::Privilege(i, p))
+                .collect(),
+            (Node::Subject(sd), "callers") => self.principals_where(|p| match p.can_call() {
+                CallRetPrivField::All => true,
+                CallRetPrivField::List(names) => names.iter().any(|n| n == sd.name()),
+            }),
+
+            (Node::Object(od), "name") => value(od.name()),
+            (Node::Object(od), "objects") => od.objects().iter().map(|id| Node::ObjectId(od, id)).collect(),
+            (Node::Object(od), "readers") => self.principals_where(|p| p.can_read().contains_domain(od.name())),
+            (Node::Object(od), "writers") => self.principals_where(|p| p.can_write().contains_domain(od.name())),
+
+            (Node::ObjectId(_, id), "alloc_type") => value(&id.alloc_type().to_string()),
+            (Node::ObjectId(_, id), "path") => value(id.path()),
+            (Node::ObjectId(_, id), "line") => value(id.lineno()),
+            (Node::ObjectId(_, id), "name") => value(id.name()),
+            (Node::ObjectId(od, _), "domain") => vec![Node::Object(od)],
+
+            (Node::Privilege(i, _), "index") => value(&i.to_string()),
+            (Node::Privilege(_, p), "principal") => vec![self.subject(p.principal().subject())],
+            (Node::Privilege(_, p), "uid") => value(&context_value(p.principal().execution_context(), true)),
+            (Node::Privilege(_, p), "gid") => value(&context_value(p.principal().execution_context(), false)),
+            (Node::Privilege(_, p), "call_context") => match p.principal().execution_context() {
+                ContextField::Context(context) => match context.call_context() {
+                    Some(CallContextSubField::List(names)) => names.iter().map(|n| self.subject(n)).collect(),
+                    _ => self.priv_map.subject_map().iter().map(Node::Subject).collect(),
+                },
+                ContextField::All => self.priv_map.subject_map().iter().map(Node::Subject).collect(),
+            },
+            (Node::Privilege(_, p), "can_call") => self.subjects_of(p.can_call()),
+            (Node::Privilege(_, p), "can_return") => self.subjects_of(p.can_return()),
+            (Node::Privilege(_, p), "can_read") => self.objects_of(p.can_read()),
+            (Node::Privilege(_, p), "can_write") => self.objects_of(p.can_write()),
+            (Node::Privilege(_, p), "all_grants") => PrivField::ALL.into_iter()
+                .filter(|f| p.grants_all(*f))
+                .map(|f| Node::Value(f.name().to_string()))
+                .collect(),
+            (Node::Privilege(_, p), "implicit") => PrivField::ALL.into_iter()
+                .filter(|f| p.implicit().is_implicit(*f))
+                .map(|f| Node::Value(f.name().to_string()))
+                .collect(),
+
+            // undefined references have no fields
+            _ => vec![],
+        }
+    }
+
+    fn path(&self, node: &Node<'a>, path: &Path) -> Vec<Node<'a>> {
+        let mut nodes = vec![node.clone()];
+        for field in &path.fields {
+            nodes = nodes.iter().flat_map(|n| self.field(n, field)).collect();
+        }
+        nodes
+    }
+
+    fn matches(&self, node: &Node<'a>, expr: &Expr) -> bool {
+        match expr {
+            Expr::And(a, b) => self.matches(node, a) && self.matches(node, b),
+            Expr::Or(a, b) => self.matches(node, a) || self.matches(node, b),
+            Expr::Not(e) => !self.matches(node, e),
+            Expr::Exists(path) => !self.path(node, path).is_empty(),
+            Expr::Compare(path, CmpOp::Ne, literal) => !self.path(node, path).iter().any(|n| n.value() == *literal),
+            Expr::Compare(path, CmpOp::Eq, literal) => self.path(node, path).iter().any(|n| n.value() == *literal),
+            Expr::Compare(path, CmpOp::Like, pattern) => self.path(node, path).iter()
+                .any(|n| glob_match(pattern, &n.value())),
+            Expr::In(path, literals) => self.path(node, path).iter().any(|n| literals.contains(&n.value())),
+            Expr::Has(path, e) => self.path(node, path).iter().any(|n| self.matches(n, e)),
+        }
+    }
+}
+
+// the uid or gid of an execution context, "all" when unrestricted
+fn context_value(context: &ContextField, uid: bool) -> String {
+    let field = match context {
+        ContextField::Context(context) if uid => context.uid(),
+        ContextField::Context(context) => context.gid(),
+        ContextField::All => &None,
+    };
+    match field {
+        Some(ContextSimpleString::String(value)) => value.to_string(),
+        _ => "all".to_string(),
+    }
+}
+
+/// Matches `text` against a glob where `*` is any run of characters and `?` any one character.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let (p, t): (Vec<char>, Vec<char>) = (pattern.chars().collect(), text.chars().collect());
+    let (mut pi, mut ti) = (0, 0);
+    // position of the last `*` and the text position it was tried at
+    let mut star: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else if let Some((sp, st)) = star {
+            pi = sp + 1;
+            ti = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+impl Query {
+    pub fn evaluate(&self, priv_map: &CPMPrivMap) -> QueryResult {
+        let evaluator = Evaluator::new(priv_map);
+        let rows = evaluator.rows(self.entity).into_iter()
+            .filter(|node| self.filter.as_ref().is_none_or(|f| evaluator.matches(node, f)));
+
+        let mut result = QueryResult::default();
+        if self.select.is_empty() {
+            result.columns.push(self.entity.kind().name().to_string());
+            result.rows = rows.map(|node| vec![node.label()]).collect();
+        } else {
+            result.columns = self.select.iter().map(|p| p.to_string()).collect();
+            result.rows = rows
+                .map(|node| self.select.iter()
+                    .map(|path| {
+                        let mut seen = HashSet::new();
+                        let values: Vec<String> = evaluator.path(&node, path).iter()
+                            .map(|n| n.label())
+                            .filter(|v| seen.insert(v.to_string()))
+                            .collect();
+                        values.join(", ")
+                    })
+                    .collect())
+                .collect();
+        }
+        if self.distinct {
+            let mut seen = HashSet::new();
+            result.rows.retain(|row| seen.insert(row.clone()));
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpm_priv_map::LoadMode;
+
+    const POLICY: &str = "
+object_map:
+  - name: net_globals
+    objects: [GLOBAL|net/core/dev.c|10|dev_base, HEAP|net/core/skb.c|20|kmalloc]
+  - name: fs_globals
+    objects: [GLOBAL|fs/inode.c|30|inode_cache]
+subject_map:
+  - name: net
+    subjects: [dev_queue_xmit]
+  - name: fs
+    subjects: [iput]
+  - name: user_net
+    subjects: [sendmsg]
+privileges:
+  - principal:
+      subject: net
+      execution_context:
+        uid: root
+    can_call: [fs]
+    can_return: []
+    can_read: []
+    can_write:
+      - objects: [net_globals]
+  - principal:
+      subject: user_net
+      execution_context:
+        uid: user
+    can_call: [net]
+    can_return: []
+    can_read:
+      - objects: [net_globals]
+    can_write:
+      - objects: [fs_globals]
+  - principal:
+      subject: fs
+    can_call: []
+    can_return: [net]
+    can_read: all
+";
+
+    fn map() -> CPMPrivMap {
+        CPMPrivMap::from_yaml_str(POLICY, LoadMode::AllowByDefault).unwrap()
+    }
+
+    fn column(result: &QueryResult) -> Vec<&str> {
+        result.rows.iter().map(|r| r[0].as_str()).collect()
+    }
+
+    #[test]
+    fn test_correlated_join() {
+        let result = run_query(&map(), r#"
+            from subjects where privileges has (uid = user and
+                can_write.objects has (alloc_type = GLOBAL and path like "net/*"))"#).unwrap();
+        assert!(result.rows.is_empty());
+
+        // uid user writes fs/, root writes net/; uncorrelated conditions would match user_net
+        let result = run_query(&map(), r#"
+            from subjects where privileges.uid = user and privileges.can_write.objects.path like "net/*""#).unwrap();
+        assert!(result.rows.is_empty());
+        let result = run_query(&map(), r#"
+            from subjects where privileges has (can_write.objects has (alloc_type = GLOBAL and path like "net/*"))"#).unwrap();
+        assert_eq!(column(&result), vec!["net", "fs"]);
+    }
+
+    #[test]
+    fn test_projection() {
+        let result = run_query(&map(), "from privileges where all_grants = can_write select principal, implicit").unwrap();
+        assert_eq!(result.columns, vec!["principal", "implicit"]);
+        assert_eq!(result.rows, vec![vec!["fs", "can_write"]]);
+
+        let result = run_query(&map(), "from object_ids where domain.readers in (user_net, nobody) select name").unwrap();
+        assert_eq!(column(&result), vec!["dev_base", "kmalloc"]);
+
+        let result = run_query(&map(), "from objects select distinct writers").unwrap();
+        assert_eq!(result.rows, vec![vec!["net, fs"], vec!["user_net, fs"]]);
+
+        let result = run_query(&map(), "from privileges where not uid != root").unwrap();
+        assert_eq!(column(&result), vec!["privileges[0] net"]);
+        assert_eq!(run_query(&map(), "from subjects where callers").unwrap().rows.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = |q: &str| parse_query(q).unwrap_err();
+        assert_eq!(err("from things"), "column 6: unknown entity 'things'; expected subjects, objects, object_ids or privileges");
+        assert_eq!(err("from subjects where nme = x"),
+                   "column 21: unknown field 'nme' of subject; expected one of name, subjects, privileges, callers");
+        assert_eq!(err("from privileges where uid has (name = x)"), "column 27: 'uid' is a value; 'has' needs an entity");
+        assert_eq!(err("from privileges where (uid = x"), "column 31: expected ')'");
+        assert_eq!(err("from objects select \"name"), "column 21: unterminated string");
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("net/*", "net/core/dev.c"));
+        assert!(glob_match("*dev?c", "net/core/dev.c"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("net/*", "fs/net/x"));
+        assert!(!glob_match("a?", "a"));
+    }
+}
@@ -0,0 +1,294 @@
+// SPDX-License-Identifier: MIT
+//
+// MIT License
+//
+// © 2024 Nathan Dautenhahn & Serenitix LLC
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::fmt;
+use std::fs;
+
+use crate::algebra::{PermissionSet, Universe};
+use crate::cpm_priv_map::{CPMPrivMap, PrivField, Privilege};
+use crate::edit::{rewrite_object_refs, rewrite_subject_refs};
+use crate::patterns::DomainRef;
+
+/*
+ * Refinement of an abstract (architectural) policy by a concrete one.
+ *
+ *   subjects:
+ *     - {concrete: "glob:tcp_*", abstract: net}
+ *     - {concrete: vfs_read, abstract: fs}
+ *   objects:
+ *     - {concrete: "re:HeapObjectDomain[0-9]+\\.skb.*", abstract: net_mem}
+ *
+ * `concrete` is a domain name or a `glob:`/`re:` pattern; the first rule
+ * matching a concrete domain maps it, and a domain matching none maps to
+ * the abstract domain of the same name if there is one. Each concrete
+ * privilege is translated into abstract names and must be covered, in the
+ * sense of `algebra::PermissionSet::is_subset`, by the privileges of its
+ * principal's abstract domain. Contexts are translated along with the rest.
+ */
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MappingRule {
+    pub concrete: String,
+    #[serde(rename = "abstract")]
+    pub abstract_domain: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RefinementMapping {
+    #[serde(default)]
+    pub subjects: Vec<MappingRule>,
+    #[serde(default)]
+    pub objects: Vec<MappingRule>,
+}
+
+impl RefinementMapping {
+    pub fn from_yaml_str(yaml: &str) -> Result<Self, String> {
+        serde_yaml::from_str(yaml).map_err(|e| format!("Failed to parse refinement mapping: {}", e))
+    }
+
+    pub fn load(file_path: &str) -> Result<Self, String> {
+        let yaml = fs::read_to_string(file_path)
+            .map_err(|e| format!("Failed to read refinement mapping: {}", e))?;
+        Self::from_yaml_str(&yaml)
+    }
+}
+
+// compiled rules of one kind, with the abstract names to fall back on
+struct Rules<'a> {
+    rules: Vec<(DomainRef, &'a str)>,
+    abstract_names: Vec<&'a str>,
+}
+
+impl<'a> Rules<'a> {
+    fn compile(rules: &'a [MappingRule], abstract_names: Vec<&'a str>, kind: &str) -> Result<Self, String> {
+        let mut compiled = vec![];
+        for rule in rules {
+            if !abstract_names.contains(&rule.abstract_domain.as_str()) {
+                return Err(format!("mapping for '{}' names undefined abstract {} domain '{}'",
+                                   rule.concrete, kind, rule.abstract_domain));
+            }
+            compiled.push((DomainRef::parse(&rule.concrete)?, rule.abstract_domain.as_str()));
+        }
+        Ok(Rules { rules: compiled, abstract_names })
+    }
+
+    fn map(&self, concrete: &str) -> Option<&'a str> {
+        match self.rules.iter().find(|(r, _)| r.matches(concrete)) {
+            Some((_, name)) => Some(name),
+            None => self.abstract_names.iter().find(|n| **n == concrete).copied(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RefinementViolation {
+    // index of the concrete privilege
+    pub privilege: usize,
+    pub subject: String,
+    pub abstract_subject: Option<String>,
+    // indices of the abstract privileges of abstract_subject
+    pub abstract_rules: Vec<usize>,
+    // abstract (field, target) grants not covered by the abstract rules
+    pub exceeded: Vec<(PrivField, String)>,
+    // concrete domains no rule maps
+    pub unmapped: Vec<String>,
+}
+
+impl fmt::Display for RefinementViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "privileges[{}] ({}", self.privilege, self.subject)?;
+        if let Some(abstract_subject) = &self.abstract_subject {
+            write!(f, " -> {}", abstract_subject)?;
+        }
+        write!(f, "): ")?;
+        if !self.unmapped.is_empty() {
+            return write!(f, "no abstract domain for {}", self.unmapped.join(", "));
+        }
+        let grants: Vec<String> = self.exceeded.iter().map(|(field, target)| format!("{} {}", field, target)).collect();
+        write!(f, "{} ", grants.join(", "))?;
+        match self.abstract_rules.as_slice() {
+            [] => write!(f, "not granted by any abstract privilege"),
+            rules => {
+                let rules: Vec<String> = rules.iter().map(|i| format!("privileges[{}]", i)).collect();
+                write!(f, "exceeds abstract {}", rules.join(", "))
+            }
+        }
+    }
+}
+
+/// Every concrete privilege not permitted by the abstract policy, in privilege order.
+pub fn check_refinement(concrete: &CPMPrivMap, abstract_map: &CPMPrivMap, mapping: &RefinementMapping)
+                        -> Result<Vec<RefinementViolation>, String> {
+    let subjects = Rules::compile(&mapping.subjects,
+                                  abstract_map.subject_map().iter().map(|sd| sd.name()).collect(), "subject")?;
+    let objects = Rules::compile(&mapping.objects,
+                                 abstract_map.object_map().iter().map(|od| od.name()).collect(), "object")?;
+    let universe = Universe::of(&[abstract_map]);
+
+    let mut violations = vec![];
+    for (index, privilege) in concrete.privileges().iter().enumerate() {
+        let subject = privilege.principal().subject();
+        let unmapped = RefCell::new(vec![]);
+        let translate = |rules: &Rules, name: &str| match rules.map(name) {
+            Some(mapped) => vec![mapped.to_string()],
+            None => {
+                if !unmapped.borrow().iter().any(|n| n == name) {
+                    unmapped.borrow_mut().push(name.to_string());
+                }
+                vec![name.to_string()]
+            }
+        };
+        let mut translated = rewrite_subject_refs(privilege, &|n| translate(&subjects, n));
+        translated = rewrite_object_refs(&translated, &|n| translate(&objects, n));
+        let abstract_subject = subjects.map(subject);
+        let mut violation = RefinementViolation {
+            privilege: index,
+            subject: subject.to_string(),
+            abstract_subject: abstract_subject.map(String::from),
+            abstract_rules: vec![],
+            exceeded: vec![],
+            unmapped: unmapped.into_inner(),
+        };
+        let Some(abstract_subject) = abstract_subject else {
+            violation.unmapped.insert(0, subject.to_string());
+            violations.push(violation);
+            continue;
+        };
+        if !violation.unmapped.is_empty() {
+            violations.push(violation);
+            continue;
+        }
+        translated.principal.subject = abstract_subject.to_string();
+
+        let mut allowed = PermissionSet::new(universe.clone());
+        for (i, rule) in abstract_map.privileges().iter().enumerate() {
+            if rule.principal().subject() == abstract_subject {
+                allowed.add_privilege(rule);
+                violation.abstract_rules.push(i);
+            }
+        }
+        let excess = permission_set(&translated, &universe).difference(&allowed);
+        if !excess.is_empty() {
+            for key in excess.grants().keys() {
+                let grant = (key.field, key.target.to_string());
+                if !violation.exceeded.contains(&grant) {
+                    violation.exceeded.push(grant);
+                }
+            }
+            violations.push(violation);
+        }
+    }
+    Ok(violations)
+}
+
+fn permission_set(privilege: &Privilege, universe: &Universe) -> PermissionSet {
+    let mut set = PermissionSet::new(universe.clone());
+    set.add_privilege(privilege);
+    set
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ABSTRACT: &str = "
+object_map:
+  - {name: net_mem, objects: []}
+  - {name: fs_mem, objects: []}
+subject_map:
+  - {name: net, subjects: []}
+  - {name: fs, subjects: []}
+privileges:
+  - principal: {subject: net}
+    can_call: [fs]
+    can_return: [fs]
+    can_read: [{objects: [net_mem]}]
+    can_write: [{objects: [net_mem]}]
+  - principal: {subject: fs}
+    can_call: []
+    can_return: [net]
+    can_read: all
+    can_write: []
+";
+
+    const CONCRETE: &str = "
+object_map:
+  - {name: skb1, objects: []}
+  - {name: inode, objects: []}
+subject_map:
+  - {name: tcp_tx, subjects: []}
+  - {name: udp_tx, subjects: []}
+  - {name: vfs_read, subjects: []}
+privileges:
+  - principal: {subject: tcp_tx}
+    can_call: [vfs_read]
+    can_return: []
+    can_read: [{objects: [skb1]}]
+    can_write: [{objects: [skb1]}]
+  - principal: {subject: udp_tx}
+    can_call: []
+    can_return: []
+    can_read: []
+    can_write: [{objects: [skb1, inode]}]
+  - principal: {subject: vfs_read}
+    can_call: [tcp_tx]
+    can_return: all
+    can_read: [{objects: [inode, skb1]}]
+    can_write: []
+";
+
+    const MAPPING: &str = "
+subjects:
+  - {concrete: 'glob:*_tx', abstract: net}
+  - {concrete: 're:vfs_.*', abstract: fs}
+objects:
+  - {concrete: 'glob:skb*', abstract: net_mem}
+  - {concrete: inode, abstract: fs_mem}
+";
+
+    #[test]
+    fn test_refinement() {
+        let abstract_map: CPMPrivMap = serde_yaml::from_str(ABSTRACT).unwrap();
+        let concrete: CPMPrivMap = serde_yaml::from_str(CONCRETE).unwrap();
+        let mapping = RefinementMapping::from_yaml_str(MAPPING).unwrap();
+        let violations: Vec<String> = check_refinement(&concrete, &abstract_map, &mapping).unwrap()
+            .iter().map(|v| v.to_string()).collect();
+        assert_eq!(violations, vec![
+            "privileges[1] (udp_tx -> net): can_write fs_mem exceeds abstract privileges[0]",
+            "privileges[2] (vfs_read -> fs): can_call net, can_return fs exceeds abstract privileges[1]",
+        ]);
+        assert!(check_refinement(&abstract_map, &abstract_map, &RefinementMapping::default()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_unmapped() {
+        let abstract_map: CPMPrivMap = serde_yaml::from_str(ABSTRACT).unwrap();
+        let concrete: CPMPrivMap = serde_yaml::from_str(CONCRETE).unwrap();
+        let mapping = RefinementMapping::from_yaml_str("subjects: [{concrete: tcp_tx, abstract: net}]").unwrap();
+        let violations: Vec<String> = check_refinement(&concrete, &abstract_map, &mapping).unwrap()
+            .iter().map(|v| v.to_string()).collect();
+        assert_eq!(violations[0], "privileges[0] (tcp_tx -> net): no abstract domain for vfs_read, skb1");
+        assert_eq!(violations[1], "privileges[1] (udp_tx): no abstract domain for udp_tx, skb1, inode");
+
+        let bad = RefinementMapping::from_yaml_str("objects: [{concrete: x, abstract: nowhere}]").unwrap();
+        assert_eq!(check_refinement(&concrete, &abstract_map, &bad).unwrap_err(),
+                   "mapping for 'x' names undefined abstract object domain 'nowhere'");
+    }
+}
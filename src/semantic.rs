@@ -0,0 +1,230 @@
+// SPDX-License-Identifier: MIT
+//
+// MIT License
+//
+// © 2024 Nathan Dautenhahn & Serenitix LLC
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::cpm_priv_map::{
+    CPMPrivMap, CallContextSubField, CallRetPrivField, ContextField, PrivField, Privilege, RWPrivField,
+};
+use crate::hierarchy::hierarchy_errors;
+use crate::parallel::Parallelism;
+use crate::patterns::{DomainRef, PatternCache};
+
+/*
+ * Semantic validation: the checks the JSON schema cannot express.
+ *
+ *  - every domain name is defined once across object_map and subject_map
+ *  - every principal, `can_call`, `can_return` and `call_context` name is a
+ *    subject domain
+ *  - every `can_read`/`can_write` object name is an object domain
+ *  - every pattern reference is valid and matches at least one domain
+ *
+ * Reference resolution is independent per privilege, so it can be spread
+ * over threads. Errors are reported in a fixed order (domain errors first,
+ * then by privilege index and field) regardless of the parallelism used.
+ */
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SemanticError {
+    // index of the privilege the error is in, None for domain definitions
+    pub privilege: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for SemanticError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.privilege {
+            Some(i) => write!(f, "privileges[{}]: {}", i, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+struct DomainNames<'a> {
+    subjects: HashSet<&'a str>,
+    objects: HashSet<&'a str>,
+    patterns: PatternCache,
+}
+
+/// Runs every semantic check over the map.
+pub fn validate_semantics(priv_map: &CPMPrivMap, par: Parallelism) -> Vec<SemanticError> {
+    let mut errors = vec![];
+    let mut defined: HashSet<&str> = HashSet::new();
+    let names = priv_map.object_map().iter().map(|od| ("object", od.name()))
+        .chain(priv_map.subject_map().iter().map(|sd| ("subject", sd.name())));
+    for (kind, name) in names {
+        if !defined.insert(name) {
+            errors.push(SemanticError {
+                privilege: None,
+                message: format!("{} domain '{}' is defined more than once", kind, name),
+            });
+        }
+    }
+    errors.extend(hierarchy_errors(priv_map).iter().map(|e| SemanticError { privilege: None, message: e.to_string() }));
+
+    let domains = DomainNames {
+        subjects: priv_map.subject_map().iter().map(|sd| sd.name()).collect(),
+        objects: priv_map.object_map().iter().map(|od| od.name()).collect(),
+        patterns: PatternCache::new(priv_map),
+    };
+    let per_privilege = par.map(priv_map.privileges(), |i, privilege| check_privilege(&domains, i, privilege));
+    errors.extend(per_privilege.into_iter().flatten());
+    errors
+}
+
+fn check_privilege(domains: &DomainNames, index: usize, privilege: &Privilege) -> Vec<SemanticError> {
+    let mut errors = vec![];
+    let mut undefined = |what: String| errors.push(SemanticError {
+        privilege: Some(index),
+        message: what,
+    });
+
+    let principal = privilege.principal();
+    if !domains.subjects.contains(principal.subject().as_str()) {
+        undefined(format!("principal '{}' is not a subject domain", principal.subject()));
+    }
+    if let ContextField::Context(context) = principal.execution_context() {
+        if let Some(CallContextSubField::List(names)) = context.call_context() {
+            for name in names {
+                if let Some(message) = check_reference(name, &domains.subjects, &domains.patterns, "call_context", "subject") {
+                    undefined(message);
+                }
+            }
+        }
+    }
+
+    for field in PrivField::ALL {
+        let names: Vec<&String> = match field {
+            PrivField::CanCall => list_of(privilege.can_call()),
+            PrivField::CanReturn => list_of(privilege.can_return()),
+            PrivField::CanRead => objects_of(privilege.can_read()),
+            PrivField::CanWrite => objects_of(privilege.can_write()),
+        };
+        let (known, kind) = match field {
+            PrivField::CanCall | PrivField::CanReturn => (&domains.subjects, "subject"),
+            PrivField::CanRead | PrivField::CanWrite => (&domains.objects, "object"),
+        };
+        for name in names {
+            if let Some(message) = check_reference(name, known, &domains.patterns, field.name(), kind) {
+                undefined(message);
+            }
+        }
+    }
+    errors
+}
+
+// why a domain reference does not resolve, if it does not
+fn check_reference(reference: &str, known: &HashSet<&str>, patterns: &PatternCache, field: &str, kind: &str)
+    -> Option<String>
+{
+    if !DomainRef::is_pattern(reference) {
+        return match known.contains(reference) {
+            true => None,
+            false => Some(format!("{} names undefined {} domain '{}'", field, kind, reference)),
+        };
+    }
+    match patterns.get(reference) {
+        Err(e) => Some(format!("{} has an {}", field, e)),
+        Ok(pattern) if !known.iter().any(|n| pattern.matches(n)) => {
+            Some(format!("{} pattern '{}' matches no {} domain", field, reference, kind))
+        }
+        Ok(_) => None,
+    }
+}
+
+fn list_of(field: &CallRetPrivField) -> Vec<&String> {
+    match field {
+        CallRetPrivField::List(names) => names.iter().collect(),
+        CallRetPrivField::All => vec![],
+    }
+}
+
+fn objects_of(field: &RWPrivField) -> Vec<&String> {
+    match field {
+        RWPrivField::List(objects) => objects.iter().flat_map(|o| o.objects().iter()).collect(),
+        RWPrivField::All => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: &str = "
+object_map:
+  - name: od1
+    objects: [a]
+  - name: s1
+    objects: [b]
+subject_map:
+  - name: s1
+    subjects: [f1]
+  - name: s2
+    subjects: [f2]
+privileges:
+  - principal:
+      subject: s1
+      execution_context:
+        call_context: [s2, s9]
+    can_call: [s2, s3, 'glob:s*', 'glob:x*', 're:(']
+    can_return: all
+    can_read:
+      - objects: [od1, od7]
+    can_write: []
+  - principal:
+      subject: ghost
+    can_call: []
+    can_return: [s1]
+    can_read: []
+    can_write:
+      - objects: [s2]
+";
+
+    #[test]
+    fn test_validate_semantics() {
+        let priv_map: CPMPrivMap = serde_yaml::from_str(POLICY).unwrap();
+        let errors: Vec<String> = validate_semantics(&priv_map, Parallelism::Sequential)
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(errors, vec![
+            "subject domain 's1' is defined more than once",
+            "privileges[0]: call_context names undefined subject domain 's9'",
+            "privileges[0]: can_call names undefined subject domain 's3'",
+            "privileges[0]: can_call pattern 'glob:x*' matches no subject domain",
+            "privileges[0]: can_call has an invalid regex in 're:(': unclosed group",
+            "privileges[0]: can_read names undefined object domain 'od7'",
+            "privileges[1]: principal 'ghost' is not a subject domain",
+            "privileges[1]: can_write names undefined object domain 's2'",
+        ]);
+    }
+
+    #[test]
+    fn test_parallel_validation_is_deterministic() {
+        let mut yaml = String::from("object_map: []\nsubject_map: [{name: s, subjects: []}]\nprivileges:\n");
+        for i in 0..200 {
+            yaml.push_str(&format!("  - principal: {{subject: s}}\n    can_call: [missing{}]\n", i));
+        }
+        let priv_map: CPMPrivMap = serde_yaml::from_str(&yaml).unwrap();
+        let sequential = validate_semantics(&priv_map, Parallelism::Sequential);
+        assert_eq!(sequential.len(), 200);
+        for threads in [2, 3, 8] {
+            assert_eq!(validate_semantics(&priv_map, Parallelism::Threads(threads)), sequential);
+        }
+    }
+}
@@ -0,0 +1,363 @@
+// SPDX-License-Identifier: MIT
+//
+// MIT License
+//
+// © 2024 Nathan Dautenhahn & Serenitix LLC
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+use serde::Serialize;
+use std::collections::{BTreeSet, VecDeque};
+
+use crate::cpm_priv_map::{CPMPrivMap, LoadMode, PrivField};
+use crate::diff::diff_policies;
+use crate::hierarchy::load_flattened;
+use crate::index::IndexedPrivMap;
+use crate::parallel::Parallelism;
+use crate::permissions::{who_can, DomainSet, EffectivePermissions};
+use crate::query::{glob_match, run_query};
+
+/*
+ * Command interpreter behind `cpm_if shell`. The policy is loaded and indexed
+ * once; every command returns its output as text, and commands that list
+ * domains make them the current selection, which `export` writes out as a
+ * policy restricted to the selected domains. Line editing, history and
+ * completion are left to the terminal front end, which uses `complete`.
+ */
+
+pub const COMMANDS: &[&str] = &["help", "list", "show", "who-can", "reach", "diff", "query", "export", "reload", "quit"];
+
+const HELP: &str = "\
+list subjects|objects [<glob>]   list domain names
+show <domain>                    print a domain and the privileges involving it
+who-can call|return|read|write <domain>
+                                 subject domains granted the access
+reach <subject>                  domains reachable through transitive calls
+diff <file.yaml>                 compare the loaded policy with another file
+query <query>                    run a query (see `cpm_if query`)
+export <file.yaml>               write the selected domains as a policy
+reload                           load the policy file again
+quit                             leave the shell";
+
+/// What the front end should do after a command.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShellOutput {
+    Text(String),
+    Quit,
+}
+
+pub struct Shell {
+    file_path: String,
+    map: IndexedPrivMap,
+    // domain names listed by the last listing command
+    selection: Vec<String>,
+}
+
+impl Shell {
+    pub fn new(file_path: &str, priv_map: CPMPrivMap) -> Self {
+        Self {
+            file_path: file_path.to_string(),
+            map: IndexedPrivMap::new(priv_map),
+            selection: vec![],
+        }
+    }
+
+    pub fn load(file_path: &str) -> Result<Self, String> {
+        Ok(Self::new(file_path, load_flattened(file_path, LoadMode::AllowByDefault)?.0))
+    }
+
+    pub fn priv_map(&self) -> &CPMPrivMap {
+        self.map.priv_map()
+    }
+
+    pub fn selection(&self) -> &[String] {
+        &self.selection
+    }
+
+    /// Every subject and object domain name, for completion.
+    pub fn domain_names(&self) -> Vec<&str> {
+        let priv_map = self.priv_map();
+        priv_map.subject_map().iter().map(|sd| sd.name())
+            .chain(priv_map.object_map().iter().map(|od| od.name()))
+            .collect()
+    }
+
+    /// Candidates for the word ending at `pos`, and the position it starts at.
+    pub fn complete(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        let line = &line[..pos];
+        let start = line.rfind(char::is_whitespace).map(|i| i + 1).unwrap_or(0);
+        let word = &line[start..];
+        let previous: Vec<&str> = line[..start].split_whitespace().collect();
+
+        let candidates: Vec<&str> = match previous.as_slice() {
+            [] => COMMANDS.to_vec(),
+            ["list"] => vec!["subjects", "objects"],
+            ["who-can"] => vec!["call", "return", "read", "write"],
+            ["show"] | ["who-can", _] | ["reach"] => self.domain_names(),
+            _ => vec![],
+        };
+        let mut matches: Vec<String> = candidates.into_iter()
+            .filter(|c| c.starts_with(word))
+            .map(|c| c.to_string())
+            .collect();
+        matches.sort();
+        matches.dedup();
+        (start, matches)
+    }
+
+    pub fn execute(&mut self, line: &str) -> Result<ShellOutput, String> {
+        let line = line.trim();
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let args: Vec<&str> = rest.split_whitespace().collect();
+        let text = match (command, args.as_slice()) {
+            ("", _) => String::new(),
+            ("help", _) => HELP.to_string(),
+            ("quit" | "exit", _) => return Ok(ShellOutput::Quit),
+            ("list", [kind]) => self.list(kind, "*")?,
+            ("list", [kind, pattern]) => self.list(kind, pattern)?,
+            ("show", [name]) => self.show(name)?,
+            ("who-can", [access, domain]) => self.who_can(access, domain)?,
+            ("reach", [subject]) => self.reach(subject)?,
+            ("diff", [file]) => self.diff(file)?,
+            ("query", _) if !rest.trim().is_empty() => self.query(rest)?,
+            ("export", [file]) => self.export(file)?,
+            ("reload", []) => {
+                *self = Self::load(&self.file_path)?;
+                format!("reloaded {}", self.file_path)
+            }
+            _ if COMMANDS.contains(&command) => return Err(format!("usage: see `help` for {}", command)),
+            _ => return Err(format!("unknown command '{}', try `help`", command)),
+        };
+        Ok(ShellOutput::Text(text))
+    }
+
+    fn select(&mut self, names: Vec<String>) -> String {
+        self.selection = names;
+        self.selection.join("\n")
+    }
+
+    fn list(&mut self, kind: &str, pattern: &str) -> Result<String, String> {
+        let priv_map = self.map.priv_map();
+        let names: Vec<String> = match kind {
+            "subjects" => priv_map.subject_map().iter().map(|sd| sd.name().to_string()).collect(),
+            "objects" => priv_map.object_map().iter().map(|od| od.name().to_string()).collect(),
+            _ => return Err(format!("cannot list '{}'; expected subjects or objects", kind)),
+        };
+        Ok(self.select(names.into_iter().filter(|n| glob_match(pattern, n)).collect()))
+    }
+
+    fn show(&mut self, name: &str) -> Result<String, String> {
+        let mut out = String::new();
+        if let Some(sd) = self.map.subject_domain(name) {
+            out.push_str(&format!("subject domain:\n{}", to_yaml(sd)));
+            for privilege in self.map.privileges_for(name) {
+                out.push_str(&format!("privilege:\n{}", to_yaml(privilege)));
+            }
+        } else if let Some(od) = self.map.object_domain(name) {
+            out.push_str(&format!("object domain:\n{}", to_yaml(od)));
+            for field in [PrivField::CanRead, PrivField::CanWrite] {
+                let subjects = who_can(self.map.priv_map(), field, name, Parallelism::Sequential);
+                out.push_str(&format!("{} by: {}\n", field, subjects.join(", ")));
+            }
+        } else {
+            return Err(format!("no domain named '{}'", name));
+        }
+        self.selection = vec![name.to_string()];
+        Ok(out.trim_end().to_string())
+    }
+
+    fn who_can(&mut self, access: &str, domain: &str) -> Result<String, String> {
+        let field = match access {
+            "call" => PrivField::CanCall,
+            "return" => PrivField::CanReturn,
+            "read" => PrivField::CanRead,
+            "write" => PrivField::CanWrite,
+            _ => return Err(format!("unknown access '{}'; expected call, return, read or write", access)),
+        };
+        let subjects = who_can(self.map.priv_map(), field, domain, Parallelism::Sequential);
+        Ok(self.select(subjects))
+    }
+
+    /*
+     * Subject domains reachable from `subject` by following can_call edges
+     * transitively, and the object domains any of them can read or write:
+     * what a compromise of `subject` could get to through calls.
+     */
+    fn reach(&mut self, subject: &str) -> Result<String, String> {
+        if self.map.subject_domain(subject).is_none() {
+            return Err(format!("no subject domain named '{}'", subject));
+        }
+        let priv_map = self.map.priv_map();
+        let all_objects = || priv_map.object_map().iter().map(|od| od.name());
+
+        let mut reached: BTreeSet<String> = BTreeSet::from([subject.to_string()]);
+        let mut reads = DomainSet::default();
+        let mut writes = DomainSet::default();
+        let mut queue = VecDeque::from([subject.to_string()]);
+        while let Some(current) = queue.pop_front() {
+            let mut permissions = EffectivePermissions::default();
+            for privilege in self.map.privileges_for(&current) {
+                permissions.add_privilege(privilege);
+            }
+            reads.union_with(&permissions.can_read);
+            writes.union_with(&permissions.can_write);
+            let callees: Vec<String> = permissions.can_call.expand(priv_map.subject_map().iter().map(|sd| sd.name())).iter().map(|s| s.to_string()).collect();
+            for callee in callees {
+                if reached.insert(callee.clone()) {
+                    queue.push_back(callee);
+                }
+            }
+        }
+
+        let reads: Vec<String> = reads.expand(all_objects()).iter().map(|s| s.to_string()).collect();
+        let writes: Vec<String> = writes.expand(all_objects()).iter().map(|s| s.to_string()).collect();
+        let out = format!("calls: {}\nreads: {}\nwrites: {}",
+                          reached.iter().cloned().collect::<Vec<_>>().join(", "),
+                          reads.join(", "), writes.join(", "));
+        let mut selection: Vec<String> = reached.into_iter().collect();
+        for name in reads.into_iter().chain(writes) {
+            if !selection.contains(&name) {
+                selection.push(name);
+            }
+        }
+        self.selection = selection;
+        Ok(out)
+    }
+
+    fn diff(&mut self, file: &str) -> Result<String, String> {
+        let other = load_flattened(file, LoadMode::AllowByDefault)?.0;
+        let changes = diff_policies(self.map.priv_map(), &other);
+        if changes.is_empty() {
+            return Ok("no differences".to_string());
+        }
+        let out = changes.iter().map(|c| c.to_string()).collect::<Vec<_>>().join("\n");
+        let mut names: Vec<String> = vec![];
+        for change in changes {
+            if !names.contains(&change.name) {
+                names.push(change.name);
+            }
+        }
+        self.selection = names;
+        Ok(out)
+    }
+
+    fn query(&mut self, text: &str) -> Result<String, String> {
+        let result = run_query(self.map.priv_map(), text)?;
+        Ok(result.to_string().trim_end().to_string())
+    }
+
+    // the loaded policy restricted to the selection and the privileges of selected subjects
+    pub fn selected_policy(&self) -> CPMPrivMap {
+        let selected = |name: &str| self.selection.iter().any(|s| s == name);
+        let priv_map = self.map.priv_map();
+        let mut policy = CPMPrivMap::new();
+        for od in priv_map.object_map().iter().filter(|od| selected(od.name())) {
+            policy.add_object_domain(od.clone());
+        }
+        for sd in priv_map.subject_map().iter().filter(|sd| selected(sd.name())) {
+            policy.add_subject_domain(sd.clone());
+        }
+        for privilege in priv_map.privileges().iter().filter(|p| selected(p.principal().subject())) {
+            policy.add_privilege(privilege.clone());
+        }
+        policy
+    }
+
+    fn export(&mut self, file: &str) -> Result<String, String> {
+        if self.selection.is_empty() {
+            return Err("nothing selected; list, show, who-can, reach or diff first".to_string());
+        }
+        self.selected_policy().save_to_yaml(file)
+            .map_err(|e| format!("Failed to write {}: {}", file, e))?;
+        Ok(format!("exported {} domain(s) to {}", self.selection.len(), file))
+    }
+}
+
+fn to_yaml<T: Serialize>(value: &T) -> String {
+    serde_yaml::to_string(value).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: &str = "
+object_map:
+  - {name: od1, objects: [a]}
+  - {name: od2, objects: [b]}
+subject_map:
+  - {name: s1, subjects: [f1]}
+  - {name: s2, subjects: [f2]}
+  - {name: s3, subjects: [f3]}
+privileges:
+  - principal: {subject: s1}
+    can_call: [s2]
+    can_return: []
+    can_read: []
+    can_write: []
+  - principal: {subject: s2}
+    can_call: []
+    can_return: [s1]
+    can_read:
+      - objects: [od1]
+    can_write:
+      - objects: [od2]
+  - principal: {subject: s3}
+    can_call: []
+    can_return: []
+    can_read: []
+    can_write:
+      - objects: [od2]
+";
+
+    fn shell() -> Shell {
+        Shell::new("policy.yaml", CPMPrivMap::from_yaml_str(POLICY, LoadMode::AllowByDefault).unwrap())
+    }
+
+    fn text(output: Result<ShellOutput, String>) -> String {
+        match output.unwrap() {
+            ShellOutput::Text(text) => text,
+            ShellOutput::Quit => panic!("unexpected quit"),
+        }
+    }
+
+    #[test]
+    fn test_commands() {
+        let mut shell = shell();
+        assert_eq!(text(shell.execute("list objects *2")), "od2");
+        assert_eq!(text(shell.execute("list subjects s?")), "s1\ns2\ns3");
+        assert_eq!(text(shell.execute("who-can write od2")), "s2\ns3");
+        assert_eq!(shell.selection(), ["s2", "s3"]);
+        assert_eq!(text(shell.execute("reach s1")), "calls: s1, s2\nreads: od1\nwrites: od2");
+        assert_eq!(shell.selection(), ["s1", "s2", "od1", "od2"]);
+
+        let exported = shell.selected_policy();
+        assert_eq!(exported.object_map().len(), 2);
+        assert_eq!(exported.privileges().len(), 2);
+
+        assert!(text(shell.execute("show od2")).ends_with("can_read by: \ncan_write by: s2, s3"));
+        assert!(text(shell.execute("show s1")).starts_with("subject domain:\nname: s1"));
+        assert_eq!(text(shell.execute("query from subjects where callers select name")), "name\ns2");
+        assert_eq!(shell.execute("quit"), Ok(ShellOutput::Quit));
+        assert_eq!(shell.execute("show nothing"), Err("no domain named 'nothing'".to_string()));
+        assert_eq!(shell.execute("frobnicate"), Err("unknown command 'frobnicate', try `help`".to_string()));
+    }
+
+    #[test]
+    fn test_complete() {
+        let shell = shell();
+        assert_eq!(shell.complete("wh", 2), (0, vec!["who-can".to_string()]));
+        assert_eq!(shell.complete("who-can w", 9), (8, vec!["write".to_string()]));
+        assert_eq!(shell.complete("who-can write o", 15), (14, vec!["od1".to_string(), "od2".to_string()]));
+        assert_eq!(shell.complete("reach s", 7).1, vec!["s1", "s2", "s3"]);
+        assert!(shell.complete("diff s", 6).1.is_empty());
+    }
+}
@@ -0,0 +1,170 @@
+// SPDX-License-Identifier: MIT
+//
+// MIT License
+//
+// © 2024 Nathan Dautenhahn & Serenitix LLC
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+/*
+ * Line positions of the entries of a policy file.
+ *
+ * serde_yaml does not keep source positions, so this scans the text for the
+ * three top-level sections and the block-style list items under them. The
+ * n-th item of a section corresponds to the n-th element of the matching
+ * `CPMPrivMap` vector. Flow-style sections (`object_map: [...]`) have no
+ * entries here.
+ */
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntrySpan {
+    // value of the entry's `name:` (domains) or `subject:` (privileges) key
+    pub name: Option<String>,
+    // 1-based, inclusive
+    pub first_line: usize,
+    pub last_line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Copy)]
+pub enum Section {
+    ObjectMap,
+    SubjectMap,
+    Privileges,
+}
+
+impl Section {
+    /// The section's top-level key in the policy file.
+    pub fn key(&self) -> &'static str {
+        match self {
+            Section::ObjectMap => "object_map",
+            Section::SubjectMap => "subject_map",
+            Section::Privileges => "privileges",
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SourceMap {
+    pub object_domains: Vec<EntrySpan>,
+    pub subject_domains: Vec<EntrySpan>,
+    pub privileges: Vec<EntrySpan>,
+}
+
+impl SourceMap {
+    pub fn parse(text: &str) -> Self {
+        let mut map = SourceMap::default();
+        let mut section: Option<Section> = None;
+        let mut item_indent: Option<usize> = None;
+
+        for (i, line) in text.lines().enumerate() {
+            let lineno = i + 1;
+            let trimmed = line.trim_start();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            let indent = line.len() - trimmed.len();
+
+            // a top-level key starts a new section
+            if indent == 0 && !trimmed.starts_with('-') {
+                let key = trimmed.split(':').next().unwrap_or("").trim();
+                section = match key {
+                    "object_map" => Some(Section::ObjectMap),
+                    "subject_map" => Some(Section::SubjectMap),
+                    "privileges" => Some(Section::Privileges),
+                    _ => None,
+                };
+                item_indent = None;
+                continue;
+            }
+            let Some(sec) = section else {
+                continue;
+            };
+            let entries = map.section_mut(sec);
+
+            let is_item = trimmed.starts_with("- ") || trimmed == "-";
+            if is_item && item_indent.is_none_or(|ii| ii == indent) {
+                item_indent = Some(indent);
+                entries.push(EntrySpan { name: None, first_line: lineno, last_line: lineno });
+            }
+            let Some(entry) = entries.last_mut() else {
+                continue;
+            };
+            entry.last_line = lineno;
+
+            let key_text = trimmed.trim_start_matches("- ").trim_start();
+            let name_key = if sec == Section::Privileges { "subject:" } else { "name:" };
+            if entry.name.is_none() {
+                if let Some(value) = key_text.strip_prefix(name_key) {
+                    entry.name = Some(unquote(value.split(" #").next().unwrap_or("").trim()));
+                }
+            }
+        }
+        map
+    }
+
+    pub fn section(&self, section: Section) -> &Vec<EntrySpan> {
+        match section {
+            Section::ObjectMap => &self.object_domains,
+            Section::SubjectMap => &self.subject_domains,
+            Section::Privileges => &self.privileges,
+        }
+    }
+
+    fn section_mut(&mut self, section: Section) -> &mut Vec<EntrySpan> {
+        match section {
+            Section::ObjectMap => &mut self.object_domains,
+            Section::SubjectMap => &mut self.subject_domains,
+            Section::Privileges => &mut self.privileges,
+        }
+    }
+
+    pub fn entry(&self, section: Section, index: usize) -> Option<&EntrySpan> {
+        self.section(section).get(index)
+    }
+}
+
+fn unquote(value: &str) -> String {
+    value.trim_matches(|c| c == '"' || c == '\'').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_source_map() {
+        let text = "\
+object_map:
+  - name: od1   # globals
+    objects: [a]
+  - objects: [b]
+    name: \"od2\"
+subject_map: []
+privileges:
+- principal:
+    subject: s1
+  can_call: all
+
+- principal:
+    subject: s2
+";
+        let map = SourceMap::parse(text);
+        assert_eq!(map.object_domains, vec![
+            EntrySpan { name: Some("od1".to_string()), first_line: 2, last_line: 3 },
+            EntrySpan { name: Some("od2".to_string()), first_line: 4, last_line: 5 },
+        ]);
+        assert!(map.subject_domains.is_empty());
+        assert_eq!(map.privileges, vec![
+            EntrySpan { name: Some("s1".to_string()), first_line: 8, last_line: 10 },
+            EntrySpan { name: Some("s2".to_string()), first_line: 12, last_line: 13 },
+        ]);
+    }
+}
@@ -0,0 +1,294 @@
+// SPDX-License-Identifier: MIT
+//
+// MIT License
+//
+// © 2024 Nathan Dautenhahn & Serenitix LLC
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use crate::cpm_priv_map::{CPMPrivMap, LoadMode, ObjectDomain, PrivField, Privilege, SubjectDomain};
+use crate::permissions::EffectivePermissions;
+use crate::stream::{visit_policy_file, PolicyVisitor};
+
+/*
+ * Summary statistics of a policy, for tracking compartmentalization across
+ * releases.
+ *
+ * Fan-out counts are per principal (subject domain named by at least one
+ * privilege), over the union of its privileges; an `all` grant counts as
+ * every domain of the map. The attack surface score of a principal is
+ *
+ *     call targets + return targets + readable domains + 2 * writable domains
+ *
+ * writes weighing double since they can corrupt state shared with others.
+ *
+ * The collector only keeps counters and per-principal permission sets, so
+ * `policy_stats_file` computes the statistics while streaming the file.
+ */
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct PolicyStats {
+    pub object_domains: usize,
+    pub subject_domains: usize,
+    pub objects: usize,
+    pub subjects: usize,
+    pub privileges: usize,
+    // keyed by the AllocType name, e.g. "GLOBAL"
+    pub objects_by_alloc_type: BTreeMap<String, usize>,
+    // privilege fields granting `all`, keyed by field name
+    pub all_grants: BTreeMap<String, usize>,
+    // how many of those come from omitted fields
+    pub implicit_all_grants: usize,
+    pub principals: Vec<PrincipalStats>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PrincipalStats {
+    pub subject: String,
+    pub privileges: usize,
+    pub call_targets: usize,
+    pub return_targets: usize,
+    pub readable_domains: usize,
+    pub writable_domains: usize,
+    pub all_grants: usize,
+    pub attack_surface: usize,
+}
+
+impl PolicyStats {
+    pub fn total_all_grants(&self) -> usize {
+        self.all_grants.values().sum()
+    }
+
+    /// The principal with the largest attack surface, first one on ties.
+    pub fn max_attack_surface(&self) -> Option<&PrincipalStats> {
+        self.principals.iter().rev().max_by_key(|p| p.attack_surface)
+    }
+
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize statistics: {}", e))
+    }
+}
+
+/*
+ * Accumulates statistics entry by entry. Feed it through the PolicyVisitor
+ * methods (or `add_*`) and call `finish` once the whole policy was seen.
+ */
+#[derive(Debug, Default)]
+pub struct StatsCollector {
+    stats: PolicyStats,
+    // principal -> (privilege count, `all` grants, union of its grants), in first-seen order
+    principals: Vec<(String, usize, usize, EffectivePermissions)>,
+    principal_index: HashMap<String, usize>,
+}
+
+impl StatsCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_object_domain(&mut self, domain: &ObjectDomain) {
+        self.stats.object_domains += 1;
+        self.stats.objects += domain.objects().len();
+        for object in domain.objects() {
+            *self.stats.objects_by_alloc_type.entry(object.alloc_type().to_string()).or_default() += 1;
+        }
+    }
+
+    pub fn add_subject_domain(&mut self, domain: &SubjectDomain) {
+        self.stats.subject_domains += 1;
+        self.stats.subjects += domain.subjects().len();
+    }
+
+    pub fn add_privilege(&mut self, privilege: &Privilege) {
+        self.stats.privileges += 1;
+        let subject = privilege.principal().subject();
+        let index = match self.principal_index.get(subject) {
+            Some(&index) => index,
+            None => {
+                self.principals.push((subject.to_string(), 0, 0, EffectivePermissions::default()));
+                self.principal_index.insert(subject.to_string(), self.principals.len() - 1);
+                self.principals.len() - 1
+            }
+        };
+        let (_, privileges, all_grants, permissions) = &mut self.principals[index];
+        *privileges += 1;
+        permissions.add_privilege(privilege);
+
+        for field in PrivField::ALL.into_iter().filter(|f| privilege.grants_all(*f)) {
+            *self.stats.all_grants.entry(field.name().to_string()).or_default() += 1;
+            *all_grants += 1;
+            if privilege.implicit().is_implicit(field) {
+                self.stats.implicit_all_grants += 1;
+            }
+        }
+    }
+
+    pub fn finish(self) -> PolicyStats {
+        let mut stats = self.stats;
+        let (subjects, objects) = (stats.subject_domains, stats.object_domains);
+        stats.principals = self.principals.into_iter()
+            .map(|(subject, privileges, all_grants, permissions)| {
+                let call_targets = permissions.can_call.size(subjects);
+                let return_targets = permissions.can_return.size(subjects);
+                let readable_domains = permissions.can_read.size(objects);
+                let writable_domains = permissions.can_write.size(objects);
+                PrincipalStats {
+                    subject,
+                    privileges,
+                    call_targets,
+                    return_targets,
+                    readable_domains,
+                    writable_domains,
+                    all_grants,
+                    attack_surface: call_targets + return_targets + readable_domains + 2 * writable_domains,
+                }
+            })
+            .collect();
+        stats
+    }
+}
+
+impl PolicyVisitor for StatsCollector {
+    fn visit_object_domain(&mut self, domain: ObjectDomain, _line: usize) -> Result<(), String> {
+        self.add_object_domain(&domain);
+        Ok(())
+    }
+    fn visit_subject_domain(&mut self, domain: SubjectDomain, _line: usize) -> Result<(), String> {
+        self.add_subject_domain(&domain);
+        Ok(())
+    }
+    fn visit_privilege(&mut self, privilege: Privilege, _line: usize) -> Result<(), String> {
+        self.add_privilege(&privilege);
+        Ok(())
+    }
+}
+
+/// Statistics of an in-memory map.
+pub fn policy_stats(priv_map: &CPMPrivMap) -> PolicyStats {
+    let mut collector = StatsCollector::new();
+    priv_map.object_map().iter().for_each(|od| collector.add_object_domain(od));
+    priv_map.subject_map().iter().for_each(|sd| collector.add_subject_domain(sd));
+    priv_map.privileges().iter().for_each(|p| collector.add_privilege(p));
+    collector.finish()
+}
+
+/// Statistics of a policy file, streamed entry by entry.
+pub fn policy_stats_file(file_path: &str) -> Result<PolicyStats, String> {
+    let mut collector = StatsCollector::new();
+    visit_policy_file(file_path, LoadMode::AllowByDefault, &mut collector)?;
+    Ok(collector.finish())
+}
+
+impl fmt::Display for PolicyStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "object domains:  {} ({} objects)", self.object_domains, self.objects)?;
+        for (alloc_type, count) in &self.objects_by_alloc_type {
+            writeln!(f, "  {:<14} {}", alloc_type, count)?;
+        }
+        writeln!(f, "subject domains: {} ({} subjects)", self.subject_domains, self.subjects)?;
+        writeln!(f, "privileges:      {} ({} principals)", self.privileges, self.principals.len())?;
+        writeln!(f, "all grants:      {} ({} implicit)", self.total_all_grants(), self.implicit_all_grants)?;
+        for (field, count) in &self.all_grants {
+            writeln!(f, "  {:<14} {}", field, count)?;
+        }
+        if self.principals.is_empty() {
+            return Ok(());
+        }
+        writeln!(f)?;
+        writeln!(f, "{:<32} {:>5} {:>5} {:>5} {:>5} {:>5} {:>5} {:>7}",
+                 "principal", "privs", "call", "ret", "read", "write", "all", "surface")?;
+        for p in &self.principals {
+            writeln!(f, "{:<32} {:>5} {:>5} {:>5} {:>5} {:>5} {:>5} {:>7}",
+                     p.subject, p.privileges, p.call_targets, p.return_targets,
+                     p.readable_domains, p.writable_domains, p.all_grants, p.attack_surface)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const POLICY: &str = "
+object_map:
+  - name: od1
+    objects: [GLOBAL|a.c|1|g, HEAP|a.c|5|kmalloc]
+  - name: od2
+    objects: [GLOBAL|b.c|2|h]
+subject_map:
+  - name: s1
+    subjects: [f1, f2]
+  - name: s2
+    subjects: [f3]
+privileges:
+  - principal:
+      subject: s1
+    can_call: [s2]
+    can_return: []
+    can_read:
+      - objects: [od1]
+    can_write:
+      - objects: [od1]
+  - principal:
+      subject: s1
+    can_call: [s2]
+    can_return: []
+    can_read:
+      - objects: [od2]
+    can_write: []
+  - principal:
+      subject: s2
+    can_call: []
+    can_return: [s1]
+    can_read: all
+";
+
+    #[test]
+    fn test_policy_stats() {
+        let priv_map = CPMPrivMap::from_yaml_str(POLICY, LoadMode::AllowByDefault).unwrap();
+        let stats = policy_stats(&priv_map);
+        assert_eq!((stats.object_domains, stats.objects), (2, 3));
+        assert_eq!((stats.subject_domains, stats.subjects), (2, 3));
+        assert_eq!(stats.privileges, 3);
+        assert_eq!(stats.objects_by_alloc_type.get("GLOBAL"), Some(&2));
+        assert_eq!(stats.objects_by_alloc_type.get("HEAP"), Some(&1));
+        assert_eq!(stats.total_all_grants(), 2);
+        assert_eq!(stats.all_grants.get("can_write"), Some(&1));
+        assert_eq!(stats.implicit_all_grants, 1);
+
+        let s1 = &stats.principals[0];
+        assert_eq!((s1.privileges, s1.call_targets, s1.readable_domains, s1.writable_domains), (2, 1, 2, 1));
+        assert_eq!(s1.attack_surface, 1 + 2 + 2);
+        let s2 = &stats.principals[1];
+        assert_eq!((s2.return_targets, s2.readable_domains, s2.writable_domains, s2.all_grants), (1, 2, 2, 2));
+        assert_eq!(s2.attack_surface, 1 + 2 + 4);
+        assert_eq!(stats.max_attack_surface().unwrap().subject, "s2");
+    }
+
+    #[test]
+    fn test_streamed_stats_match() {
+        let path = std::env::temp_dir().join(format!("cpm_if_stats_{}.yaml", std::process::id()));
+        std::fs::File::create(&path).unwrap().write_all(POLICY.as_bytes()).unwrap();
+        let streamed = policy_stats_file(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let priv_map = CPMPrivMap::from_yaml_str(POLICY, LoadMode::AllowByDefault).unwrap();
+        assert_eq!(streamed, policy_stats(&priv_map));
+        assert!(streamed.to_json().unwrap().contains("\"attack_surface\": 7"));
+    }
+}
@@ -0,0 +1,384 @@
+// SPDX-License-Identifier: MIT
+//
+// MIT License
+//
+// © 2024 Nathan Dautenhahn & Serenitix LLC
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Lines};
+
+use crate::cpm_priv_map::{LoadMode, ObjectDomain, Privilege, SubjectDomain};
+use crate::include::{declares_includes, load_policy};
+use crate::source_map::Section;
+
+/*
+ * Incremental loading of very large policy files.
+ *
+ * serde_yaml reads a whole document before deserializing it, so a policy of
+ * several hundred megabytes needs the text and the full tree in memory at
+ * once. This reader instead scans the file line by line for the top-level
+ * `object_map`, `subject_map` and `privileges` sections and hands each
+ * block-style list item to serde_yaml on its own, so memory is bounded by
+ * the largest single entry. Flow-style sections (`object_map: []`) are
+ * parsed as a whole from their line. Anchors and aliases that cross entries
+ * are not supported.
+ *
+ * `validate --stream`, `stats` and `lint` read policies this way. Lint
+ * rules that compare entries keep only domain names across them (see
+ * `lint::LintPass`); `lint --full` loads the whole policy for the rules
+ * that need all of it.
+ */
+
+/// A raw entry of one section, before being converted to the policy types.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawEntry {
+    pub section: Section,
+    // position of the entry within its section
+    pub index: usize,
+    // 1-based line the entry starts on
+    pub line: usize,
+    pub value: serde_yaml::Value,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PolicyEntry {
+    ObjectDomain(ObjectDomain),
+    SubjectDomain(SubjectDomain),
+    Privilege(Privilege),
+}
+
+/// Iterator over the raw entries of a policy read from `R`.
+pub struct RawEntries<R: BufRead> {
+    lines: Lines<R>,
+    lineno: usize,
+    section: Option<Section>,
+    item_indent: Option<usize>,
+    // the entry being accumulated: section, start line and its lines
+    pending: Option<(Section, usize, String)>,
+    // entries parsed but not yet returned, e.g. from a flow-style section
+    ready: std::collections::VecDeque<Result<RawEntry, String>>,
+    counts: [usize; 3],
+    seen: [bool; 3],
+    done: bool,
+}
+
+fn section_slot(section: Section) -> usize {
+    match section {
+        Section::ObjectMap => 0,
+        Section::SubjectMap => 1,
+        Section::Privileges => 2,
+    }
+}
+
+impl<R: BufRead> RawEntries<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            lines: reader.lines(),
+            lineno: 0,
+            section: None,
+            item_indent: None,
+            pending: None,
+            ready: Default::default(),
+            counts: [0; 3],
+            seen: [false; 3],
+            done: false,
+        }
+    }
+
+    /// Sections that appeared in the input so far.
+    pub fn seen_sections(&self) -> Vec<Section> {
+        [Section::ObjectMap, Section::SubjectMap, Section::Privileges]
+            .into_iter()
+            .filter(|&s| self.seen[section_slot(s)])
+            .collect()
+    }
+
+    // parse the accumulated entry, if any, into the ready queue
+    fn flush(&mut self) {
+        let Some((section, line, text)) = self.pending.take() else {
+            return;
+        };
+        let result = serde_yaml::from_str::<Vec<serde_yaml::Value>>(&text)
+            .map_err(|e| format!("Failed to parse entry at line {}: {}", line, e))
+            .map(|mut values| values.pop().unwrap_or(serde_yaml::Value::Null));
+        let slot = section_slot(section);
+        let index = self.counts[slot];
+        self.counts[slot] += 1;
+        self.ready.push_back(result.map(|value| RawEntry { section, index, line, value }));
+    }
+
+    // a section written in flow style on its key's line, e.g. `privileges: []`
+    fn flow_section(&mut self, section: Section, value_text: &str) {
+        let line = self.lineno;
+        match serde_yaml::from_str::<Option<Vec<serde_yaml::Value>>>(value_text) {
+            Ok(values) => {
+                for value in values.unwrap_or_default() {
+                    let slot = section_slot(section);
+                    let index = self.counts[slot];
+                    self.counts[slot] += 1;
+                    self.ready.push_back(Ok(RawEntry { section, index, line, value }));
+                }
+            }
+            Err(e) => self.ready.push_back(Err(format!("Failed to parse section at line {}: {}", line, e))),
+        }
+    }
+
+    fn process_line(&mut self, line: String) {
+        let trimmed = line.trim_start();
+        let indent = line.len() - trimmed.len();
+
+        if indent == 0 && !trimmed.is_empty() && !trimmed.starts_with('#') && !trimmed.starts_with('-') {
+            self.flush();
+            self.item_indent = None;
+            let (key, value) = trimmed.split_once(':').unwrap_or((trimmed, ""));
+            self.section = match key.trim() {
+                "object_map" => Some(Section::ObjectMap),
+                "subject_map" => Some(Section::SubjectMap),
+                "privileges" => Some(Section::Privileges),
+                _ => None,
+            };
+            if let Some(section) = self.section {
+                self.seen[section_slot(section)] = true;
+                let value = value.split(" #").next().unwrap_or("").trim();
+                if !value.is_empty() {
+                    self.flow_section(section, value);
+                }
+            }
+            return;
+        }
+
+        let Some(section) = self.section else {
+            return;
+        };
+        let is_item = trimmed.starts_with("- ") || trimmed == "-";
+        if is_item && self.item_indent.is_none_or(|ii| ii == indent) {
+            self.flush();
+            self.item_indent = Some(indent);
+            self.pending = Some((section, self.lineno, String::new()));
+        }
+        if let Some((_, _, text)) = self.pending.as_mut() {
+            // re-indent so the entry is a one element list at column 0
+            let strip = self.item_indent.unwrap_or(0).min(indent);
+            text.push_str(&line[strip..]);
+            text.push('\n');
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for RawEntries<R> {
+    type Item = Result<RawEntry, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.ready.pop_front() {
+                return Some(entry);
+            }
+            if self.done {
+                return None;
+            }
+            match self.lines.next() {
+                Some(Ok(line)) => {
+                    self.lineno += 1;
+                    self.process_line(line);
+                }
+                Some(Err(e)) => {
+                    self.done = true;
+                    return Some(Err(format!("Failed to read YAML file: {}", e)));
+                }
+                None => {
+                    self.done = true;
+                    self.flush();
+                }
+            }
+        }
+    }
+}
+
+/// Iterator over the typed entries of a policy, in file order.
+pub struct PolicyStream<R: BufRead> {
+    raw: RawEntries<R>,
+    mode: LoadMode,
+}
+
+impl<R: BufRead> PolicyStream<R> {
+    pub fn new(reader: R, mode: LoadMode) -> Self {
+        Self { raw: RawEntries::new(reader), mode }
+    }
+}
+
+impl PolicyStream<BufReader<File>> {
+    pub fn open(file_path: &str, mode: LoadMode) -> Result<Self, String> {
+        let file = File::open(file_path)
+            .map_err(|e| format!("Failed to read YAML file: {}", e))?;
+        Ok(Self::new(BufReader::new(file), mode))
+    }
+}
+
+impl<R: BufRead> Iterator for PolicyStream<R> {
+    // the entry with the line it starts on
+    type Item = Result<(usize, PolicyEntry), String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let raw = match self.raw.next()? {
+            Ok(raw) => raw,
+            Err(e) => return Some(Err(e)),
+        };
+        let line = raw.line;
+        let err = |e: serde_yaml::Error| format!("Invalid entry at line {}: {}", line, e);
+        let entry = match raw.section {
+            Section::ObjectMap => serde_yaml::from_value(raw.value).map(PolicyEntry::ObjectDomain).map_err(err),
+            Section::SubjectMap => serde_yaml::from_value(raw.value).map(PolicyEntry::SubjectDomain).map_err(err),
+            Section::Privileges => serde_yaml::from_value::<Privilege>(raw.value)
+                .map(|mut privilege| {
+                    if self.mode == LoadMode::DenyByDefault {
+                        privilege.deny_implicit_grants();
+                    }
+                    PolicyEntry::Privilege(privilege)
+                })
+                .map_err(err),
+        };
+        Some(entry.map(|entry| (line, entry)))
+    }
+}
+
+/*
+ * Callback interface over a streamed policy. Each method receives an entry
+ * by value and its starting line; returning an error stops the walk.
+ */
+pub trait PolicyVisitor {
+    fn visit_object_domain(&mut self, _domain: ObjectDomain, _line: usize) -> Result<(), String> {
+        Ok(())
+    }
+    fn visit_subject_domain(&mut self, _domain: SubjectDomain, _line: usize) -> Result<(), String> {
+        Ok(())
+    }
+    fn visit_privilege(&mut self, _privilege: Privilege, _line: usize) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Streams the policy read from `reader` through the visitor.
+pub fn visit_policy<R: BufRead, V: PolicyVisitor>(reader: R, mode: LoadMode, visitor: &mut V) -> Result<(), String> {
+    for entry in PolicyStream::new(reader, mode) {
+        match entry? {
+            (line, PolicyEntry::ObjectDomain(domain)) => visitor.visit_object_domain(domain, line)?,
+            (line, PolicyEntry::SubjectDomain(domain)) => visitor.visit_subject_domain(domain, line)?,
+            (line, PolicyEntry::Privilege(privilege)) => visitor.visit_privilege(privilege, line)?,
+        }
+    }
+    Ok(())
+}
+
+/// Streams the policy file through the visitor.
+///
+/// A policy with includes is assembled first and its entries are visited
+/// in order with line 0, as they come from several files.
+pub fn visit_policy_file<V: PolicyVisitor>(file_path: &str, mode: LoadMode, visitor: &mut V) -> Result<(), String> {
+    if declares_includes(file_path)? {
+        let priv_map = load_policy(file_path, mode)?;
+        priv_map.object_map.into_iter().try_for_each(|od| visitor.visit_object_domain(od, 0))?;
+        priv_map.subject_map.into_iter().try_for_each(|sd| visitor.visit_subject_domain(sd, 0))?;
+        return priv_map.privileges.into_iter().try_for_each(|p| visitor.visit_privilege(p, 0));
+    }
+    let file = File::open(file_path)
+        .map_err(|e| format!("Failed to read YAML file: {}", e))?;
+    visit_policy(BufReader::new(file), mode, visitor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpm_priv_map::CPMPrivMap;
+
+    const POLICY: &str = "\
+# generated policy
+object_map:
+  - name: od1
+    objects:
+      - GLOBAL|a.c|1|g
+      - HEAP|a.c|2|
+  # comment between entries
+  - name: od2
+    objects: [b]
+subject_map: [{name: s1, subjects: [f1]}]
+privileges:
+- principal:
+    subject: s1
+  can_call: [s1]
+
+  can_read:
+    - objects: [od1]
+- principal:
+    subject: s2
+  can_write: all
+";
+
+    #[test]
+    fn test_stream_matches_full_parse() {
+        let full: CPMPrivMap = serde_yaml::from_str(POLICY).unwrap();
+        let entries: Vec<(usize, PolicyEntry)> = PolicyStream::new(POLICY.as_bytes(), LoadMode::AllowByDefault)
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        let lines: Vec<usize> = entries.iter().map(|(line, _)| *line).collect();
+        assert_eq!(lines, vec![3, 8, 10, 12, 18]);
+
+        let mut streamed = CPMPrivMap::new();
+        for (_, entry) in entries {
+            match entry {
+                PolicyEntry::ObjectDomain(od) => streamed.add_object_domain(od),
+                PolicyEntry::SubjectDomain(sd) => streamed.add_subject_domain(sd),
+                PolicyEntry::Privilege(p) => streamed.add_privilege(p),
+            }
+        }
+        assert_eq!(streamed, full);
+        assert!(streamed.privileges()[0].implicit().can_write);
+    }
+
+    #[test]
+    fn test_visit_policy() {
+        #[derive(Default)]
+        struct Counter {
+            objects: usize,
+            subjects: usize,
+            privileges: Vec<usize>,
+        }
+        impl PolicyVisitor for Counter {
+            fn visit_object_domain(&mut self, domain: ObjectDomain, _line: usize) -> Result<(), String> {
+                self.objects += domain.objects().len();
+                Ok(())
+            }
+            fn visit_subject_domain(&mut self, _domain: SubjectDomain, _line: usize) -> Result<(), String> {
+                self.subjects += 1;
+                Ok(())
+            }
+            fn visit_privilege(&mut self, _privilege: Privilege, line: usize) -> Result<(), String> {
+                self.privileges.push(line);
+                Ok(())
+            }
+        }
+
+        let mut counter = Counter::default();
+        visit_policy(POLICY.as_bytes(), LoadMode::DenyByDefault, &mut counter).unwrap();
+        assert_eq!(counter.objects, 3);
+        assert_eq!(counter.subjects, 1);
+        assert_eq!(counter.privileges, vec![12, 18]);
+    }
+
+    #[test]
+    fn test_stream_reports_bad_entry() {
+        let policy = "object_map:\n  - name: od1\n    objects: {a: b}\n";
+        let result: Result<Vec<_>, _> = PolicyStream::new(policy.as_bytes(), LoadMode::AllowByDefault).collect();
+        assert!(result.unwrap_err().contains("line 2"));
+    }
+}
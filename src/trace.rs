@@ -0,0 +1,370 @@
+// SPDX-License-Identifier: MIT
+//
+// MIT License
+//
+// © 2024 Nathan Dautenhahn & Serenitix LLC
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use crate::cpm_priv_map::{
+    CPMPrivMapContainer, CallRetPrivField, ContextField, Object, Principal, Privilege, RWPrivField,
+};
+
+/*
+ * Access traces collected from instrumented builds, one JSON event per line:
+ *
+ *   {"function": "tcp_sendmsg", "access": "read", "object": {"path": "net/ipv4/tcp.c", "line": "120"}}
+ *   {"function": "tcp_sendmsg", "access": "write", "object": {"name": "sysctl_tcp_mem"}}
+ *   {"function": "tcp_sendmsg", "access": "write", "object": {"alias": "0xffff8880"}}
+ *   {"function": "tcp_sendmsg", "access": "call", "target": "ip_queue_xmit"}
+ *
+ * Objects are resolved to object domains through the container's alias maps
+ * when an alias is given, and otherwise by matching name/path/line against
+ * the objects of each domain. Functions and call targets are resolved to the
+ * subject domain listing them as a subject.
+ */
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum TraceAccess {
+    Read,
+    Write,
+    Call,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Default)]
+pub struct TraceObject {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default)]
+    pub line: Option<String>,
+    #[serde(default)]
+    pub alias: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct TraceEvent {
+    pub function: String,
+    pub access: TraceAccess,
+    #[serde(default)]
+    pub object: Option<TraceObject>,
+    #[serde(default)]
+    pub target: Option<String>,
+}
+
+/// An event that could not be mapped onto the policy, with the reason why.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct UnresolvedEvent {
+    pub line: usize,
+    pub reason: String,
+}
+
+/// Privileges synthesized from a trace, one per subject domain observed.
+#[derive(Debug)]
+pub struct TraceSynthesis {
+    pub privileges: Vec<Privilege>,
+    pub unresolved: Vec<UnresolvedEvent>,
+}
+
+/// Parses a JSONL trace, skipping blank lines.
+///
+/// # Returns
+/// * `Ok(events)` paired with the 1-based line number of each event.
+/// * `Err(String)` naming the first line that fails to parse.
+pub fn parse_trace<R: BufRead>(reader: R) -> Result<Vec<(usize, TraceEvent)>, String> {
+    let mut events = vec![];
+    for (i, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| format!("Failed to read trace: {}", e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event: TraceEvent = serde_json::from_str(&line)
+            .map_err(|e| format!("Failed to parse trace line {}: {}", i + 1, e))?;
+        events.push((i + 1, event));
+    }
+    Ok(events)
+}
+
+/// Reads and parses a JSONL trace file.
+pub fn read_trace_file(trace_file: &str) -> Result<Vec<(usize, TraceEvent)>, String> {
+    let file = File::open(trace_file)
+        .map_err(|e| format!("Failed to read trace file: {}", e))?;
+    parse_trace(BufReader::new(file))
+}
+
+#[derive(Default)]
+struct ObservedAccess {
+    calls: BTreeSet<String>,
+    returns: BTreeSet<String>,
+    reads: BTreeSet<String>,
+    writes: BTreeSet<String>,
+}
+
+/// Synthesizes least-privilege `Privilege` entries from trace events.
+///
+/// Each subject domain seen in the trace gets exactly the call, return, read
+/// and write grants it was observed using: `can_return` holds the domains of
+/// its callers. Fields with no observed use become empty lists, never `all`.
+pub fn synthesize_privileges(container: &CPMPrivMapContainer, events: &[(usize, TraceEvent)]) -> TraceSynthesis {
+    let priv_map = &container.cpm_priv_map;
+    let mut observed: BTreeMap<String, ObservedAccess> = BTreeMap::new();
+    let mut unresolved = vec![];
+
+    let subject_domain = |function: &str| {
+        priv_map.get_subject_domain_for_subject(function).map(|sd| sd.name().to_string())
+    };
+
+    for (line, event) in events {
+        let line = *line;
+        let Some(subject) = subject_domain(&event.function) else {
+            unresolved.push(UnresolvedEvent {
+                line,
+                reason: format!("no subject domain for function '{}'", event.function),
+            });
+            continue;
+        };
+
+        match event.access {
+            TraceAccess::Call => {
+                let Some(target) = event.target.as_deref() else {
+                    unresolved.push(UnresolvedEvent { line, reason: "call event without target".to_string() });
+                    continue;
+                };
+                let Some(callee) = subject_domain(target) else {
+                    unresolved.push(UnresolvedEvent {
+                        line,
+                        reason: format!("no subject domain for call target '{}'", target),
+                    });
+                    continue;
+                };
+                observed.entry(subject.to_string()).or_default().calls.insert(callee.to_string());
+                observed.entry(callee).or_default().returns.insert(subject);
+            }
+            TraceAccess::Read | TraceAccess::Write => {
+                let Some(object) = event.object.as_ref() else {
+                    unresolved.push(UnresolvedEvent { line, reason: "access event without object".to_string() });
+                    continue;
+                };
+                let domains = resolve_object(container, object);
+                if domains.is_empty() {
+                    unresolved.push(UnresolvedEvent {
+                        line,
+                        reason: format!("no object domain for object {:?}", object),
+                    });
+                    continue;
+                }
+                let access = observed.entry(subject).or_default();
+                let set = if event.access == TraceAccess::Read { &mut access.reads } else { &mut access.writes };
+                set.extend(domains);
+            }
+        }
+    }
+
+    // emit privileges in subject map order so output is stable
+    let privileges = priv_map.subject_map().iter()
+        .filter_map(|sd| observed.remove(sd.name()).map(|access| (sd.name().to_string(), access)))
+        .map(|(subject, access)| Privilege::new(
+            Principal {
+                subject,
+                execution_context: ContextField::All,
+            },
+            CallRetPrivField::List(access.calls.into_iter().collect()),
+            CallRetPrivField::List(access.returns.into_iter().collect()),
+            rw_field(access.reads),
+            rw_field(access.writes),
+        ))
+        .collect();
+
+    TraceSynthesis { privileges, unresolved }
+}
+
+/// Reads a trace file and adds the synthesized privileges to the container.
+///
+/// A privilege whose principal (subject and execution context) already has
+/// one in the policy is merged into it; others are appended.
+///
+/// # Returns
+/// * `Ok(unresolved)` listing the events that could not be mapped.
+/// * `Err(String)` if the trace cannot be read or parsed.
+pub fn ingest_trace_file(container: &mut CPMPrivMapContainer, trace_file: &str) -> Result<Vec<UnresolvedEvent>, String> {
+    let events = read_trace_file(trace_file)?;
+    let synthesis = synthesize_privileges(container, &events);
+    for privilege in synthesis.privileges {
+        let existing = container.cpm_priv_map.privileges.iter_mut().find(|p| p.principal() == privilege.principal());
+        match existing {
+            Some(existing) => merge_privilege(existing, privilege),
+            None => container.add_privilege(privilege),
+        }
+    }
+    Ok(synthesis.unresolved)
+}
+
+// add the observed grants that `existing` does not already give
+fn merge_privilege(existing: &mut Privilege, observed: Privilege) {
+    for (field, names) in [(&mut existing.can_call, observed.can_call), (&mut existing.can_return, observed.can_return)] {
+        if let (CallRetPrivField::List(list), CallRetPrivField::List(names)) = (field, names) {
+            for name in names {
+                if !list.contains(&name) {
+                    list.push(name);
+                }
+            }
+        }
+    }
+    for (field, observed) in [(&mut existing.can_read, observed.can_read), (&mut existing.can_write, observed.can_write)] {
+        let RWPrivField::List(objects) = observed else {
+            continue;
+        };
+        // traced accesses are unrestricted, so only an unrestricted grant covers them
+        let covered = |domain: &String| match &*field {
+            RWPrivField::List(list) => list.iter()
+                .any(|o| o.object_context() == &ContextField::All && o.objects().contains(domain)),
+            RWPrivField::All => true,
+        };
+        let missing: Vec<String> = objects.iter()
+            .flat_map(|o| o.objects().iter())
+            .filter(|domain| !covered(domain))
+            .cloned()
+            .collect();
+        if !missing.is_empty() {
+            field.add_object(Object::new(missing));
+        }
+    }
+}
+
+// map a traced object to the names of the object domains holding it
+fn resolve_object(container: &CPMPrivMapContainer, object: &TraceObject) -> Vec<String> {
+    if let Some(alias) = &object.alias {
+        return container.get_domains_for_aliases(vec![alias.to_string()]);
+    }
+    if object.name.is_none() && object.path.is_none() && object.line.is_none() {
+        return vec![];
+    }
+    container.cpm_priv_map
+        .find_object_domains(object.name.as_deref(), object.path.as_deref(), object.line.as_deref(), None)
+        .iter()
+        .map(|od| od.name().to_string())
+        .collect()
+}
+
+fn rw_field(domains: BTreeSet<String>) -> RWPrivField {
+    if domains.is_empty() {
+        RWPrivField::List(vec![])
+    } else {
+        RWPrivField::List(vec![Object::new(domains.into_iter().collect())])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpm_priv_map::SubjectDomain;
+
+    fn container() -> CPMPrivMapContainer {
+        let mut container = CPMPrivMapContainer::new();
+        container.add_global("counter".to_string(), "a.c".to_string(), "5".to_string(), "alias_counter".to_string());
+        container.add_alloc("kmalloc".to_string(), "b.c".to_string(), "42".to_string(), &vec!["0xbeef".to_string()]);
+        container.add_subject_domain(SubjectDomain::new("net".to_string(), vec!["send".to_string()]));
+        container.add_subject_domain(SubjectDomain::new("ip".to_string(), vec!["xmit".to_string()]));
+        container
+    }
+
+    #[test]
+    fn test_parse_trace() {
+        let trace = "{\"function\": \"send\", \"access\": \"call\", \"target\": \"xmit\"}\n\n\
+                     {\"function\": \"send\", \"access\": \"read\", \"object\": {\"name\": \"counter\"}}\n";
+        let events = parse_trace(trace.as_bytes()).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].0, 1);
+        assert_eq!(events[1].0, 3);
+        assert_eq!(events[1].1.access, TraceAccess::Read);
+
+        let err = parse_trace("{\"function\": \"send\"}".as_bytes()).unwrap_err();
+        assert!(err.contains("line 1"));
+    }
+
+    #[test]
+    fn test_synthesize_privileges() {
+        let container = container();
+        let globals = container.cpm_priv_map.object_map()[0].name().to_string();
+        let heap = container.cpm_priv_map.object_map()[1].name().to_string();
+        let trace = "\
+{\"function\": \"send\", \"access\": \"call\", \"target\": \"xmit\"}
+{\"function\": \"send\", \"access\": \"read\", \"object\": {\"name\": \"counter\"}}
+{\"function\": \"xmit\", \"access\": \"write\", \"object\": {\"path\": \"b.c\", \"line\": \"42\"}}
+{\"function\": \"xmit\", \"access\": \"read\", \"object\": {\"alias\": \"0xbeef\"}}
+{\"function\": \"unknown\", \"access\": \"read\", \"object\": {\"name\": \"counter\"}}
+{\"function\": \"send\", \"access\": \"write\", \"object\": {\"name\": \"missing\"}}
+";
+        let events = parse_trace(trace.as_bytes()).unwrap();
+        let synthesis = synthesize_privileges(&container, &events);
+
+        assert_eq!(synthesis.privileges.len(), 2);
+        let net = &synthesis.privileges[0];
+        assert_eq!(net.principal().subject(), "net");
+        assert_eq!(net.can_call(), &CallRetPrivField::List(vec!["ip".to_string()]));
+        assert_eq!(net.can_return(), &CallRetPrivField::List(vec![]));
+        assert_eq!(net.can_read(), &RWPrivField::List(vec![Object::new(vec![globals])]));
+        assert_eq!(net.can_write(), &RWPrivField::List(vec![]));
+
+        let ip = &synthesis.privileges[1];
+        assert_eq!(ip.principal().subject(), "ip");
+        assert_eq!(ip.can_call(), &CallRetPrivField::List(vec![]));
+        assert_eq!(ip.can_return(), &CallRetPrivField::List(vec!["net".to_string()]));
+        assert_eq!(ip.can_read(), &RWPrivField::List(vec![Object::new(vec![heap.clone()])]));
+        assert_eq!(ip.can_write(), &RWPrivField::List(vec![Object::new(vec![heap])]));
+
+        let lines: Vec<usize> = synthesis.unresolved.iter().map(|u| u.line).collect();
+        assert_eq!(lines, vec![5, 6]);
+    }
+
+    #[test]
+    fn test_ingest_merges_into_existing_privileges() {
+        let mut container = container();
+        let globals = container.cpm_priv_map.object_map()[0].name().to_string();
+        let heap = container.cpm_priv_map.object_map()[1].name().to_string();
+        container.add_privilege(Privilege::new(
+            Principal { subject: "net".to_string(), execution_context: ContextField::All },
+            CallRetPrivField::List(vec!["ip".to_string()]),
+            CallRetPrivField::List(vec![]),
+            RWPrivField::List(vec![Object::new(vec![globals.clone()])]),
+            RWPrivField::All,
+        ));
+        let trace_file = std::env::temp_dir().join(format!("cpm_if_trace_{}.jsonl", std::process::id()));
+        std::fs::write(&trace_file, "\
+{\"function\": \"send\", \"access\": \"call\", \"target\": \"xmit\"}
+{\"function\": \"send\", \"access\": \"read\", \"object\": {\"name\": \"counter\"}}
+{\"function\": \"send\", \"access\": \"read\", \"object\": {\"alias\": \"0xbeef\"}}
+{\"function\": \"send\", \"access\": \"write\", \"object\": {\"alias\": \"0xbeef\"}}
+").unwrap();
+        let unresolved = ingest_trace_file(&mut container, trace_file.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&trace_file).unwrap();
+        assert!(unresolved.is_empty());
+
+        // net is merged into its existing privilege, ip is new
+        let privileges = container.cpm_priv_map.privileges();
+        assert_eq!(privileges.len(), 2);
+        let net = &privileges[0];
+        assert_eq!(net.can_call(), &CallRetPrivField::List(vec!["ip".to_string()]));
+        assert_eq!(net.can_read(), &RWPrivField::List(vec![
+            Object::new(vec![globals]),
+            Object::new(vec![heap]),
+        ]));
+        assert_eq!(net.can_write(), &RWPrivField::All);
+        assert_eq!(privileges[1].principal().subject(), "ip");
+        assert_eq!(privileges[1].can_return(), &CallRetPrivField::List(vec!["net".to_string()]));
+    }
+}
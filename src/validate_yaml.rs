@@ -1,8 +1,12 @@
 use serde_json;
 use serde_yaml;
-use jsonschema::{JSONSchema, ValidationError};
+use jsonschema::JSONSchema;
 use std::fs;
-use std::error::Error;
+use std::io::BufReader;
+
+use crate::include::IncludedPolicy;
+use crate::source_map::Section;
+use crate::stream::RawEntries;
 
 /// Validates a YAML file against a JSON schema.
 ///
@@ -42,4 +46,163 @@ pub fn validate_yaml(schema_file: &str, yaml_file: &str) -> Result<(), String> {
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Validates a YAML file against a JSON schema one entry at a time.
+///
+/// Each entry of `object_map`, `subject_map` and `privileges` is read with
+/// `stream::RawEntries` and checked against the schema's `items` definition
+/// for its section, so the whole file is never held in memory. The schema's
+/// top-level `required` sections are checked once the file has been read.
+///
+/// # Arguments
+/// * `schema_file` - Path to the JSON schema file.
+/// * `yaml_file` - Path to the YAML file to validate.
+///
+/// # Returns
+/// * `Ok(())` if the YAML file is valid.
+/// * `Err(String)` if validation fails with a detailed error message.
+pub fn validate_yaml_streaming(schema_file: &str, yaml_file: &str) -> Result<(), String> {
+    // Read and parse the JSON schema
+    let schema_content = fs::read_to_string(schema_file)
+        .map_err(|e| format!("Failed to read schema file: {}", e))?;
+    let schema_json: serde_json::Value = serde_json::from_str(&schema_content)
+        .map_err(|e| format!("Failed to parse schema file as JSON: {}", e))?;
+
+    // Compile the per-entry schema of each section
+    let object_items = compile_items(&schema_json, Section::ObjectMap)?;
+    let subject_items = compile_items(&schema_json, Section::SubjectMap)?;
+    let privilege_items = compile_items(&schema_json, Section::Privileges)?;
+
+    let file = fs::File::open(yaml_file)
+        .map_err(|e| format!("Failed to read YAML file: {}", e))?;
+    let mut entries = RawEntries::new(BufReader::new(file));
+
+    let mut error_messages: Vec<String> = vec![];
+    for entry in entries.by_ref() {
+        let entry = entry?;
+        let items = match entry.section {
+            Section::ObjectMap => &object_items,
+            Section::SubjectMap => &subject_items,
+            Section::Privileges => &privilege_items,
+        };
+        let Some(items) = items else {
+            continue;
+        };
+        let value = serde_json::to_value(&entry.value)
+            .map_err(|e| format!("Failed to convert entry at line {}: {}", entry.line, e))?;
+        if let Err(errors) = items.validate(&value) {
+            error_messages.extend(errors.map(|e| format!("line {}: {}", entry.line, e)));
+        };
+    }
+
+    // Check the required top-level sections were present
+    let seen: Vec<&str> = entries.seen_sections().iter().map(|s| s.key()).collect();
+    if let Some(required) = schema_json.get("required").and_then(|r| r.as_array()) {
+        for name in required.iter().filter_map(|r| r.as_str()) {
+            if !seen.contains(&name) {
+                error_messages.push(format!("\"{}\" is a required property", name));
+            }
+        }
+    }
+
+    if !error_messages.is_empty() {
+        return Err(format!(
+            "Validation failed with the following errors:\n{}",
+            error_messages.join("\n")
+        ));
+    }
+
+    Ok(())
+}
+
+// the schema of a single entry of the section, if the schema has one
+fn compile_items(schema_json: &serde_json::Value, section: Section) -> Result<Option<JSONSchema>, String> {
+    match schema_json.pointer(&format!("/properties/{}/items", section.key())) {
+        Some(items) => JSONSchema::compile(items)
+            .map(Some)
+            .map_err(|e| format!("Failed to compile schema for {}: {}", section.key(), e)),
+        None => Ok(None),
+    }
+}
+
+// the whole property schema of a section, so its type is checked along with its entries
+fn compile_section(schema_json: &serde_json::Value, section: Section) -> Result<Option<JSONSchema>, String> {
+    match schema_json.pointer(&format!("/properties/{}", section.key())) {
+        Some(property) => JSONSchema::compile(property)
+            .map(Some)
+            .map_err(|e| format!("Failed to compile schema for {}: {}", section.key(), e)),
+        None => Ok(None),
+    }
+}
+
+/// Validates a policy assembled from several files against a JSON schema.
+///
+/// Each section a file has is checked against the schema's definition of
+/// that section, and errors name the file and, for an entry, its index
+/// there. Each required top-level section must appear in at
+/// least one of the files.
+///
+/// # Arguments
+/// * `schema_file` - Path to the JSON schema file.
+/// * `policy` - The policy and the files it was loaded from.
+///
+/// # Returns
+/// * `Ok(())` if every file is valid.
+/// * `Err(String)` if validation fails with a detailed error message.
+pub fn validate_yaml_included(schema_file: &str, policy: &IncludedPolicy) -> Result<(), String> {
+    // Read and parse the JSON schema
+    let schema_content = fs::read_to_string(schema_file)
+        .map_err(|e| format!("Failed to read schema file: {}", e))?;
+    let schema_json: serde_json::Value = serde_json::from_str(&schema_content)
+        .map_err(|e| format!("Failed to parse schema file as JSON: {}", e))?;
+
+    let sections = [Section::ObjectMap, Section::SubjectMap, Section::Privileges];
+    let mut schemas = vec![];
+    for section in sections {
+        schemas.push(compile_section(&schema_json, section)?);
+    }
+
+    let mut error_messages: Vec<String> = vec![];
+    let mut seen: Vec<&str> = vec![];
+    for file in policy.files() {
+        let yaml_data: serde_json::Value = serde_yaml::from_str(&file.text)
+            .map_err(|e| format!("{}: Failed to parse YAML file: {}", file.path, e))?;
+        for (section, schema) in sections.iter().zip(&schemas) {
+            let Some(entries) = yaml_data.get(section.key()) else {
+                continue;
+            };
+            seen.push(section.key());
+            let Some(schema) = schema else {
+                continue;
+            };
+            if let Err(errors) = schema.validate(entries) {
+                error_messages.extend(errors.map(|e| {
+                    // the first step into the section is the entry's index
+                    match e.instance_path.clone().into_vec().first() {
+                        Some(index) => format!("{}: {}[{}]: {}", file.path, section.key(), index, e),
+                        None => format!("{}: {}: {}", file.path, section.key(), e),
+                    }
+                }));
+            }
+        }
+    }
+
+    // Check the required top-level sections were present in some file
+    if let Some(required) = schema_json.get("required").and_then(|r| r.as_array()) {
+        for name in required.iter().filter_map(|r| r.as_str()) {
+            if !seen.contains(&name) {
+                error_messages.push(format!("\"{}\" is a required property", name));
+            }
+        }
+    }
+
+    if !error_messages.is_empty() {
+        return Err(format!(
+            "Validation failed with the following errors:\n{}",
+            error_messages.join("\n")
+        ));
+    }
+
+    Ok(())
+}
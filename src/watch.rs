@@ -0,0 +1,146 @@
+// SPDX-License-Identifier: MIT
+//
+// MIT License
+//
+// © 2024 Nathan Dautenhahn & Serenitix LLC
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/*
+ * Support for `--watch`: polling input files for changes and reporting how
+ * a command's output changed between runs.
+ *
+ * Files are compared by modification time and length, which needs nothing
+ * beyond the standard library and works on every local filesystem. Saving
+ * a file twice within the filesystem's timestamp granularity without
+ * changing its length can go unnoticed until the next change. A missing
+ * file is a state of its own, so deleting or recreating an input counts as
+ * a change.
+ */
+
+// modification time and length, or None for a missing file
+type Stamp = Option<(SystemTime, u64)>;
+
+fn stamp(path: &Path) -> Stamp {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+pub struct FileWatcher {
+    files: Vec<(PathBuf, Stamp)>,
+}
+
+impl FileWatcher {
+    pub fn new<P: AsRef<Path>>(paths: &[P]) -> Self {
+        let mut watcher = FileWatcher { files: vec![] };
+        paths.iter().for_each(|path| watcher.add(path));
+        watcher
+    }
+
+    /// Starts watching another file; changes are reported from the next poll on.
+    pub fn add<P: AsRef<Path>>(&mut self, path: P) {
+        let path = path.as_ref().to_path_buf();
+        if !self.files.iter().any(|(p, _)| *p == path) {
+            let stamp = stamp(&path);
+            self.files.push((path, stamp));
+        }
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.files.iter().map(|(p, _)| p.as_path())
+    }
+
+    /// Files changed since the watcher was created or last polled.
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        let mut changed = vec![];
+        for (path, last) in &mut self.files {
+            let current = stamp(path);
+            if current != *last {
+                *last = current;
+                changed.push(path.clone());
+            }
+        }
+        changed
+    }
+}
+
+/// Lines of `previous` missing from `current` as "- line" and new lines of `current` as "+ line".
+///
+/// Repeated lines are counted, so a diagnostic reported once more than before shows up as added.
+pub fn diff_lines(previous: &[String], current: &[String]) -> Vec<String> {
+    let count = |lines: &[String]| {
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for line in lines {
+            *counts.entry(line.to_string()).or_default() += 1;
+        }
+        counts
+    };
+    let mut in_current = count(current);
+    let mut in_previous = count(previous);
+
+    let mut diff = vec![];
+    for line in previous {
+        match in_current.get_mut(line) {
+            Some(n) if *n > 0 => *n -= 1,
+            _ => diff.push(format!("- {}", line)),
+        }
+    }
+    for line in current {
+        match in_previous.get_mut(line) {
+            Some(n) if *n > 0 => *n -= 1,
+            _ => diff.push(format!("+ {}", line)),
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_diff_lines() {
+        let previous = lines("a.yaml:3: error\na.yaml:9: warning\nLint failed with 1 error(s)");
+        let current = lines("a.yaml:9: warning\na.yaml:9: warning");
+        assert_eq!(diff_lines(&previous, &current), vec![
+            "- a.yaml:3: error",
+            "- Lint failed with 1 error(s)",
+            "+ a.yaml:9: warning",
+        ]);
+        assert!(diff_lines(&current, &current).is_empty());
+    }
+
+    #[test]
+    fn test_poll() {
+        let path = std::env::temp_dir().join(format!("cpm_if_watch_{}.yaml", std::process::id()));
+        fs::write(&path, "object_map: []\n").unwrap();
+        let mut watcher = FileWatcher::new(&[&path, &path]);
+        assert_eq!(watcher.paths().count(), 1);
+        assert!(watcher.poll().is_empty());
+
+        fs::write(&path, "object_map: []\nsubject_map: []\n").unwrap();
+        assert_eq!(watcher.poll(), vec![path.clone()]);
+        assert!(watcher.poll().is_empty());
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(watcher.poll(), vec![path.clone()]);
+        assert!(watcher.poll().is_empty());
+    }
+}
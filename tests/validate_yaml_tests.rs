@@ -0,0 +1,65 @@
+// SPDX-License-Identifier: MIT
+//
+// MIT License
+//
+// © 2024 Nathan Dautenhahn & Serenitix LLC
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+use cpm_if::validate_yaml::{validate_yaml, validate_yaml_streaming};
+use std::fs;
+
+const SCHEMA: &str = "tests/cpm_if_schema_v1.3.json";
+
+fn write_policy(name: &str, yaml: &str) -> String {
+    let path = std::env::temp_dir().join(format!("cpm_if_{}_{}.yaml", name, std::process::id()));
+    fs::write(&path, yaml).unwrap();
+    path.to_string_lossy().to_string()
+}
+
+#[test]
+fn test_streaming_validation_accepts_valid_policy() {
+    let path = write_policy("valid", "
+object_map:
+  - name: ObjectDomain1
+    objects: [object1]
+subject_map: [{name: SubjectDomain1, subjects: [subject1]}]
+privileges:
+  - principal:
+      subject: SubjectDomain1
+      execution_context:
+        uid: user
+    can_call: [SubjectDomain1]
+    can_read:
+      - objects: [ObjectDomain1]
+");
+    assert!(validate_yaml(SCHEMA, &path).is_ok());
+    assert!(validate_yaml_streaming(SCHEMA, &path).is_ok());
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_streaming_validation_rejects_invalid_policy() {
+    let path = write_policy("invalid", "
+object_map:
+  - name: ObjectDomain1
+privileges:
+  - principal:
+      subject: SubjectDomain1
+    can_call: SubjectDomain2
+");
+    assert!(validate_yaml(SCHEMA, &path).is_err());
+    let err = validate_yaml_streaming(SCHEMA, &path).unwrap_err();
+    assert!(err.contains("line 3"), "{}", err);
+    assert!(err.contains("line 5"), "{}", err);
+    assert!(err.contains("\"subject_map\" is a required property"), "{}", err);
+    fs::remove_file(path).unwrap();
+}